@@ -0,0 +1,1520 @@
+extern crate declarative_dataflow;
+extern crate differential_dataflow;
+extern crate getopts;
+extern crate mio;
+extern crate serde_cbor;
+extern crate serde_json;
+#[macro_use]
+extern crate serde_derive;
+extern crate slab;
+extern crate timely;
+extern crate ws;
+
+#[macro_use]
+extern crate log;
+extern crate env_logger;
+
+#[macro_use]
+extern crate abomonation_derive;
+extern crate abomonation;
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::File;
+use std::io::BufRead;
+use std::net::{IpAddr, SocketAddr};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use std::{thread, usize};
+
+use getopts::Options;
+
+use timely::communication::Allocate;
+use timely::dataflow::operators::generic::OutputHandle;
+use timely::dataflow::operators::{Broadcast, Operator, Probe};
+use timely::dataflow::channels::pact::{Exchange, Pipeline};
+use timely::dataflow::ProbeHandle;
+use timely::synchronization::Sequencer;
+use timely::worker::Worker;
+
+use mio::net::TcpListener;
+use mio::*;
+
+use slab::Slab;
+
+use ws::connection::{ConnEvent, Connection};
+
+use declarative_dataflow::plan::{Collation, CompiledPredicate};
+use declarative_dataflow::server::auth::{AllowAll, Guard, Policy};
+use declarative_dataflow::server::{client_of, Config, CreateInput, OverflowPolicy, Request, Server};
+use declarative_dataflow::Value;
+
+mod peer;
+mod protocol;
+
+use peer::{HttpConnection, Peer, TcpConnection};
+use protocol::{Encoding, Message, Notice, Output, Response};
+
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Abomonation, Serialize, Deserialize, Debug)]
+struct Command {
+    // the worker (typically a controller) that issued this command
+    // and is the one that should receive outputs
+    owner: usize,
+    // the client token that issued the command (only relevant to the
+    // owning worker, no one else has the connection)
+    client: Option<usize>,
+    // the encoding of the command
+    encoding: Encoding,
+    cmd: Vec<u8>,
+}
+
+/// A command releasing the resources held on behalf of a client that
+/// disconnected.
+fn disconnect_command(owner: usize, client: usize) -> Command {
+    Command {
+        owner,
+        client: Some(client),
+        encoding: Encoding::Json,
+        cmd: b"[\"Disconnect\"]".to_vec(),
+    }
+}
+
+/// Sends a notice to the client that issued a command, if this
+/// worker owns the client's connection.
+fn reply(
+    send_results: &mio::channel::Sender<(String, Option<usize>, Response)>,
+    worker_index: usize,
+    command: &Command,
+    notice: Notice,
+) {
+    if command.owner == worker_index {
+        if let Some(client) = command.client {
+            send_results
+                .send((String::new(), Some(client), Response::Notice(notice)))
+                .unwrap();
+        }
+    }
+}
+
+/// Checks a message received from a client before it is sequenced.
+/// Handshakes establish the principal of a connection, requests have
+/// to be authorized for the principal of their connection, or for the
+/// credential presented along with them. Returns a notice answering
+/// the message in place of sequencing it, if any.
+fn admit(
+    guard: &dyn Guard,
+    principals: &mut HashMap<Token, String>,
+    token: Token,
+    credential: Option<&str>,
+    command: &Command,
+) -> Option<Notice> {
+    match command.encoding.decode(&command.cmd) {
+        Err(msg) => Some(Notice::Error(msg, None)),
+        Ok(Message::Authenticate(presented)) => match guard.authenticate(&presented) {
+            None => {
+                principals.remove(&token);
+                Some(Notice::Unauthorized("authentication failed".to_string(), None))
+            }
+            Some(principal) => {
+                principals.insert(token, principal.clone());
+                Some(Notice::Authenticated(principal))
+            }
+        },
+        Ok(Message::Requests(id, requests)) => {
+            let principal = match credential {
+                Some(credential) => guard.authenticate(credential),
+                None => principals.get(&token).cloned().or_else(|| guard.anonymous()),
+            };
+
+            match principal {
+                None => Some(Notice::Unauthorized("not authenticated".to_string(), id)),
+                Some(principal) => requests
+                    .iter()
+                    .filter_map(|req| guard.authorize(&principal, req).err())
+                    .next()
+                    .map(|msg| Notice::Unauthorized(msg, id)),
+            }
+        }
+    }
+}
+
+/// A transaction to be acknowledged once the relations it waits for
+/// reflect it.
+struct PendingAck {
+    client: usize,
+    id: Option<u64>,
+    tx: u64,
+    wait_for: Vec<String>,
+}
+
+/// A client subscribed to a relation, held by the owning worker.
+struct Subscriber {
+    client: usize,
+    id: Option<u64>,
+    constraint: Option<CompiledPredicate>,
+    /// Updates before this time make up the snapshot, later ones are
+    /// delivered as deltas.
+    snapshot_time: u64,
+    snapshotted: bool,
+    /// Results are complete up to this time.
+    reported: u64,
+}
+
+impl Subscriber {
+    fn accepts(&self, tuple: &[Value]) -> bool {
+        match self.constraint {
+            None => true,
+            Some(ref constraint) => constraint.eval(tuple),
+        }
+    }
+}
+
+/// The subscribers to a relation, served by a single dataflow per
+/// relation and generation.
+struct Subscribers {
+    generation: u64,
+    subscribers: Rc<RefCell<Vec<Subscriber>>>,
+    probe: ProbeHandle<u64>,
+}
+
+/// The clients looking up a query, served by a single dataflow per
+/// query and generation.
+struct Lookups {
+    generation: u64,
+    clients: Rc<RefCell<HashSet<usize>>>,
+}
+
+/// Creates the dataflow serving the lookups of all clients of a
+/// query. Results are routed to the worker owning the client their
+/// first value identifies, which only delivers them to clients that
+/// looked up the query.
+fn serve_lookups<A: Allocate>(
+    worker: &mut Worker<A>,
+    server: &mut Server,
+    query: &str,
+    send_results: &mio::channel::Sender<(String, Option<usize>, Response)>,
+) -> Lookups {
+    let clients = Rc::new(RefCell::new(HashSet::new()));
+    let routed = clients.clone();
+    let send_results = send_results.clone();
+    let worker_index = worker.index();
+    let name = query.to_string();
+
+    worker.dataflow::<u64, _, _>(|mut scope| {
+        server.interest(name.clone(), &mut scope)
+            .inner
+            .unary(
+                Exchange::new(|&(ref tuple, _, _): &(Vec<Value>, u64, isize)| {
+                    client_of(&tuple[0]).map(|(owner, _)| owner as u64).unwrap_or(0)
+                }),
+                "LookupsRecv",
+                move |_capability, _info| {
+                    move |input, _output: &mut OutputHandle<_, (), _>| {
+                        input.for_each(|_time, data| {
+                            let clients = routed.borrow();
+                            let mut outputs: HashMap<usize, Vec<Output>> = HashMap::new();
+
+                            for &(ref tuple, t, diff) in data.iter() {
+                                match client_of(&tuple[0]) {
+                                    Some((owner, client)) if owner == worker_index && clients.contains(&client) => {
+                                        outputs
+                                            .entry(client)
+                                            .or_insert_with(Vec::new)
+                                            .push((tuple[1..].to_vec(), diff, t));
+                                    }
+                                    _ => {}
+                                }
+                            }
+
+                            for (client, out) in outputs.into_iter() {
+                                send_results
+                                    .send((name.clone(), Some(client), Response::Results(name.clone(), out)))
+                                    .unwrap();
+                            }
+                        });
+                    }
+                })
+            .probe_with(&mut server.probe);
+    });
+
+    Lookups {
+        generation: server.generation(query),
+        clients,
+    }
+}
+
+/// Forgets the subscriptions, lookups, queued responses, and pending
+/// acknowledgements of a client.
+fn forget(
+    token: Token,
+    interests: &HashMap<String, Subscribers>,
+    lookup_flows: &HashMap<String, Lookups>,
+    queues: &mut HashMap<Token, OutputQueue>,
+    pending_acks: &mut Vec<PendingAck>,
+) {
+    for interest in interests.values() {
+        interest.subscribers.borrow_mut().retain(|subscriber| Token(subscriber.client) != token);
+    }
+
+    for lookups in lookup_flows.values() {
+        lookups.clients.borrow_mut().remove(&token.0);
+    }
+
+    queues.remove(&token);
+    pending_acks.retain(|ack| Token(ack.client) != token);
+}
+
+/// Sends a subscriber the consolidated state of a relation as of its
+/// snapshot time.
+fn send_snapshot(
+    send_results: &mio::channel::Sender<(String, Option<usize>, Response)>,
+    name: &str,
+    subscriber: &mut Subscriber,
+    state: &HashMap<Vec<Value>, isize>,
+) {
+    let out: Vec<Output> = state
+        .iter()
+        .filter(|&(tuple, _)| subscriber.accepts(tuple))
+        .map(|(tuple, &diff)| (tuple.clone(), diff, subscriber.snapshot_time - 1))
+        .collect();
+
+    let response = Response::Notice(Notice::Snapshot(name.to_string(), out, subscriber.id));
+    send_results.send((name.to_string(), Some(subscriber.client), response)).unwrap();
+
+    subscriber.snapshotted = true;
+}
+
+/// Consequences of a client falling behind on receiving results.
+#[derive(Debug, PartialEq, Eq)]
+enum Overflow {
+    /// Queued results for the specified relations were dropped, the
+    /// client has to be unsubscribed from them.
+    Resynced(Vec<String>),
+    /// The client has to be disconnected.
+    Disconnect,
+    /// Inputs must not be accepted until the client has caught up.
+    Block,
+}
+
+/// Responses queued for delivery to a single client.
+#[derive(Default)]
+struct OutputQueue {
+    responses: VecDeque<Response>,
+    size: usize,
+}
+
+impl OutputQueue {
+    /// Enqueues a response, coalescing it with preceding results of
+    /// the same relation.
+    fn push(&mut self, mut response: Response) {
+        self.size += response.size();
+
+        if let Response::Results(ref name, ref mut outputs) = response {
+            if let Some(&mut Response::Results(ref last, ref mut queued)) = self.responses.back_mut() {
+                if last == name {
+                    queued.append(outputs);
+                    return;
+                }
+            }
+        }
+
+        self.responses.push_back(response);
+    }
+
+    /// Drops all queued results, replacing them with a notice asking
+    /// the client to resubscribe to the affected relations, which are
+    /// returned. Other notices are kept.
+    fn resync(&mut self) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        let mut kept = VecDeque::new();
+
+        for response in self.responses.drain(..) {
+            match response {
+                Response::Results(name, _) => {
+                    if !names.contains(&name) {
+                        names.push(name);
+                    }
+                }
+                notice => kept.push_back(notice),
+            }
+        }
+
+        self.responses = kept;
+        self.size = self.responses.iter().map(|response| response.size()).sum();
+        self.push(Response::Notice(Notice::Resync(names.clone())));
+
+        names
+    }
+
+    /// Applies the specified policy if more than `max` results are
+    /// queued, returning what is left to do for the client.
+    fn overflow(&mut self, max: usize, policy: OverflowPolicy) -> Option<Overflow> {
+        if self.size <= max {
+            return None;
+        }
+
+        match policy {
+            OverflowPolicy::Resync => Some(Overflow::Resynced(self.resync())),
+            OverflowPolicy::Disconnect => Some(Overflow::Disconnect),
+            OverflowPolicy::Block => Some(Overflow::Block),
+        }
+    }
+
+    fn drain(&mut self) -> ::std::collections::vec_deque::Drain<Response> {
+        self.size = 0;
+        self.responses.drain(..)
+    }
+}
+
+/// Sorts updates and accumulates the diffs of equal tuples, dropping
+/// those that cancel out.
+fn consolidate(updates: &mut Vec<(Vec<Value>, isize)>) {
+    updates.sort();
+
+    let mut consolidated: Vec<(Vec<Value>, isize)> = Vec::with_capacity(updates.len());
+    for (tuple, diff) in updates.drain(..) {
+        let merge = match consolidated.last() {
+            Some(&(ref last, _)) => *last == tuple,
+            None => false,
+        };
+
+        if merge {
+            consolidated.last_mut().unwrap().1 += diff;
+        } else {
+            consolidated.push((tuple, diff));
+        }
+    }
+
+    consolidated.retain(|&(_, diff)| diff != 0);
+    *updates = consolidated;
+}
+
+const SERVER: Token = Token(usize::MAX - 1);
+const RESULTS: Token = Token(usize::MAX - 2);
+const CLI: Token = Token(usize::MAX - 3);
+const TCP_SERVER: Token = Token(usize::MAX - 4);
+const HTTP_SERVER: Token = Token(usize::MAX - 5);
+
+/// Tokens of plain TCP and HTTP connections start here, WebSocket
+/// connections use the tokens below.
+const PEER_TOKENS: usize = 1 << 20;
+
+fn main() {
+    env_logger::init();
+
+    let mut opts = Options::new();
+    opts.optopt("", "bind", "address to listen at", "ADDRESS");
+    opts.optopt("", "port", "server port", "PORT");
+    opts.optopt("", "tcp-port", "port for plain TCP connections", "PORT");
+    opts.optopt("", "http-port", "port for HTTP requests", "PORT");
+    opts.optflag("", "enable-cli", "enable the CLI interface");
+    opts.optflag("", "enable-history", "enable historical queries");
+    opts.optopt("", "max-queued-results", "maximum number of results queued per client", "COUNT");
+    opts.optopt("", "overflow-policy", "what to do with clients exceeding their queue (resync, disconnect, block)", "POLICY");
+    opts.optopt("", "auth-policy", "JSON file authenticating and authorizing clients", "FILE");
+
+    let args: Vec<String> = std::env::args().collect();
+    let timely_args = std::env::args().take_while(|ref arg| arg.to_string() != "--");
+
+    timely::execute_from_args(timely_args, move |worker| {
+        // read configuration
+        let server_args = args.iter().rev().take_while(|arg| arg.to_string() != "--");
+        let default_config: Config = Default::default();
+        let (config, auth_policy) = match opts.parse(server_args) {
+            Err(err) => panic!(err),
+            Ok(matches) => {
+                let starting_port = matches
+                    .opt_str("port")
+                    .map(|x| x.parse().unwrap_or(default_config.port))
+                    .unwrap_or(default_config.port);
+
+                let config = Config {
+                    bind_address: matches
+                        .opt_str("bind")
+                        .map(|x| x.parse::<IpAddr>().expect("invalid bind address"))
+                        .unwrap_or(default_config.bind_address),
+                    port: starting_port + (worker.index() as u16),
+                    tcp_port: matches
+                        .opt_str("tcp-port")
+                        .map(|x| x.parse::<u16>().expect("invalid tcp port") + (worker.index() as u16)),
+                    http_port: matches
+                        .opt_str("http-port")
+                        .map(|x| x.parse::<u16>().expect("invalid http port") + (worker.index() as u16)),
+                    enable_cli: matches.opt_present("enable-cli"),
+                    enable_history: matches.opt_present("enable-history"),
+                    max_queued_results: matches
+                        .opt_str("max-queued-results")
+                        .map(|x| x.parse().unwrap_or(default_config.max_queued_results))
+                        .unwrap_or(default_config.max_queued_results),
+                    overflow_policy: matches
+                        .opt_str("overflow-policy")
+                        .map(|x| OverflowPolicy::parse(&x).expect("unknown overflow policy"))
+                        .unwrap_or(default_config.overflow_policy),
+                };
+
+                (config, matches.opt_str("auth-policy"))
+            }
+        };
+
+        // setup authentication and authorization of clients, commands
+        // from the CLI are trusted
+        let guard: Box<dyn Guard> = match auth_policy {
+            None => Box::new(AllowAll),
+            Some(path) => {
+                let file = File::open(&path).expect("failed to open auth policy");
+                let policy: Policy = serde_json::from_reader(file).expect("failed to parse auth policy");
+                Box::new(policy)
+            }
+        };
+
+        // principals of authenticated connections
+        let mut principals: HashMap<Token, String> = HashMap::new();
+
+        // setup interpretation context
+        let mut server = Server::new(config.clone());
+
+        // subscribers to each relation, owned by this worker
+        let mut interests: HashMap<String, Subscribers> = HashMap::new();
+
+        // responses waiting to be delivered, per client
+        let mut queues: HashMap<Token, OutputQueue> = HashMap::new();
+
+        // transactions waiting to be acknowledged by this worker
+        let mut pending_acks: Vec<PendingAck> = Vec::new();
+
+        // tokens of disconnected clients, reserved until everything
+        // issued on their behalf has been processed (i.e. until their
+        // `Disconnect` was processed and, if it retracted lookups, the
+        // epoch it was applied at is complete), s.t. nothing still in
+        // flight reaches a client reusing the token
+        let mut departed: HashMap<Token, Option<u64>> = HashMap::new();
+        let mut released: Vec<Token> = Vec::new();
+
+        // the clients looking up each query, by query name
+        let mut lookup_flows: HashMap<String, Lookups> = HashMap::new();
+
+        // setup serialized command queue (shared between all workers)
+        let mut sequencer: Sequencer<Command> = Sequencer::new(worker, Instant::now());
+
+        // configure websocket server
+        let ws_settings = ws::Settings {
+            max_connections: 1024,
+            ..ws::Settings::default()
+        };
+
+        // setup CLI channel
+        let (send_cli, recv_cli) = mio::channel::channel();
+
+        // setup results channel, carrying the client to deliver to
+        // for results of lookup queries
+        let (send_results, recv_results) = mio::channel::channel::<(String, Option<usize>, Response)>();
+
+        // setup server socket
+        let addr = SocketAddr::new(config.bind_address, config.port);
+        let server_socket = TcpListener::bind(&addr).unwrap();
+        let mut connections = Slab::with_capacity(ws_settings.max_connections);
+        let mut next_connection_id: u32 = 0;
+
+        // encodings chosen by WebSocket clients
+        let mut ws_encodings: HashMap<Token, Encoding> = HashMap::new();
+
+        // setup plain TCP socket, sharing the command sequencing and
+        // result routing with WebSocket connections
+        let tcp_socket = config.tcp_port.map(|port| {
+            let addr = SocketAddr::new(config.bind_address, port);
+            TcpListener::bind(&addr).unwrap()
+        });
+        let http_socket = config.http_port.map(|port| {
+            let addr = SocketAddr::new(config.bind_address, port);
+            TcpListener::bind(&addr).unwrap()
+        });
+        let mut peers: Slab<Peer> = Slab::new();
+
+        // setup event loop
+        let poll = Poll::new().unwrap();
+        let mut events = Events::with_capacity(1024);
+
+        if config.enable_cli {
+            poll.register(
+                &recv_cli,
+                CLI,
+                Ready::readable(),
+                PollOpt::edge() | PollOpt::oneshot(),
+            ).unwrap();
+
+            thread::spawn(move || {
+                info!("[CLI] accepting cli commands");
+
+                let input = std::io::stdin();
+                while let Some(line) = input.lock().lines().map(|x| x.unwrap()).next() {
+                    send_cli
+                        .send(line.to_string())
+                        .expect("failed to send command");
+                }
+            });
+        }
+
+        poll.register(
+            &recv_results,
+            RESULTS,
+            Ready::readable(),
+            PollOpt::edge() | PollOpt::oneshot(),
+        ).unwrap();
+        poll.register(&server_socket, SERVER, Ready::readable(), PollOpt::level())
+            .unwrap();
+
+        if let Some(ref tcp_socket) = tcp_socket {
+            poll.register(tcp_socket, TCP_SERVER, Ready::readable(), PollOpt::level())
+                .unwrap();
+        }
+
+        if let Some(ref http_socket) = http_socket {
+            poll.register(http_socket, HTTP_SERVER, Ready::readable(), PollOpt::level())
+                .unwrap();
+        }
+
+        info!(
+            "[WORKER {}] running with config {:?}",
+            worker.index(),
+            config
+        );
+
+        loop {
+            // each worker has to...
+            //
+            // ...accept new client connections
+            // ...accept commands on a client connection and push them to the sequencer
+            // ...step computations
+            // ...send results to clients
+            //
+            // by having everything inside a single event loop, we can
+            // easily make trade-offs such as limiting the number of
+            // commands consumed, in order to ensure timely progress
+            // on registered queues
+
+            // polling - should usually be driven completely
+            // non-blocking (i.e. timeout 0), but higher timeouts can
+            // be used for debugging or artificial braking
+            //
+            // @TODO handle errors
+            poll.poll(&mut events, Some(Duration::from_millis(0)))
+                .unwrap();
+
+            for event in events.iter() {
+                trace!(
+                    "[WORKER {}] recv event on {:?}",
+                    worker.index(),
+                    event.token()
+                );
+
+                match event.token() {
+                    CLI => {
+                        while let Ok(cli_input) = recv_cli.try_recv() {
+                            let command = Command {
+                                owner: worker.index(),
+                                client: None,
+                                encoding: Encoding::Json,
+                                cmd: cli_input.into_bytes(),
+                            };
+
+                            sequencer.push(command);
+                        }
+
+                        poll.reregister(
+                            &recv_cli,
+                            CLI,
+                            Ready::readable(),
+                            PollOpt::edge() | PollOpt::oneshot(),
+                        ).unwrap();
+                    }
+                    SERVER => {
+                        if event.readiness().is_readable() {
+                            // new connection arrived on the server socket
+                            match server_socket.accept() {
+                                Err(err) => error!(
+                                    "[WORKER {}] error while accepting connection {:?}",
+                                    worker.index(),
+                                    err
+                                ),
+                                Ok((socket, addr)) => {
+                                    info!(
+                                        "[WORKER {}] new tcp connection from {}",
+                                        worker.index(),
+                                        addr
+                                    );
+
+                                    // @TODO to nagle or not to nagle?
+                                    // sock.set_nodelay(true)
+
+                                    let token = {
+                                        let entry = connections.vacant_entry();
+                                        let token = Token(entry.key());
+                                        let connection_id = next_connection_id;
+                                        next_connection_id = next_connection_id.wrapping_add(1);
+
+                                        entry.insert(Connection::new(
+                                            token,
+                                            socket,
+                                            ws_settings,
+                                            connection_id,
+                                        ));
+
+                                        token
+                                    };
+
+                                    let conn = &mut connections[token.into()];
+
+                                    conn.as_server().unwrap();
+
+                                    poll.register(
+                                        conn.socket(),
+                                        conn.token(),
+                                        conn.events(),
+                                        PollOpt::edge() | PollOpt::oneshot(),
+                                    ).unwrap();
+                                }
+                            }
+                        }
+                    }
+                    TCP_SERVER => {
+                        if let Some(ref tcp_socket) = tcp_socket {
+                            match tcp_socket.accept() {
+                                Err(err) => error!(
+                                    "[WORKER {}] error while accepting tcp connection {:?}",
+                                    worker.index(),
+                                    err
+                                ),
+                                Ok((socket, addr)) => {
+                                    info!(
+                                        "[WORKER {}] new plain tcp connection from {}",
+                                        worker.index(),
+                                        addr
+                                    );
+
+                                    let entry = peers.vacant_entry();
+                                    let token = Token(PEER_TOKENS + entry.key());
+
+                                    poll.register(
+                                        &socket,
+                                        token,
+                                        Ready::readable(),
+                                        PollOpt::edge() | PollOpt::oneshot(),
+                                    ).unwrap();
+
+                                    entry.insert(Peer::Tcp(TcpConnection::new(socket)));
+                                }
+                            }
+                        }
+                    }
+                    HTTP_SERVER => {
+                        if let Some(ref http_socket) = http_socket {
+                            match http_socket.accept() {
+                                Err(err) => error!(
+                                    "[WORKER {}] error while accepting http connection {:?}",
+                                    worker.index(),
+                                    err
+                                ),
+                                Ok((socket, addr)) => {
+                                    info!(
+                                        "[WORKER {}] new http connection from {}",
+                                        worker.index(),
+                                        addr
+                                    );
+
+                                    let entry = peers.vacant_entry();
+                                    let token = Token(PEER_TOKENS + entry.key());
+
+                                    poll.register(
+                                        &socket,
+                                        token,
+                                        Ready::readable(),
+                                        PollOpt::edge() | PollOpt::oneshot(),
+                                    ).unwrap();
+
+                                    entry.insert(Peer::Http(HttpConnection::new(socket)));
+                                }
+                            }
+                        }
+                    }
+                    RESULTS => {
+                        while let Ok((query_name, client, response)) = recv_results.try_recv() {
+                            info!("[WORKER {}] {:?} {:?}", worker.index(), query_name, response);
+
+                            match client {
+                                None => info!("NO RECIPIENT FOR THIS RESULT"),
+                                Some(client) if departed.contains_key(&Token(client)) => {}
+                                Some(client) => {
+                                    queues
+                                        .entry(Token(client))
+                                        .or_insert_with(OutputQueue::default)
+                                        .push(response);
+                                }
+                            }
+                        }
+
+                        poll.reregister(
+                            &recv_results,
+                            RESULTS,
+                            Ready::readable(),
+                            PollOpt::edge() | PollOpt::oneshot(),
+                        ).unwrap();
+                    }
+                    token if token.0 >= PEER_TOKENS => {
+                        let key = token.0 - PEER_TOKENS;
+                        let readiness = event.readiness();
+
+                        let active = match peers.get_mut(key) {
+                            None => continue,
+                            Some(conn) => {
+                                let mut active = true;
+
+                                if readiness.is_readable() {
+                                    match conn.read() {
+                                        Err(err) => {
+                                            trace!("[WORKER {}] error while reading: {}", worker.index(), err);
+                                            active = false;
+                                        }
+                                        Ok((messages, open)) => {
+                                            for msg in messages.into_iter() {
+                                                let command = Command {
+                                                    owner: worker.index(),
+                                                    client: Some(token.into()),
+                                                    encoding: conn.encoding(),
+                                                    cmd: msg,
+                                                };
+
+                                                match admit(&*guard, &mut principals, token, conn.credential(), &command) {
+                                                    None => {
+                                                        conn.admit();
+                                                        sequencer.push(command);
+                                                    }
+                                                    Some(notice) => reply(&send_results, worker.index(), &command, notice),
+                                                }
+                                            }
+
+                                            active = open;
+                                        }
+                                    }
+                                }
+
+                                if active && readiness.is_writable() {
+                                    if let Err(err) = conn.write() {
+                                        trace!("[WORKER {}] error while writing: {}", worker.index(), err);
+                                        active = false;
+                                    }
+                                }
+
+                                if conn.is_done() {
+                                    active = false;
+                                }
+
+                                if active {
+                                    poll.reregister(
+                                        conn.socket(),
+                                        token,
+                                        conn.events(),
+                                        PollOpt::edge() | PollOpt::oneshot(),
+                                    ).unwrap();
+                                }
+
+                                active
+                            }
+                        };
+
+                        if !active {
+                            debug!("Connection to token={:?} disconnected.", token);
+                            poll.deregister(peers[key].socket()).ok();
+                            principals.remove(&token);
+                            departed.insert(token, None);
+                            forget(token, &interests, &lookup_flows, &mut queues, &mut pending_acks);
+
+                            // release the lookup bindings of this client
+                            sequencer.push(disconnect_command(worker.index(), token.into()));
+                        }
+                    }
+                    _ => {
+                        let token = event.token();
+                        let active = {
+                            let readiness = event.readiness();
+                            let conn_events = connections[token.into()].events();
+
+                            // @TODO refactor connection to accept a
+                            // vector in which to place events and
+                            // rename conn_events to avoid name clash
+
+                            if (readiness & conn_events).is_readable() {
+                                match connections[token.into()].read() {
+                                    Err(err) => {
+                                        trace!(
+                                            "[WORKER {}] error while reading: {}",
+                                            worker.index(),
+                                            err
+                                        );
+                                        // @TODO error handling
+                                        connections[token.into()].error(err)
+                                    }
+                                    Ok(mut conn_events) => {
+                                        for conn_event in conn_events.drain(0..) {
+                                            match conn_event {
+                                                ConnEvent::Message(msg) => {
+                                                    // clients choose the encoding of
+                                                    // responses by that of their requests
+                                                    let encoding = if msg.is_binary() {
+                                                        Encoding::Cbor
+                                                    } else {
+                                                        Encoding::Json
+                                                    };
+
+                                                    ws_encodings.insert(token, encoding);
+
+                                                    let command = Command {
+                                                        owner: worker.index(),
+                                                        client: Some(token.into()),
+                                                        encoding,
+                                                        cmd: msg.into_data(),
+                                                    };
+
+                                                    trace!(
+                                                        "[WORKER {}] {:?}",
+                                                        worker.index(),
+                                                        command
+                                                    );
+
+                                                    match admit(&*guard, &mut principals, token, None, &command) {
+                                                        None => sequencer.push(command),
+                                                        Some(notice) => reply(&send_results, worker.index(), &command, notice),
+                                                    }
+                                                }
+                                                _ => {
+                                                    debug!("Ignoring connection event from token={:?}.", token);
+                                                }
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+
+                            let conn_events = connections[token.into()].events();
+
+                            if (readiness & conn_events).is_writable() {
+                                match connections[token.into()].write() {
+                                    Err(err) => {
+                                        trace!(
+                                            "[WORKER {}] error while writing: {}",
+                                            worker.index(),
+                                            err
+                                        );
+                                        // @TODO error handling
+                                        connections[token.into()].error(err)
+                                    }
+                                    Ok(_) => {}
+                                }
+                            }
+
+                            // connection events may have changed
+                            connections[token.into()].events().is_readable()
+                                || connections[token.into()].events().is_writable()
+                        };
+
+                        // NOTE: Closing state only applies after a ws connection was successfully
+                        // established. It's possible that we may go inactive while in a connecting
+                        // state if the handshake fails.
+                        if !active {
+                            if let Ok(addr) = connections[token.into()].socket().peer_addr() {
+                                debug!("WebSocket connection to {} disconnected.", addr);
+                            } else {
+                                trace!("WebSocket connection to token={:?} disconnected.", token);
+                            }
+                            poll.deregister(connections[token.into()].socket()).ok();
+                            ws_encodings.remove(&token);
+                            principals.remove(&token);
+                            departed.insert(token, None);
+                            forget(token, &interests, &lookup_flows, &mut queues, &mut pending_acks);
+
+                            // release the lookup bindings of this client
+                            sequencer.push(disconnect_command(worker.index(), token.into()));
+                        } else {
+                            let conn = &connections[token.into()];
+                            poll.reregister(
+                                conn.socket(),
+                                conn.token(),
+                                conn.events(),
+                                PollOpt::edge() | PollOpt::oneshot(),
+                            ).unwrap();
+                        }
+                    }
+                }
+            }
+
+            // deliver queued responses to clients that are done
+            // receiving previous ones
+
+            let mut blocked = false;
+            let mut overflowed = Vec::new();
+
+            for (&token, queue) in queues.iter_mut() {
+                let idle = if token.0 >= PEER_TOKENS {
+                    peers.get(token.0 - PEER_TOKENS).map(|conn| !conn.is_writing())
+                } else {
+                    connections.get(token.into()).map(|conn| !conn.events().is_writable())
+                };
+
+                if let Some(idle) = idle {
+                    if idle {
+                        for response in queue.drain() {
+                            info!("[WORKER {}] sending msg {:?}", worker.index(), response);
+
+                            if token.0 >= PEER_TOKENS {
+                                peers[token.0 - PEER_TOKENS].send(&response);
+                            } else {
+                                let msg = match ws_encodings.get(&token).cloned().unwrap_or(Encoding::Json) {
+                                    Encoding::Json => ws::Message::text(
+                                        serde_json::to_string(&response).expect("failed to serialize outputs"),
+                                    ),
+                                    Encoding::Cbor => ws::Message::binary(Encoding::Cbor.encode(&response)),
+                                };
+
+                                connections[token.into()]
+                                    .send_message(msg)
+                                    .expect("failed to send message");
+                            }
+                        }
+
+                        if token.0 >= PEER_TOKENS {
+                            let conn = &peers[token.0 - PEER_TOKENS];
+                            poll.reregister(
+                                conn.socket(),
+                                token,
+                                conn.events(),
+                                PollOpt::edge() | PollOpt::oneshot(),
+                            ).unwrap();
+                        } else {
+                            let conn = &connections[token.into()];
+                            poll.reregister(
+                                conn.socket(),
+                                conn.token(),
+                                conn.events(),
+                                PollOpt::edge() | PollOpt::oneshot(),
+                            ).unwrap();
+                        }
+                    }
+
+                    match queue.overflow(config.max_queued_results, config.overflow_policy) {
+                        None => {}
+                        Some(Overflow::Resynced(names)) => {
+                            // the client stops receiving results until
+                            // it expresses interest anew
+                            for name in names.iter() {
+                                if let Some(interest) = interests.get(name) {
+                                    interest.subscribers.borrow_mut().retain(|s| Token(s.client) != token);
+                                }
+                            }
+                        }
+                        Some(Overflow::Disconnect) => overflowed.push(token),
+                        Some(Overflow::Block) => blocked = true,
+                    }
+                }
+            }
+
+            for token in overflowed.into_iter() {
+                info!("[WORKER {}] disconnecting client {:?}, which fell behind", worker.index(), token);
+
+                if token.0 >= PEER_TOKENS {
+                    poll.deregister(peers[token.0 - PEER_TOKENS].socket()).ok();
+                } else {
+                    poll.deregister(connections[token.into()].socket()).ok();
+                    ws_encodings.remove(&token);
+                }
+
+                principals.remove(&token);
+                departed.insert(token, None);
+                forget(token, &interests, &lookup_flows, &mut queues, &mut pending_acks);
+
+                // release the lookup bindings of this client
+                sequencer.push(disconnect_command(worker.index(), token.into()));
+            }
+
+            queues.retain(|&token, queue| {
+                let connected = if token.0 >= PEER_TOKENS {
+                    peers.contains(token.0 - PEER_TOKENS)
+                } else {
+                    connections.contains(token.into())
+                };
+
+                connected && !queue.responses.is_empty()
+            });
+
+            // handle commands, unless clients have to catch up first
+
+            while let Some(command) = if blocked { None } else { sequencer.next() } {
+                match command.encoding.decode(&command.cmd) {
+                    Err(msg) => {
+                        error!("[WORKER {}] failed to parse command: {:?}", worker.index(), msg);
+                        reply(&send_results, worker.index(), &command, Notice::Error(msg, None));
+                    }
+                    // handshakes are answered on receipt and never sequenced
+                    Ok(Message::Authenticate(_)) => {}
+                    Ok(Message::Requests(id, mut requests)) => {
+                        info!("[WORKER {}] {:?} {:?}", worker.index(), id, requests);
+
+                        for req in requests.drain(..) {
+                            let owner = command.owner.clone();
+
+                            // requests without a reply of their own are
+                            // acknowledged once processed
+                            let acknowledge = match req {
+                                Request::Transact(_) | Request::Interest(_) | Request::Disconnect => false,
+                                _ => true,
+                            };
+
+                            // @TODO only create a single dataflow, but only if req != Transact
+
+                            match req {
+                                Request::Datom(e, a, v, diff, tx) => server.datom(owner, worker.index(), e, a, v, diff, tx),
+                                Request::Transact(req) => {
+                                    let wait_for: Vec<String> = req.wait_for
+                                        .iter()
+                                        .filter(|name| {
+                                            let known = server.probes.contains_key(name.as_str());
+                                            if !known {
+                                                let msg = format!("can't wait for unknown relation {:?}", name);
+                                                error!("[WORKER {}] {}", worker.index(), msg);
+                                                reply(&send_results, worker.index(), &command, Notice::Error(msg, id));
+                                            }
+                                            known
+                                        })
+                                        .cloned()
+                                        .collect();
+
+                                    let tx = server.transact(req, owner, worker.index());
+
+                                    if owner == worker.index() {
+                                        if let Some(client) = command.client {
+                                            pending_acks.push(PendingAck { client, id, tx, wait_for });
+                                        }
+                                    }
+                                }
+                                Request::Interest(req) => {
+                                    let constraint = match req.constraint {
+                                        None => None,
+                                        Some(ref expr) => match expr.compile_positional(Collation::Binary) {
+                                            Err(msg) => {
+                                                error!("[WORKER {}] invalid constraint: {}", worker.index(), msg);
+                                                reply(&send_results, worker.index(), &command, Notice::Error(msg, id));
+                                                continue;
+                                            }
+                                            Ok(compiled) => Some(compiled),
+                                        },
+                                    };
+
+                                    // all subscribers to a relation are served by
+                                    // a single dataflow, which is created by all
+                                    // workers alike
+                                    if !interests.contains_key(&req.name) {
+                                        let subscribers: Rc<RefCell<Vec<Subscriber>>> = Rc::new(RefCell::new(Vec::new()));
+                                        let probe = ProbeHandle::new();
+                                        let send_results_handle = send_results.clone();
+
+                                        worker.dataflow::<u64, _, _>(|mut scope| {
+                                            let name = req.name.clone();
+                                            let subscribers = subscribers.clone();
+                                            let mut probe = probe.clone();
+
+                                            // consolidated state as of all complete times,
+                                            // from which snapshots are taken
+                                            let mut state: HashMap<Vec<Value>, isize> = HashMap::new();
+                                            let mut deltas: HashMap<u64, Vec<(Vec<Value>, isize)>> = HashMap::new();
+
+                                            // subscribers may be owned by any worker,
+                                            // thus each worker receives all updates
+                                            server.interest(req.name.clone(), &mut scope)
+                                                .inner
+                                                .broadcast()
+                                                .unary_notify(
+                                                    Pipeline,
+                                                    "OutputsRecv",
+                                                    vec![],
+                                                    move |input, _output: &mut OutputHandle<_, (), _>, notificator| {
+                                                        input.for_each(|cap, data| {
+                                                            for &(ref tuple, t, diff) in data.iter() {
+                                                                deltas.entry(t).or_insert_with(Vec::new).push((tuple.clone(), diff));
+                                                                notificator.notify_at(cap.delayed(&t));
+                                                            }
+                                                        });
+
+                                                        // results are only sent once their time is
+                                                        // complete, s.t. they can be consolidated
+                                                        notificator.for_each(|cap, _, _| {
+                                                            let time = *cap.time();
+                                                            let mut subscribers = subscribers.borrow_mut();
+
+                                                            for subscriber in subscribers.iter_mut() {
+                                                                if !subscriber.snapshotted && subscriber.snapshot_time <= time {
+                                                                    send_snapshot(&send_results_handle, &name, subscriber, &state);
+                                                                }
+                                                            }
+
+                                                            if let Some(mut updates) = deltas.remove(&time) {
+                                                                consolidate(&mut updates);
+
+                                                                for subscriber in subscribers.iter().filter(|s| s.snapshotted) {
+                                                                    let out: Vec<Output> = updates
+                                                                        .iter()
+                                                                        .filter(|&&(ref tuple, _)| subscriber.accepts(tuple))
+                                                                        .map(|&(ref tuple, diff)| (tuple.clone(), diff, time))
+                                                                        .collect();
+
+                                                                    if !out.is_empty() {
+                                                                        let response = Response::Results(name.clone(), out);
+                                                                        send_results_handle.send((name.clone(), Some(subscriber.client), response)).unwrap();
+                                                                    }
+                                                                }
+
+                                                                for (tuple, diff) in updates.into_iter() {
+                                                                    let remove = {
+                                                                        let count = state.entry(tuple.clone()).or_insert(0);
+                                                                        *count += diff;
+                                                                        *count == 0
+                                                                    };
+
+                                                                    if remove {
+                                                                        state.remove(&tuple);
+                                                                    }
+                                                                }
+                                                            }
+                                                        });
+
+                                                        // snapshots are due once all earlier times
+                                                        // are complete, even if nothing changed
+                                                        let frontier = notificator.frontier(0).to_vec();
+                                                        for subscriber in subscribers.borrow_mut().iter_mut() {
+                                                            if !subscriber.snapshotted && frontier.iter().all(|t| subscriber.snapshot_time <= *t) {
+                                                                send_snapshot(&send_results_handle, &name, subscriber, &state);
+                                                            }
+                                                        }
+                                                    })
+                                                .probe_with(&mut probe)
+                                                .probe_with(&mut server.probe);
+                                        });
+
+                                        interests.insert(req.name.clone(), Subscribers {
+                                            generation: server.generation(&req.name),
+                                            subscribers,
+                                            probe,
+                                        });
+                                    }
+
+                                    if owner == worker.index() {
+                                        if let Some(client) = command.client {
+                                            let snapshot_time = server.epoch();
+
+                                            interests[&req.name].subscribers.borrow_mut().push(Subscriber {
+                                                client,
+                                                id,
+                                                constraint,
+                                                snapshot_time,
+                                                snapshotted: false,
+                                                reported: snapshot_time,
+                                            });
+                                        }
+                                    }
+                                }
+                                Request::Register(mut req) => {
+                                    if let Err(msg) = server.validate(&mut req) {
+                                        error!("[WORKER {}] {}", worker.index(), msg);
+                                        reply(&send_results, worker.index(), &command, Notice::Error(msg, id));
+                                        continue;
+                                    }
+
+                                    let send_results_handle = send_results.clone();
+                                    let client = command.client;
+                                    let worker_index = worker.index();
+
+                                    worker.dataflow::<u64, _, _>(|mut scope| {
+                                        server.register(req, &mut scope)
+                                            .inner
+                                            .unary(
+                                                Exchange::new(move |_: &(String, u64, isize)| owner as u64),
+                                                "FailuresRecv",
+                                                move |_capability, _info| {
+                                                    move |input, _output: &mut OutputHandle<_, (), _>| {
+                                                        input.for_each(|_time, data| {
+                                                            for &(ref msg, _, diff) in data.iter() {
+                                                                if diff > 0 {
+                                                                    error!("[WORKER {}] {}", worker_index, msg);
+
+                                                                    if let Some(client) = client {
+                                                                        send_results_handle
+                                                                            .send((String::new(), Some(client), Response::Notice(Notice::Error(msg.clone(), id))))
+                                                                            .unwrap();
+                                                                    }
+                                                                }
+                                                            }
+                                                        });
+                                                    }
+                                                })
+                                            .probe_with(&mut server.probe);
+                                    });
+
+                                    // subscribers to replaced relations have to
+                                    // express interest anew
+                                    let replaced: Vec<String> = interests
+                                        .iter()
+                                        .filter(|&(name, interest)| interest.generation != server.generation(name))
+                                        .map(|(name, _)| name.clone())
+                                        .collect();
+
+                                    for name in replaced.into_iter() {
+                                        if let Some(interest) = interests.remove(&name) {
+                                            for subscriber in interest.subscribers.borrow_mut().drain(..) {
+                                                let response = Response::Notice(Notice::Resync(vec![name.clone()]));
+                                                send_results.send((name.clone(), Some(subscriber.client), response)).unwrap();
+                                            }
+                                        }
+                                    }
+
+                                    // lookups of replaced queries are served by a
+                                    // new dataflow, the previous one no longer
+                                    // delivers to anyone
+                                    let replaced: Vec<String> = lookup_flows
+                                        .iter()
+                                        .filter(|&(name, lookups)| lookups.generation != server.generation(name))
+                                        .map(|(name, _)| name.clone())
+                                        .collect();
+
+                                    for name in replaced.into_iter() {
+                                        let clients: Vec<usize> = lookup_flows[&name].clients.borrow_mut().drain().collect();
+
+                                        for &client in clients.iter() {
+                                            let response = Response::Notice(Notice::Resync(vec![name.clone()]));
+                                            send_results.send((name.clone(), Some(client), response)).unwrap();
+                                        }
+
+                                        let lookups = serve_lookups(worker, &mut server, &name, &send_results);
+                                        lookups.clients.borrow_mut().extend(clients);
+                                        lookup_flows.insert(name, lookups);
+                                    }
+                                }
+                                Request::RegisterSource(req) => {
+                                    worker.dataflow::<u64, _, _>(|mut scope| {
+                                        server.register_source(req, &mut scope);
+                                    });
+                                }
+                                Request::CreateInput(CreateInput { name }) => {
+                                    worker.dataflow::<u64, _, _>(|mut scope| {
+                                        server.create_input(name, &mut scope);
+                                    });
+                                }
+                                Request::AdvanceInput(name, tx) => server.advance_input(name, tx),
+                                Request::CloseInput(name) => server.close_input(name),
+                                Request::SetParams(req) => {
+                                    if let Err(msg) = server.validate_params(&req) {
+                                        error!("[WORKER {}] {}", worker.index(), msg);
+                                        reply(&send_results, worker.index(), &command, Notice::Error(msg, id));
+                                        continue;
+                                    }
+
+                                    server.set_params(req, owner, worker.index());
+                                }
+                                Request::Lookup(req) => {
+                                    let client = match command.client {
+                                        None => {
+                                            error!("[WORKER {}] lookups require a client", worker.index());
+                                            reply(&send_results, worker.index(), &command, Notice::Error("lookups require a client".to_string(), id));
+                                            continue;
+                                        }
+                                        Some(client) => client,
+                                    };
+
+                                    if let Err(msg) = server.validate_lookup(&req) {
+                                        error!("[WORKER {}] {}", worker.index(), msg);
+                                        reply(&send_results, worker.index(), &command, Notice::Error(msg, id));
+                                        continue;
+                                    }
+
+                                    if !lookup_flows.contains_key(&req.query) {
+                                        let lookups = serve_lookups(worker, &mut server, &req.query, &send_results);
+                                        lookup_flows.insert(req.query.clone(), lookups);
+                                    }
+
+                                    if owner == worker.index() {
+                                        lookup_flows[&req.query].clients.borrow_mut().insert(client);
+                                    }
+
+                                    server.lookup(req, owner, client, worker.index());
+                                }
+                                Request::Disconnect => {
+                                    if let Some(client) = command.client {
+                                        let epoch = server.epoch();
+                                        let retracted = server.disconnect(owner, client, worker.index());
+
+                                        if owner == worker.index() {
+                                            let token = Token(client);
+                                            forget(token, &interests, &lookup_flows, &mut queues, &mut pending_acks);
+
+                                            if retracted {
+                                                if let Some(applied) = departed.get_mut(&token) {
+                                                    *applied = Some(epoch);
+                                                }
+                                            } else if departed.remove(&token).is_some() {
+                                                released.push(token);
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+
+                            if acknowledge {
+                                if let Some(id) = id {
+                                    reply(&send_results, worker.index(), &command, Notice::Ack(id));
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+
+            // ensure work continues, even if no queries registered,
+            // s.t. the sequencer continues issuing commands
+            worker.step();
+
+            worker.step_while(|| server.is_any_outdated());
+
+            // acknowledge transactions reflected in all relations waited for
+            let (complete, pending): (Vec<PendingAck>, Vec<PendingAck>) = pending_acks
+                .drain(..)
+                .partition(|ack| ack.wait_for.iter().all(|name| server.is_complete(name, ack.tx)));
+
+            pending_acks = pending;
+
+            for ack in complete.into_iter() {
+                let response = Response::Notice(Notice::Transacted(ack.tx, ack.id));
+                send_results.send((String::new(), Some(ack.client), response)).unwrap();
+            }
+
+            // release the tokens of departed clients
+            departed.retain(|&token, applied| match *applied {
+                Some(epoch) if !server.probe.less_equal(&epoch) => {
+                    released.push(token);
+                    false
+                }
+                _ => true,
+            });
+
+            for token in released.drain(..) {
+                if token.0 >= PEER_TOKENS {
+                    peers.remove(token.0 - PEER_TOKENS);
+                } else {
+                    connections.remove(token.into());
+                }
+            }
+
+            // notify clients of transactions whose results are complete
+            for (name, interest) in interests.iter() {
+                let frontier = interest.probe.with_frontier(|frontier| frontier.iter().min().cloned());
+
+                if let Some(frontier) = frontier {
+                    for subscriber in interest.subscribers.borrow_mut().iter_mut() {
+                        // frontiers are only reported after snapshots
+                        if subscriber.snapshotted && frontier > subscriber.reported {
+                            subscriber.reported = frontier;
+
+                            let response = Response::Notice(Notice::Frontier(name.clone(), frontier - 1));
+                            send_results.send((name.clone(), Some(subscriber.client), response)).unwrap();
+                        }
+                    }
+                }
+            }
+        }
+    }).unwrap(); // asserts error-free execution
+}
+
+#[cfg(test)]
+mod tests {
+    use declarative_dataflow::server::OverflowPolicy;
+    use declarative_dataflow::Value;
+
+    use protocol::{Notice, Response};
+
+    use super::{Overflow, OutputQueue};
+
+    fn results(name: &str, count: usize) -> Response {
+        let outputs = (0..count).map(|i| (vec![Value::Number(i as i64)], 1, 0)).collect();
+        Response::Results(name.to_string(), outputs)
+    }
+
+    /// A queue holding results for two relations around a notice.
+    fn filled() -> OutputQueue {
+        let mut queue = OutputQueue::default();
+        queue.push(results("a", 2));
+        queue.push(results("a", 1));
+        queue.push(Response::Notice(Notice::Ack(0)));
+        queue.push(results("b", 3));
+        queue.push(results("a", 1));
+        queue
+    }
+
+    #[test]
+    fn coalescing() {
+        let mut queue = filled();
+        assert_eq!(queue.size, 8);
+
+        let sizes: Vec<(Option<String>, usize)> = queue
+            .drain()
+            .map(|response| match response {
+                Response::Results(ref name, ref outputs) => (Some(name.clone()), outputs.len()),
+                Response::Notice(_) => (None, 1),
+            }).collect();
+
+        assert_eq!(
+            sizes,
+            vec![
+                (Some("a".to_string()), 3),
+                (None, 1),
+                (Some("b".to_string()), 3),
+                (Some("a".to_string()), 1),
+            ]
+        );
+        assert_eq!(queue.size, 0);
+    }
+
+    #[test]
+    fn resync() {
+        let mut queue = filled();
+        assert_eq!(queue.resync(), vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(queue.size, 2);
+
+        let responses: Vec<Response> = queue.drain().collect();
+        match &responses[..] {
+            &[Response::Notice(Notice::Ack(0)), Response::Notice(Notice::Resync(ref names))] => {
+                assert_eq!(names, &vec!["a".to_string(), "b".to_string()]);
+            }
+            other => panic!("unexpected responses {:?}", other),
+        }
+    }
+
+    #[test]
+    fn overflow_policies() {
+        for policy in &[OverflowPolicy::Resync, OverflowPolicy::Disconnect, OverflowPolicy::Block] {
+            let mut queue = filled();
+            assert_eq!(queue.overflow(8, *policy), None);
+            assert_eq!(queue.size, 8);
+        }
+
+        let mut queue = filled();
+        assert_eq!(
+            queue.overflow(7, OverflowPolicy::Resync),
+            Some(Overflow::Resynced(vec!["a".to_string(), "b".to_string()]))
+        );
+        assert_eq!(queue.size, 2);
+        assert_eq!(queue.overflow(7, OverflowPolicy::Resync), None);
+
+        let mut queue = filled();
+        assert_eq!(queue.overflow(7, OverflowPolicy::Disconnect), Some(Overflow::Disconnect));
+        assert_eq!(queue.size, 8);
+
+        let mut queue = filled();
+        assert_eq!(queue.overflow(7, OverflowPolicy::Block), Some(Overflow::Block));
+        assert_eq!(queue.size, 8);
+    }
+}
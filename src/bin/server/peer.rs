@@ -0,0 +1,730 @@
+//! Client connections other than WebSocket ones.
+
+use std::io::{self, Read, Write};
+
+use mio::net::TcpStream;
+use mio::Ready;
+
+use serde_json;
+
+use declarative_dataflow::server::{Register, Transact};
+
+use protocol::{Encoding, Notice, Response};
+
+/// Maximum size of messages, excluding their framing.
+const MAX_FRAME: usize = (1 << 24) - 1;
+
+/// Maximum size of the head of HTTP requests.
+const MAX_HEAD: usize = 1 << 16;
+
+/// Reads all input available on the socket, returning whether the
+/// connection is still open.
+fn read_available(socket: &mut TcpStream, inbox: &mut Vec<u8>) -> io::Result<bool> {
+    let mut buffer = [0; 4096];
+
+    loop {
+        match socket.read(&mut buffer) {
+            Ok(0) => return Ok(false),
+            Ok(count) => inbox.extend_from_slice(&buffer[..count]),
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(true),
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Writes as much output as the socket accepts.
+fn write_available(socket: &mut TcpStream, outbox: &mut Vec<u8>) -> io::Result<()> {
+    while !outbox.is_empty() {
+        match socket.write(outbox) {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::WriteZero, "connection closed")),
+            Ok(count) => {
+                outbox.drain(..count);
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => break,
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(())
+}
+
+/// A plain TCP client connection. Clients sending JSON separate
+/// messages by newlines, clients sending CBOR prefix each message by
+/// its length as a 32 bit big-endian integer. Messages are limited to
+/// `MAX_FRAME` bytes, s.t. the first byte sent by CBOR clients is
+/// always zero, which selects the encoding for the connection.
+pub struct TcpConnection {
+    socket: TcpStream,
+    encoding: Option<Encoding>,
+    inbox: Vec<u8>,
+    outbox: Vec<u8>,
+}
+
+impl TcpConnection {
+    /// Wraps an accepted socket.
+    pub fn new(socket: TcpStream) -> Self {
+        TcpConnection {
+            socket,
+            encoding: None,
+            inbox: Vec::new(),
+            outbox: Vec::new(),
+        }
+    }
+
+    fn read(&mut self) -> io::Result<(Vec<Vec<u8>>, bool)> {
+        let open = read_available(&mut self.socket, &mut self.inbox)?;
+
+        if self.encoding.is_none() {
+            self.encoding = match self.inbox.first() {
+                None => None,
+                Some(&0) => Some(Encoding::Cbor),
+                Some(_) => Some(Encoding::Json),
+            };
+        }
+
+        let mut messages = Vec::new();
+        match self.encoding {
+            None => {}
+            Some(Encoding::Json) => {
+                while let Some(end) = self.inbox.iter().position(|&byte| byte == b'\n') {
+                    if end > MAX_FRAME {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "message too large"));
+                    }
+
+                    let line: Vec<u8> = self.inbox.drain(..end + 1).collect();
+
+                    if !line.iter().all(|byte| byte.is_ascii_whitespace()) {
+                        messages.push(line);
+                    }
+                }
+
+                // the pending line is incomplete, but may not grow
+                // without bounds
+                if self.inbox.len() > MAX_FRAME {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "message too large"));
+                }
+            }
+            Some(Encoding::Cbor) => {
+                while self.inbox.len() >= 4 {
+                    let length = self.inbox[..4]
+                        .iter()
+                        .fold(0, |length, &byte| (length << 8) | byte as usize);
+
+                    if length > MAX_FRAME {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "message too large"));
+                    } else if self.inbox.len() < 4 + length {
+                        break;
+                    }
+
+                    messages.push(self.inbox.drain(..4 + length).skip(4).collect());
+                }
+            }
+        }
+
+        Ok((messages, open))
+    }
+
+    fn send(&mut self, response: &Response) {
+        let encoding = self.encoding.unwrap_or(Encoding::Json);
+        let msg = encoding.encode(response);
+
+        match encoding {
+            Encoding::Json => {
+                self.outbox.extend_from_slice(&msg);
+                self.outbox.push(b'\n');
+            }
+            Encoding::Cbor => {
+                let length = msg.len() as u32;
+                self.outbox.extend_from_slice(&[
+                    (length >> 24) as u8,
+                    (length >> 16) as u8,
+                    (length >> 8) as u8,
+                    length as u8,
+                ]);
+                self.outbox.extend_from_slice(&msg);
+            }
+        }
+    }
+}
+
+/// States of an HTTP connection, which serves a single request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum HttpState {
+    /// Reading the request.
+    Reading,
+    /// Waiting for the request to be admitted, to then answer it
+    /// immediately.
+    Admitting,
+    /// Waiting for the request to be admitted, to then start
+    /// streaming.
+    Subscribing,
+    /// Waiting for the acknowledgement or snapshot answering the
+    /// request.
+    Awaiting,
+    /// Streaming results as server-sent events.
+    Streaming,
+    /// Closing the connection once the response is written.
+    Closing,
+}
+
+/// An HTTP client connection. Requests are mapped onto JSON commands,
+/// bodies that don't hold a valid request are answered with 400:
+///
+/// - `POST /transact` (with a `Transact` body) answers with the
+///   transaction acknowledgement
+/// - `POST /register` (with a `Register` body) answers immediately
+/// - `GET /query/{name}` answers with a snapshot of the relation
+/// - `GET /subscribe/{name}` streams all responses for the relation as
+///   server-sent events
+///
+/// Clients authenticate by an `Authorization: Bearer {token}` header.
+/// Request heads are limited to `MAX_HEAD` bytes, bodies to
+/// `MAX_FRAME` bytes.
+pub struct HttpConnection {
+    socket: TcpStream,
+    state: HttpState,
+    credential: Option<String>,
+    inbox: Vec<u8>,
+    outbox: Vec<u8>,
+}
+
+impl HttpConnection {
+    /// Wraps an accepted socket.
+    pub fn new(socket: TcpStream) -> Self {
+        HttpConnection {
+            socket,
+            state: HttpState::Reading,
+            credential: None,
+            inbox: Vec::new(),
+            outbox: Vec::new(),
+        }
+    }
+
+    fn read(&mut self) -> io::Result<(Vec<Vec<u8>>, bool)> {
+        let open = read_available(&mut self.socket, &mut self.inbox)?;
+
+        if self.state != HttpState::Reading {
+            self.inbox.clear();
+            return Ok((Vec::new(), open));
+        }
+
+        let head_end = match self.inbox.windows(4).position(|window| window == b"\r\n\r\n") {
+            None if self.inbox.len() > MAX_HEAD => {
+                self.respond("413 Payload Too Large", "text/plain", b"Request head too large");
+                return Ok((Vec::new(), open));
+            }
+            None => return Ok((Vec::new(), open)),
+            Some(position) if position + 4 > MAX_HEAD => {
+                self.respond("413 Payload Too Large", "text/plain", b"Request head too large");
+                return Ok((Vec::new(), open));
+            }
+            Some(position) => position + 4,
+        };
+
+        let head = String::from_utf8_lossy(&self.inbox[..head_end]).into_owned();
+        let mut lines = head.lines();
+        let mut request_line = lines.next().unwrap_or("").split_whitespace();
+        let method = request_line.next().unwrap_or("").to_string();
+        let path = request_line.next().unwrap_or("").to_string();
+
+        let mut content_length = 0;
+        let mut credential = None;
+
+        for line in lines {
+            let mut parts = line.splitn(2, ':');
+            let name = parts.next().unwrap_or("").trim();
+            let value = parts.next().unwrap_or("").trim();
+
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = match value.parse::<usize>() {
+                    Err(_) => {
+                        self.respond("400 Bad Request", "text/plain", b"Invalid Content-Length");
+                        return Ok((Vec::new(), open));
+                    }
+                    Ok(length) if length > MAX_FRAME => {
+                        self.respond("413 Payload Too Large", "text/plain", b"Request body too large");
+                        return Ok((Vec::new(), open));
+                    }
+                    Ok(length) => length,
+                };
+            } else if name.eq_ignore_ascii_case("authorization") && value.starts_with("Bearer ") {
+                credential = Some(value["Bearer ".len()..].trim().to_string());
+            }
+        }
+
+        if self.inbox.len() < head_end + content_length {
+            return Ok((Vec::new(), open));
+        }
+
+        self.credential = credential;
+
+        let body: Vec<u8> = self.inbox
+            .drain(..head_end + content_length)
+            .skip(head_end)
+            .collect();
+
+        let interest = |name: &str| {
+            let mut interest = serde_json::Map::new();
+            interest.insert("name".to_string(), serde_json::Value::String(percent_decode(name)));
+            command("Interest", serde_json::Value::Object(interest))
+        };
+
+        let cmd = match (method.as_str(), path.as_str()) {
+            ("POST", "/transact") => match parse_body(&body, serde_json::from_value::<Transact>) {
+                Err(msg) => {
+                    self.respond("400 Bad Request", "text/plain", msg.as_bytes());
+                    None
+                }
+                Ok(body) => {
+                    self.state = HttpState::Awaiting;
+                    Some(command("Transact", body))
+                }
+            },
+            ("POST", "/register") => match parse_body(&body, serde_json::from_value::<Register>) {
+                Err(msg) => {
+                    self.respond("400 Bad Request", "text/plain", msg.as_bytes());
+                    None
+                }
+                Ok(body) => {
+                    self.state = HttpState::Admitting;
+                    Some(command("Register", body))
+                }
+            },
+            ("GET", path) if path.starts_with("/query/") => {
+                self.state = HttpState::Awaiting;
+                Some(interest(&path["/query/".len()..]))
+            }
+            ("GET", path) if path.starts_with("/subscribe/") => {
+                self.state = HttpState::Subscribing;
+                Some(interest(&path["/subscribe/".len()..]))
+            }
+            ("GET", _) | ("POST", _) => {
+                self.respond("404 Not Found", "text/plain", b"Not Found");
+                None
+            }
+            _ => {
+                self.respond("405 Method Not Allowed", "text/plain", b"Method Not Allowed");
+                None
+            }
+        };
+
+        Ok((cmd.into_iter().collect(), open))
+    }
+
+    /// Answers requests waiting for their admission.
+    fn admit(&mut self) {
+        match self.state {
+            HttpState::Admitting => self.respond("202 Accepted", "application/json", b"{}"),
+            HttpState::Subscribing => {
+                self.state = HttpState::Streaming;
+                self.outbox.extend_from_slice(
+                    b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+                );
+            }
+            _ => {}
+        }
+    }
+
+    /// Writes a complete response, closing the connection afterwards.
+    fn respond(&mut self, status: &str, content_type: &str, body: &[u8]) {
+        let head = format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            status,
+            content_type,
+            body.len()
+        );
+
+        self.outbox.extend_from_slice(head.as_bytes());
+        self.outbox.extend_from_slice(body);
+        self.state = HttpState::Closing;
+    }
+
+    fn send(&mut self, response: &Response) {
+        match self.state {
+            HttpState::Awaiting | HttpState::Admitting | HttpState::Subscribing => match response {
+                &Response::Notice(Notice::Transacted(_, _)) | &Response::Notice(Notice::Snapshot(_, _, _)) => {
+                    let body = Encoding::Json.encode(response);
+                    self.respond("200 OK", "application/json", &body);
+                }
+                &Response::Notice(Notice::Error(_, _)) => {
+                    let body = Encoding::Json.encode(response);
+                    self.respond("400 Bad Request", "application/json", &body);
+                }
+                &Response::Notice(Notice::Unauthorized(_, _)) => {
+                    let body = Encoding::Json.encode(response);
+                    self.respond("403 Forbidden", "application/json", &body);
+                }
+                _ => {}
+            },
+            HttpState::Streaming => {
+                self.outbox.extend_from_slice(b"data: ");
+                self.outbox.extend_from_slice(&Encoding::Json.encode(response));
+                self.outbox.extend_from_slice(b"\n\n");
+            }
+            HttpState::Reading | HttpState::Closing => {}
+        }
+    }
+}
+
+/// Parses the body of an HTTP request, which has to be a valid
+/// request of the type the specified parser produces.
+fn parse_body<T>(
+    body: &[u8],
+    parse: fn(serde_json::Value) -> serde_json::Result<T>,
+) -> Result<serde_json::Value, String> {
+    let value: serde_json::Value = serde_json::from_slice(body).map_err(|err| err.to_string())?;
+    parse(value.clone()).map_err(|err| err.to_string())?;
+
+    Ok(value)
+}
+
+/// Builds a JSON command holding a single request of the specified
+/// variant.
+fn command(variant: &str, request: serde_json::Value) -> Vec<u8> {
+    let mut wrapped = serde_json::Map::new();
+    wrapped.insert(variant.to_string(), request);
+
+    serde_json::to_vec(&serde_json::Value::Array(vec![serde_json::Value::Object(wrapped)]))
+        .expect("JSON values are always serializable")
+}
+
+/// Decodes percent-encoded characters in a path segment.
+fn percent_decode(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = if bytes[i] == b'%' && i + 2 < bytes.len() {
+            ::std::str::from_utf8(&bytes[i + 1..i + 3])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        } else {
+            None
+        };
+
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Client connections served by this module.
+pub enum Peer {
+    /// A plain TCP connection
+    Tcp(TcpConnection),
+    /// An HTTP connection
+    Http(HttpConnection),
+}
+
+impl Peer {
+    /// The socket of the connection.
+    pub fn socket(&self) -> &TcpStream {
+        match self {
+            &Peer::Tcp(ref conn) => &conn.socket,
+            &Peer::Http(ref conn) => &conn.socket,
+        }
+    }
+
+    /// The encoding of messages received on this connection.
+    pub fn encoding(&self) -> Encoding {
+        match self {
+            &Peer::Tcp(ref conn) => conn.encoding.unwrap_or(Encoding::Json),
+            &Peer::Http(_) => Encoding::Json,
+        }
+    }
+
+    /// The credential presented along with the last message, for
+    /// connections authenticating each request.
+    pub fn credential(&self) -> Option<&str> {
+        match self {
+            &Peer::Tcp(_) => None,
+            &Peer::Http(ref conn) => conn.credential.as_ref().map(|credential| credential.as_str()),
+        }
+    }
+
+    /// Reads all available input, returning complete messages and
+    /// whether the connection is still open.
+    pub fn read(&mut self) -> io::Result<(Vec<Vec<u8>>, bool)> {
+        match self {
+            &mut Peer::Tcp(ref mut conn) => conn.read(),
+            &mut Peer::Http(ref mut conn) => conn.read(),
+        }
+    }
+
+    /// Notifies the connection that a message it read has been
+    /// admitted for sequencing.
+    pub fn admit(&mut self) {
+        match self {
+            &mut Peer::Tcp(_) => {}
+            &mut Peer::Http(ref mut conn) => conn.admit(),
+        }
+    }
+
+    /// Queues a response for sending.
+    pub fn send(&mut self, response: &Response) {
+        match self {
+            &mut Peer::Tcp(ref mut conn) => conn.send(response),
+            &mut Peer::Http(ref mut conn) => conn.send(response),
+        }
+    }
+
+    /// Writes as much queued output as the socket accepts.
+    pub fn write(&mut self) -> io::Result<()> {
+        match self {
+            &mut Peer::Tcp(ref mut conn) => write_available(&mut conn.socket, &mut conn.outbox),
+            &mut Peer::Http(ref mut conn) => write_available(&mut conn.socket, &mut conn.outbox),
+        }
+    }
+
+    /// True iff previously queued output has not been written yet.
+    pub fn is_writing(&self) -> bool {
+        match self {
+            &Peer::Tcp(ref conn) => !conn.outbox.is_empty(),
+            &Peer::Http(ref conn) => !conn.outbox.is_empty(),
+        }
+    }
+
+    /// True iff the connection has nothing left to do and should be
+    /// closed.
+    pub fn is_done(&self) -> bool {
+        match self {
+            &Peer::Tcp(_) => false,
+            &Peer::Http(ref conn) => conn.state == HttpState::Closing && conn.outbox.is_empty(),
+        }
+    }
+
+    /// The readiness the connection is interested in.
+    pub fn events(&self) -> Ready {
+        if self.is_writing() {
+            Ready::readable() | Ready::writable()
+        } else {
+            Ready::readable()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Write};
+    use std::net::{self, TcpListener};
+    use std::thread;
+    use std::time::Duration;
+
+    use mio::net::TcpStream;
+
+    use serde_json;
+
+    use protocol::Encoding;
+
+    use super::{HttpConnection, HttpState, TcpConnection, MAX_FRAME, MAX_HEAD};
+
+    /// Returns a client socket and the accepted end of its connection.
+    fn connect() -> (net::TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (accepted, _) = listener.accept().unwrap();
+
+        (client, TcpStream::from_stream(accepted).unwrap())
+    }
+
+    /// Retries until the specified function returns a result.
+    fn poll<T, F: FnMut() -> Option<T>>(mut f: F) -> T {
+        for _ in 0..500 {
+            if let Some(result) = f() {
+                return result;
+            }
+
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        panic!("timed out");
+    }
+
+    fn read_messages(conn: &mut TcpConnection, count: usize) -> Vec<Vec<u8>> {
+        let mut messages = Vec::new();
+        poll(|| {
+            let (mut read, open) = conn.read().unwrap();
+            assert!(open);
+            messages.append(&mut read);
+
+            if messages.len() >= count {
+                Some(())
+            } else {
+                None
+            }
+        });
+
+        messages
+    }
+
+    #[test]
+    fn json_split_frames() {
+        let (mut client, socket) = connect();
+        let mut conn = TcpConnection::new(socket);
+
+        client.write_all(b"[{\"AdvanceInput\"").unwrap();
+        thread::sleep(Duration::from_millis(50));
+        assert!(conn.read().unwrap().0.is_empty());
+        assert_eq!(conn.encoding, Some(Encoding::Json));
+
+        client.write_all(b": [null, 1]}]\n\n[]\n").unwrap();
+        let messages = read_messages(&mut conn, 2);
+        assert_eq!(
+            messages,
+            vec![b"[{\"AdvanceInput\": [null, 1]}]\n".to_vec(), b"[]\n".to_vec()]
+        );
+    }
+
+    #[test]
+    fn cbor_split_frames() {
+        let (mut client, socket) = connect();
+        let mut conn = TcpConnection::new(socket);
+
+        client.write_all(&[0, 0]).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        assert!(conn.read().unwrap().0.is_empty());
+        assert_eq!(conn.encoding, Some(Encoding::Cbor));
+
+        client.write_all(&[0, 3, b'a']).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        assert!(conn.read().unwrap().0.is_empty());
+
+        client.write_all(&[b'b', b'c', 0, 0, 0, 1, b'd']).unwrap();
+        let messages = read_messages(&mut conn, 2);
+        assert_eq!(messages, vec![b"abc".to_vec(), b"d".to_vec()]);
+    }
+
+    #[test]
+    fn oversized_frames() {
+        let (mut client, socket) = connect();
+        let mut conn = TcpConnection::new(socket);
+
+        // the connection is dropped before all input is written
+        let writer = thread::spawn(move || {
+            let _ = client.write_all(&vec![b'['; MAX_FRAME + 2]);
+        });
+
+        let err = poll(|| match conn.read() {
+            Ok((messages, open)) => {
+                assert!(messages.is_empty());
+                assert!(open);
+                None
+            }
+            Err(err) => Some(err),
+        });
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        drop(conn);
+        writer.join().unwrap();
+    }
+
+    /// Reads until the request is complete or answered immediately.
+    fn read_request(conn: &mut HttpConnection) -> Vec<Vec<u8>> {
+        poll(|| {
+            let (messages, _) = conn.read().unwrap();
+
+            if !messages.is_empty() || conn.state != HttpState::Reading {
+                Some(messages)
+            } else {
+                None
+            }
+        })
+    }
+
+    fn assert_status(conn: &HttpConnection, status: &str) {
+        assert_eq!(conn.state, HttpState::Closing);
+        assert!(conn.outbox.starts_with(format!("HTTP/1.1 {}\r\n", status).as_bytes()));
+    }
+
+    #[test]
+    fn bad_content_length() {
+        let (mut client, socket) = connect();
+        let mut conn = HttpConnection::new(socket);
+
+        client
+            .write_all(b"POST /transact HTTP/1.1\r\nContent-Length: -1\r\n\r\n")
+            .unwrap();
+        assert!(read_request(&mut conn).is_empty());
+        assert_status(&conn, "400 Bad Request");
+    }
+
+    #[test]
+    fn oversized_requests() {
+        let (mut client, socket) = connect();
+        let mut conn = HttpConnection::new(socket);
+
+        let head = format!("POST /transact HTTP/1.1\r\nContent-Length: {}\r\n\r\n", MAX_FRAME + 1);
+        client.write_all(head.as_bytes()).unwrap();
+        assert!(read_request(&mut conn).is_empty());
+        assert_status(&conn, "413 Payload Too Large");
+
+        let (mut client, socket) = connect();
+        let mut conn = HttpConnection::new(socket);
+
+        let writer = thread::spawn(move || {
+            let _ = client.write_all(&vec![b'a'; MAX_HEAD + 1]);
+        });
+
+        assert!(read_request(&mut conn).is_empty());
+        assert_status(&conn, "413 Payload Too Large");
+
+        drop(conn);
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn malformed_bodies() {
+        for body in &[
+            "{\"tx_data\": 1}",
+            "{\"tx\": null, \"tx_data\": []}, {\"Register\": {}}",
+            "[]",
+        ] {
+            let (mut client, socket) = connect();
+            let mut conn = HttpConnection::new(socket);
+
+            let request = format!("POST /transact HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}", body.len(), body);
+            client.write_all(request.as_bytes()).unwrap();
+            assert!(read_request(&mut conn).is_empty());
+            assert_status(&conn, "400 Bad Request");
+        }
+    }
+
+    #[test]
+    fn transact() {
+        let (mut client, socket) = connect();
+        let mut conn = HttpConnection::new(socket);
+
+        let body = "{\"tx\": null, \"tx_data\": []}";
+        let request = format!(
+            "POST /transact HTTP/1.1\r\nAuthorization: Bearer s3cr3t\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        client.write_all(request.as_bytes()).unwrap();
+
+        let messages = read_request(&mut conn);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(conn.state, HttpState::Awaiting);
+        assert_eq!(conn.credential, Some("s3cr3t".to_string()));
+
+        let cmd: serde_json::Value = serde_json::from_slice(&messages[0]).unwrap();
+        let expected: serde_json::Value =
+            serde_json::from_str("[{\"Transact\": {\"tx\": null, \"tx_data\": []}}]").unwrap();
+        assert_eq!(cmd, expected);
+
+        match Encoding::Json.decode(&messages[0]) {
+            Ok(_) => {}
+            Err(err) => panic!("invalid command {}", err),
+        }
+    }
+}
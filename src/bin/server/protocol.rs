@@ -0,0 +1,223 @@
+//! Messages exchanged with clients.
+
+use serde_cbor;
+use serde_json;
+
+use declarative_dataflow::server::Request;
+use declarative_dataflow::Value;
+
+/// (tuple, diff) as sent back to external clients.
+pub type Output = (Vec<Value>, isize, u64);
+
+/// Notices sent back to external clients, tagged by their kind.
+#[derive(Serialize, Clone, Debug)]
+pub enum Notice {
+    /// The consolidated state of a relation at the time a client
+    /// expressed interest in it, answering the request with the
+    /// specified id.
+    Snapshot(String, Vec<Output>, Option<u64>),
+    /// All results of a relation up to and including the specified
+    /// transaction have been sent.
+    Frontier(String, u64),
+    /// The transaction inputs of a `Transact` were introduced at,
+    /// answering the request with the specified id.
+    Transacted(u64, Option<u64>),
+    /// The request with the specified id has been processed.
+    Ack(u64),
+    /// The request with the specified id could not be processed.
+    Error(String, Option<u64>),
+    /// Results of the specified relations had to be dropped, because
+    /// the client fell behind or the relations were replaced. It
+    /// should express interest anew. Lookups of replaced queries are
+    /// answered anew without further requests.
+    Resync(Vec<String>),
+    /// The connection has been authenticated as the specified
+    /// principal.
+    Authenticated(String),
+    /// The request with the specified id was denied, because the
+    /// client could not be authenticated or is not authorized to
+    /// issue it.
+    Unauthorized(String, Option<u64>),
+}
+
+/// Messages sent back to external clients.
+#[derive(Serialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum Response {
+    /// A batch of results, sent as a `[name, outputs]` pair.
+    Results(String, Vec<Output>),
+    /// A tagged notice.
+    Notice(Notice),
+}
+
+impl Response {
+    /// The number of queued results this response accounts for.
+    pub fn size(&self) -> usize {
+        match self {
+            &Response::Results(_, ref outputs) => outputs.len(),
+            &Response::Notice(_) => 1,
+        }
+    }
+}
+
+/// Messages received from clients.
+#[derive(Debug)]
+pub enum Message {
+    /// A handshake presenting a credential for the connection.
+    Authenticate(String),
+    /// Requests, optionally tagged with an id that is echoed in
+    /// replies to them.
+    Requests(Option<u64>, Vec<Request>),
+}
+
+/// Messages as sent by clients.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum Envelope {
+    /// A handshake, e.g. `{"authenticate": "s3cr3t"}`.
+    Handshake {
+        /// A credential identifying the client
+        authenticate: String,
+    },
+    /// Requests tagged with an id.
+    Tagged {
+        /// A client-chosen id
+        id: u64,
+        /// Requests sharing the id
+        requests: Vec<Request>,
+    },
+    /// Untagged requests.
+    Untagged(Vec<Request>),
+}
+
+impl Envelope {
+    fn open(self) -> Message {
+        match self {
+            Envelope::Handshake { authenticate } => Message::Authenticate(authenticate),
+            Envelope::Tagged { id, requests } => Message::Requests(Some(id), requests),
+            Envelope::Untagged(requests) => Message::Requests(None, requests),
+        }
+    }
+}
+
+/// Wire encodings of requests and responses, chosen per connection.
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Abomonation, Serialize, Deserialize, Debug)]
+pub enum Encoding {
+    /// JSON text
+    Json,
+    /// Binary CBOR
+    Cbor,
+}
+
+impl Encoding {
+    /// Decodes a handshake or a sequence of requests.
+    pub fn decode(&self, cmd: &[u8]) -> Result<Message, String> {
+        let envelope: Envelope = match self {
+            &Encoding::Json => serde_json::from_slice(cmd).map_err(|err| err.to_string())?,
+            &Encoding::Cbor => serde_cbor::from_slice(cmd).map_err(|err| err.to_string())?,
+        };
+
+        Ok(envelope.open())
+    }
+
+    /// Encodes a response.
+    pub fn encode(&self, response: &Response) -> Vec<u8> {
+        match self {
+            &Encoding::Json => serde_json::to_vec(response).expect("failed to serialize outputs"),
+            &Encoding::Cbor => serde_cbor::to_vec(response).expect("failed to serialize outputs"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use declarative_dataflow::server::Request;
+    use declarative_dataflow::Value;
+
+    use super::{Encoding, Message, Notice, Response};
+
+    const UUID: [u8; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+
+    #[test]
+    fn uuids_as_byte_strings() {
+        let response = Response::Results("q".to_string(), vec![(vec![Value::Uuid(UUID)], 1, 0)]);
+
+        let cbor = Encoding::Cbor.encode(&response);
+        let mut byte_string = vec![0x50];
+        byte_string.extend_from_slice(&UUID);
+        assert!(cbor.windows(17).any(|window| window == &byte_string[..]));
+
+        let json = Encoding::Json.encode(&response);
+        assert!(String::from_utf8(json).unwrap().contains("[0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15]"));
+    }
+
+    #[test]
+    fn cbor_decode() {
+        // [{"Datom": [1, "a", {"Uuid": h'000102...0f'}, 1, 0]}]
+        let mut cmd = vec![0x81, 0xa1, 0x65];
+        cmd.extend_from_slice(b"Datom");
+        cmd.extend_from_slice(&[0x85, 0x01, 0x61, b'a', 0xa1, 0x64]);
+        cmd.extend_from_slice(b"Uuid");
+        cmd.push(0x50);
+        cmd.extend_from_slice(&UUID);
+        cmd.extend_from_slice(&[0x01, 0x00]);
+
+        match Encoding::Cbor.decode(&cmd) {
+            Ok(Message::Requests(None, ref requests)) => match &requests[..] {
+                &[Request::Datom(1, ref a, Value::Uuid(uuid), 1, 0)] => {
+                    assert_eq!(a, "a");
+                    assert_eq!(uuid, UUID);
+                }
+                other => panic!("unexpected requests {:?}", other),
+            },
+            other => panic!("unexpected message {:?}", other),
+        }
+
+        let json = br#"[{"Datom": [1, "a", {"Uuid": [0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15]}, 1, 0]}]"#;
+        match Encoding::Json.decode(json) {
+            Ok(Message::Requests(None, ref requests)) => match &requests[..] {
+                &[Request::Datom(_, _, Value::Uuid(uuid), _, _)] => assert_eq!(uuid, UUID),
+                other => panic!("unexpected requests {:?}", other),
+            },
+            other => panic!("unexpected message {:?}", other),
+        }
+
+        assert!(Encoding::Json.decode(br#"[{"Datom": [1, "a", {"Uuid": [0, 1]}, 1, 0]}]"#).is_err());
+    }
+
+    #[test]
+    fn request_ids() {
+        let tagged = br#"{"id": 7, "requests": [{"AdvanceInput": [null, 1]}]}"#;
+        match Encoding::Json.decode(tagged) {
+            Ok(Message::Requests(Some(7), ref requests)) => match &requests[..] {
+                &[Request::AdvanceInput(None, 1)] => {}
+                other => panic!("unexpected requests {:?}", other),
+            },
+            other => panic!("unexpected message {:?}", other),
+        }
+
+        // {"id": 7, "requests": []}
+        let mut cmd = vec![0xa2, 0x62];
+        cmd.extend_from_slice(b"id");
+        cmd.extend_from_slice(&[0x07, 0x68]);
+        cmd.extend_from_slice(b"requests");
+        cmd.push(0x80);
+        match Encoding::Cbor.decode(&cmd) {
+            Ok(Message::Requests(Some(7), ref requests)) => assert!(requests.is_empty()),
+            other => panic!("unexpected message {:?}", other),
+        }
+
+        match Encoding::Json.decode(br#"[{"AdvanceInput": [null, 1]}]"#) {
+            Ok(Message::Requests(None, ref requests)) => assert_eq!(requests.len(), 1),
+            other => panic!("unexpected message {:?}", other),
+        }
+
+        match Encoding::Json.decode(br#"{"authenticate": "s3cr3t"}"#) {
+            Ok(Message::Authenticate(ref credential)) => assert_eq!(credential, "s3cr3t"),
+            other => panic!("unexpected message {:?}", other),
+        }
+
+        let json = Encoding::Json.encode(&Response::Notice(Notice::Ack(7)));
+        assert_eq!(json, br#"{"Ack":7}"#.to_vec());
+    }
+}
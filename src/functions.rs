@@ -0,0 +1,114 @@
+//! Scalar functions.
+//!
+//! Programs embedding the library may register named functions with
+//! the server, which can then be referenced from `Transform` plans
+//! via `Function::UDF`. Arguments are checked against the declared
+//! signature when a plan is registered (as far as they are constant)
+//! and before each application. Built-in as well as user-defined
+//! functions report failures as `FunctionError`s.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use {Value, ValueType};
+
+/// A map from names to registered scalar functions.
+pub type FunctionRegistry = HashMap<String, ScalarFunction>;
+
+/// Reasons for which a function can not be applied.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FunctionError {
+    /// The function was given the wrong number of arguments.
+    Arity(usize),
+    /// The argument at the given position is of the wrong type.
+    Type(usize, Value),
+    /// The argument at the given position is of the right type, but
+    /// not admissible.
+    Invalid(usize, String),
+    /// The result can not be represented.
+    Overflow,
+    /// A numeric division by zero.
+    DivisionByZero,
+    /// Any other failure, e.g. raised by a user-defined function.
+    Other(String),
+}
+
+impl fmt::Display for FunctionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &FunctionError::Arity(n) => write!(f, "Unexpected number of arguments ({})", n),
+            &FunctionError::Type(position, ref value) => {
+                write!(f, "Unexpected type at position {}: {:?}", position, value)
+            }
+            &FunctionError::Invalid(position, ref msg) => {
+                write!(f, "Invalid argument at position {}: {}", position, msg)
+            }
+            &FunctionError::Overflow => write!(f, "Result can not be represented"),
+            &FunctionError::DivisionByZero => write!(f, "Division by zero"),
+            &FunctionError::Other(ref msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+/// The result of applying a scalar function.
+pub type FunctionResult = Result<Value, FunctionError>;
+
+/// A named Rust closure over a fixed number of arguments.
+#[derive(Clone)]
+pub struct ScalarFunction {
+    arguments: Vec<Option<ValueType>>,
+    function: Arc<dyn Fn(&[Value]) -> FunctionResult + Send + Sync>,
+}
+
+impl ScalarFunction {
+    /// Creates a new scalar function. Its arity is the number of
+    /// declared arguments, each of which is either required to be of
+    /// a specific type, or `None` to accept any value.
+    pub fn new<F>(arguments: Vec<Option<ValueType>>, function: F) -> Self
+    where
+        F: Fn(&[Value]) -> FunctionResult + Send + Sync + 'static,
+    {
+        ScalarFunction {
+            arguments,
+            function: Arc::new(function),
+        }
+    }
+
+    /// The number of arguments expected by this function.
+    pub fn arity(&self) -> usize {
+        self.arguments.len()
+    }
+
+    /// Checks whether a value is admissible at the specified argument
+    /// position.
+    pub fn check_argument(&self, position: usize, value: &Value) -> Result<(), FunctionError> {
+        match self.arguments.get(position) {
+            None => Err(FunctionError::Arity(position + 1)),
+            Some(&None) => Ok(()),
+            Some(&Some(expected)) => match ValueType::of(value) {
+                Some(actual) if actual == expected => Ok(()),
+                _ => Err(FunctionError::Type(position, value.clone())),
+            },
+        }
+    }
+
+    /// Applies the function after checking its arguments.
+    pub fn apply(&self, arguments: &[Value]) -> FunctionResult {
+        if arguments.len() != self.arity() {
+            return Err(FunctionError::Arity(arguments.len()));
+        }
+
+        for (position, value) in arguments.iter().enumerate() {
+            self.check_argument(position, value)?;
+        }
+
+        (self.function)(arguments)
+    }
+}
+
+impl fmt::Debug for ScalarFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ScalarFunction({:?})", self.arguments)
+    }
+}
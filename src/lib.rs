@@ -13,32 +13,52 @@ extern crate timely;
 // extern crate abomonation_derive;
 // extern crate abomonation;
 
+extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+#[macro_use]
+extern crate log;
 
+extern crate chrono;
+extern crate chrono_tz;
 extern crate num_rational;
+extern crate num_traits;
+extern crate ordered_float;
+extern crate regex;
+extern crate rust_decimal;
 
+use std::collections::hash_map::DefaultHasher;
 use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
 
 use timely::communication::Allocate;
+use timely::dataflow::channels::pact::Pipeline;
 use timely::dataflow::scopes::child::{Child, Iterative};
+use timely::dataflow::operators::{Filter, Map, Operator, ToStream};
 use timely::dataflow::*;
 use timely::order::Product;
 use timely::worker::Worker;
 
 use differential_dataflow::collection::Collection;
+use differential_dataflow::AsCollection;
 use differential_dataflow::operators::arrange::{ArrangeBySelf, TraceAgent};
 use differential_dataflow::operators::group::Threshold;
+use differential_dataflow::operators::{Count, Join as JoinMap};
 use differential_dataflow::operators::iterate::Variable;
 use differential_dataflow::trace::implementations::ord::{OrdKeySpine, OrdValSpine};
 
-pub use num_rational::Rational32;
+pub use num_rational::{Rational32, Rational64};
+pub use ordered_float::OrderedFloat;
+pub use rust_decimal::Decimal;
 
+pub mod functions;
+pub mod numeric;
 pub mod plan;
 pub use plan::{Implementable, Plan};
 
 pub mod server;
 pub mod sources;
+pub mod time;
 
 //
 // TYPES
@@ -59,8 +79,13 @@ pub type Attribute = String; // u32
 ///
 /// This enum captures the currently supported data types, and is the least common denominator
 /// for the types of records moved around.
+///
+/// `Nil` marks the absence of a value (e.g. an empty field in a
+/// source). It is ordered before all other values.
 #[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
 pub enum Value {
+    /// The absence of a value
+    Nil,
     /// An attribute identifier
     Attribute(Attribute),
     /// A string
@@ -71,12 +96,117 @@ pub enum Value {
     Number(i64),
     /// A 32 bit rational
     Rational32(Rational32),
+    /// A 64 bit rational
+    Rational64(Rational64),
+    /// A 64 bit floating point number, totally ordered
+    Real(OrderedFloat<f64>),
+    /// A fixed-precision decimal number
+    Decimal(Decimal),
     /// An entity identifier
     Eid(Entity),
     /// Milliseconds since midnight, January 1, 1970 UTC
     Instant(u64),
-    /// A 16 byte unique identifier.
-    Uuid([u8; 16]),
+    /// A 16 byte unique identifier, serialized as a byte string.
+    Uuid(#[serde(with = "uuid_bytes")] [u8; 16]),
+}
+
+/// Serializes UUIDs as byte strings, s.t. binary encodings represent
+/// them compactly. Sequences of 16 bytes are accepted as well, e.g.
+/// from JSON, which has no byte strings.
+mod uuid_bytes {
+    use std::fmt;
+
+    use serde::de::{self, SeqAccess, Visitor};
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8; 16], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(bytes)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; 16], D::Error> {
+        deserializer.deserialize_bytes(UuidVisitor)
+    }
+
+    struct UuidVisitor;
+
+    impl<'de> Visitor<'de> for UuidVisitor {
+        type Value = [u8; 16];
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("16 bytes")
+        }
+
+        fn visit_bytes<E: de::Error>(self, value: &[u8]) -> Result<[u8; 16], E> {
+            if value.len() != 16 {
+                return Err(E::invalid_length(value.len(), &self));
+            }
+
+            let mut bytes = [0; 16];
+            bytes.copy_from_slice(value);
+            Ok(bytes)
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<[u8; 16], A::Error> {
+            let mut bytes = [0; 16];
+            for (i, byte) in bytes.iter_mut().enumerate() {
+                *byte = seq.next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(i, &self))?;
+            }
+
+            if seq.next_element::<u8>()?.is_some() {
+                return Err(de::Error::invalid_length(17, &self));
+            }
+
+            Ok(bytes)
+        }
+    }
+}
+
+/// The types of non-nil data values.
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum ValueType {
+    /// An attribute identifier
+    Attribute,
+    /// A string
+    String,
+    /// A boolean
+    Bool,
+    /// A 64 bit signed integer
+    Number,
+    /// A 32 bit rational
+    Rational32,
+    /// A 64 bit rational
+    Rational64,
+    /// A 64 bit floating point number
+    Real,
+    /// A fixed-precision decimal number
+    Decimal,
+    /// An entity identifier
+    Eid,
+    /// Milliseconds since midnight, January 1, 1970 UTC
+    Instant,
+    /// A 16 byte unique identifier
+    Uuid,
+}
+
+impl ValueType {
+    /// Returns the type of a value, or `None` for `Value::Nil`.
+    pub fn of(value: &Value) -> Option<ValueType> {
+        match value {
+            &Value::Nil => None,
+            &Value::Attribute(_) => Some(ValueType::Attribute),
+            &Value::String(_) => Some(ValueType::String),
+            &Value::Bool(_) => Some(ValueType::Bool),
+            &Value::Number(_) => Some(ValueType::Number),
+            &Value::Rational32(_) => Some(ValueType::Rational32),
+            &Value::Rational64(_) => Some(ValueType::Rational64),
+            &Value::Real(_) => Some(ValueType::Real),
+            &Value::Decimal(_) => Some(ValueType::Decimal),
+            &Value::Eid(_) => Some(ValueType::Eid),
+            &Value::Instant(_) => Some(ValueType::Instant),
+            &Value::Uuid(_) => Some(ValueType::Uuid),
+        }
+    }
 }
 
 /// An entity, attribute, value triple.
@@ -135,6 +265,18 @@ pub struct Rule {
 
 type Var = u32;
 
+/// Bounds on the evaluation of rules, guarding against rule sets
+/// that never converge. A rule exceeding either bound fails: all of
+/// its tuples are retracted for good, and the failure is reported.
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct Limits {
+    /// Maximum number of iterations of a rule per timestamp.
+    pub max_iterations: Option<u64>,
+    /// Maximum number of distinct tuples derived by a rule, across
+    /// all workers.
+    pub max_tuples: Option<isize>,
+}
+
 //
 // RELATIONS
 //
@@ -230,31 +372,104 @@ impl<'a, G: Scope> Relation<'a, G> for SimpleRelation<'a, G> {
 // QUERY PLAN IMPLEMENTATION
 //
 
+/// Fails a rule once its distinct tuples exceed the limits, returning
+/// the tuples to continue with and a description of each failure.
+/// Limits are checked against consolidated, global counts, s.t. all
+/// workers agree on the time a rule fails at.
+fn bounded<S: Scope<Timestamp = Product<u64, u64>>>(
+    name: &str,
+    tuples: &Collection<S, Vec<Value>, isize>,
+    limits: &Limits,
+) -> (Collection<S, Vec<Value>, isize>, Collection<S, String, isize>) {
+    let mut violations: Vec<Collection<S, String, isize>> = Vec::new();
+
+    if let Some(max) = limits.max_iterations {
+        let msg = format!("Rule {} exceeded {} iterations", name, max);
+        violations.push(
+            tuples
+                .inner
+                .filter(move |&(_, ref time, _)| time.inner > max)
+                .map(move |(_, time, _)| (msg.clone(), time, 1))
+                .as_collection(),
+        );
+    }
+
+    if let Some(max) = limits.max_tuples {
+        let msg = format!("Rule {} exceeded {} tuples", name, max);
+
+        // Tuples are counted on each worker first, s.t. only the
+        // counts have to be exchanged.
+        let counts = tuples
+            .inner
+            .unary(Pipeline, "CountTuples", |_capability, _info| {
+                move |input, output| {
+                    input.for_each(|time, data| {
+                        let count: isize = data.iter().map(|&(_, _, diff)| diff).sum();
+                        output.session(&time).give(((), time.time().clone(), count));
+                    });
+                }
+            })
+            .as_collection()
+            .count();
+
+        violations.push(
+            counts
+                .filter(move |&((), count)| count > max)
+                .inner
+                .map(move |(_, time, _)| (msg.clone(), time, 1))
+                .as_collection(),
+        );
+    }
+
+    // Violations are never retracted, s.t. a failed rule stays failed.
+    let mut failures = violations.remove(0);
+    for violation in violations.iter() {
+        failures = failures.concat(violation);
+    }
+    let failures = failures.distinct();
+
+    // Failures are spread across buckets, s.t. tuples are gated on
+    // all workers rather than on a single one.
+    let buckets = 16 * tuples.scope().peers() as u64;
+    let failed = failures.map(|_| ()).distinct().flat_map(move |()| 0..buckets);
+
+    let gated = tuples
+        .map(move |tuple| {
+            let mut hasher = DefaultHasher::new();
+            tuple.hash(&mut hasher);
+            (hasher.finish() % buckets, tuple)
+        })
+        .antijoin(&failed)
+        .map(|(_, tuple)| tuple);
+
+    (gated, failures)
+}
+
 /// Takes a query plan and turns it into a differential dataflow. The
-/// dataflow is extended to feed output tuples to JS clients. A probe
-/// on the dataflow is returned.
-pub fn implement<A: Allocate>(
+/// dataflow is extended to feed output tuples to JS clients. Returns
+/// the published relations, along with the failures of rules
+/// exceeding the limits.
+pub fn implement<'a, A: Allocate>(
     mut rules: Vec<Rule>,
     publish: Vec<String>,
-    scope: &mut Child<Worker<A>, u64>,
+    limits: &Limits,
+    scope: &mut Child<'a, Worker<A>, u64>,
     global_arrangements: &mut QueryMap<isize>,
     _probe: &mut ProbeHandle<u64>,
-) -> HashMap<String, RelationHandle> {
+) -> (HashMap<String, RelationHandle>, Collection<Child<'a, Worker<A>, u64>, String, isize>) {
     scope.iterative::<u64, _, _>(|nested| {
         let mut local_arrangements = RelationMap::new();
         let mut result_map = QueryMap::new();
 
-        // Step 0: Canonicalize, check uniqueness of bindings.
+        // Step 0: Canonicalize. Multiple rules of the same name
+        // define the union of their results.
         rules.sort_by(|x, y| x.name.cmp(&y.name));
-        for index in 1..rules.len() - 1 {
-            if rules[index].name == rules[index - 1].name {
-                panic!("Duplicate rule definitions for rule {}", rules[index].name);
-            }
-        }
 
         // Step 1: Create new recursive variables for each rule.
         for rule in rules.iter() {
-            local_arrangements.insert(rule.name.clone(), Variable::new(nested, Product::new(0, 1)));
+            if !local_arrangements.contains_key(&rule.name) {
+                local_arrangements.insert(rule.name.clone(), Variable::new(nested, Product::new(0, 1)));
+            }
         }
 
         // Step 2: Create public arrangements for published relations.
@@ -283,15 +498,43 @@ pub fn implement<A: Allocate>(
         }
 
         // Step 4: complete named relations in a specific order (sorted by name).
+        let mut definitions: Vec<(&String, Collection<_, Vec<Value>, isize>)> = Vec::new();
         for (rule, execution) in rules.iter().zip(executions.drain(..)) {
+            let tuples = execution.tuples();
+
+            let is_repeated = definitions
+                .last()
+                .map(|&(name, _)| *name == rule.name)
+                .unwrap_or(false);
+
+            if is_repeated {
+                let (name, union) = definitions.pop().unwrap();
+                definitions.push((name, union.concat(&tuples)));
+            } else {
+                definitions.push((&rule.name, tuples));
+            }
+        }
+
+        let mut parent = nested.parent.clone();
+        let mut failures = Vec::new().to_stream(&mut parent).as_collection();
+
+        for (name, tuples) in definitions.into_iter() {
+            let mut tuples = tuples.distinct();
+
+            if limits.max_iterations.is_some() || limits.max_tuples.is_some() {
+                let (gated, failed) = bounded(name, &tuples, limits);
+                tuples = gated;
+                failures = failures.concat(&failed.leave());
+            }
+
             local_arrangements
-                .remove(&rule.name)
+                .remove(name)
                 .expect("Rule should be in local_arrangements, but isn't")
-                .set(&execution.tuples().distinct());
+                .set(&tuples);
         }
 
         println!("Done");
-        result_map
+        (result_map, failures)
     })
 }
 
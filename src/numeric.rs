@@ -0,0 +1,296 @@
+//! Arithmetic on numeric values.
+//!
+//! Operands of different numeric types are promoted to the wider of
+//! both types before applying an operation, where `Number` <
+//! `Rational32` < `Rational64` < `Decimal` < `Real`. All operations
+//! return `None` if either operand is not numeric, or if the result
+//! can not be represented (e.g. on overflow or division by zero).
+
+use std::cmp::Ordering;
+
+use num_traits::{CheckedAdd, CheckedDiv, CheckedMul, CheckedSub, ToPrimitive, Zero};
+
+use {Decimal, OrderedFloat, Rational32, Rational64, Value};
+
+/// Numeric operands, promoted to a common type.
+enum Num {
+    Number(i64),
+    Rational(Rational32),
+    Rational64(Rational64),
+    Decimal(Decimal),
+    Real(f64),
+}
+
+impl Num {
+    fn from_value(value: &Value) -> Option<Num> {
+        match value {
+            &Value::Number(x) => Some(Num::Number(x)),
+            &Value::Rational32(x) => Some(Num::Rational(x)),
+            &Value::Rational64(x) => Some(Num::Rational64(x)),
+            &Value::Decimal(x) => Some(Num::Decimal(x)),
+            &Value::Real(x) => Some(Num::Real(x.into_inner())),
+            _ => None,
+        }
+    }
+
+    fn rank(&self) -> usize {
+        match self {
+            &Num::Number(_) => 0,
+            &Num::Rational(_) => 1,
+            &Num::Rational64(_) => 2,
+            &Num::Decimal(_) => 3,
+            &Num::Real(_) => 4,
+        }
+    }
+
+    fn to_rank(self, rank: usize) -> Option<Num> {
+        match (self, rank) {
+            (Num::Number(x), 1) => {
+                if fits_i32(x) {
+                    Some(Num::Rational(Rational32::from_integer(x as i32)))
+                } else {
+                    None
+                }
+            }
+            (Num::Number(x), 2) => Some(Num::Rational64(Rational64::from_integer(x))),
+            (Num::Number(x), 3) => Some(Num::Decimal(Decimal::from(x))),
+            (Num::Number(x), 4) => Some(Num::Real(x as f64)),
+            (Num::Rational(x), 2) => Some(Num::Rational64(Rational64::new_raw(
+                *x.numer() as i64,
+                *x.denom() as i64,
+            ))),
+            (Num::Rational(x), 3) => Decimal::from(*x.numer())
+                .checked_div(Decimal::from(*x.denom()))
+                .map(Num::Decimal),
+            (Num::Rational(x), 4) => Some(Num::Real(*x.numer() as f64 / *x.denom() as f64)),
+            (Num::Rational64(x), 3) => Decimal::from(*x.numer())
+                .checked_div(Decimal::from(*x.denom()))
+                .map(Num::Decimal),
+            (Num::Rational64(x), 4) => Some(Num::Real(*x.numer() as f64 / *x.denom() as f64)),
+            (Num::Decimal(x), 4) => x.to_f64().map(Num::Real),
+            (num, rank) => {
+                if num.rank() == rank {
+                    Some(num)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+fn fits_i32(x: i64) -> bool {
+    x >= i32::min_value() as i64 && x <= i32::max_value() as i64
+}
+
+/// Converts both operands to their common numeric type.
+fn promote(a: &Value, b: &Value) -> Option<(Num, Num)> {
+    let a = Num::from_value(a)?;
+    let b = Num::from_value(b)?;
+    let rank = a.rank().max(b.rank());
+
+    if rank == 1 {
+        // Numbers exceeding 32 bits are promoted to 64 bit rationals.
+        let wide = match (&a, &b) {
+            (&Num::Number(x), _) | (_, &Num::Number(x)) => !fits_i32(x),
+            _ => false,
+        };
+
+        if wide {
+            return Some((a.to_rank(2)?, b.to_rank(2)?));
+        }
+    }
+
+    Some((a.to_rank(rank)?, b.to_rank(rank)?))
+}
+
+fn binary<FN, FR, FW, FD, FF>(
+    a: &Value,
+    b: &Value,
+    number_op: FN,
+    rational_op: FR,
+    rational64_op: FW,
+    decimal_op: FD,
+    real_op: FF,
+) -> Option<Value>
+where
+    FN: Fn(i64, i64) -> Option<i64>,
+    FR: Fn(Rational32, Rational32) -> Option<Rational32>,
+    FW: Fn(Rational64, Rational64) -> Option<Rational64>,
+    FD: Fn(Decimal, Decimal) -> Option<Decimal>,
+    FF: Fn(f64, f64) -> Option<f64>,
+{
+    match promote(a, b)? {
+        (Num::Number(x), Num::Number(y)) => number_op(x, y).map(Value::Number),
+        (Num::Rational(x), Num::Rational(y)) => rational_op(x, y).map(Value::Rational32),
+        (Num::Rational64(x), Num::Rational64(y)) => rational64_op(x, y).map(Value::Rational64),
+        (Num::Decimal(x), Num::Decimal(y)) => decimal_op(x, y).map(Value::Decimal),
+        (Num::Real(x), Num::Real(y)) => real_op(x, y).map(|z| Value::Real(OrderedFloat(z))),
+        _ => None,
+    }
+}
+
+/// Returns true iff the value is of a numeric type.
+pub fn is_numeric(value: &Value) -> bool {
+    Num::from_value(value).is_some()
+}
+
+/// Adds two numeric values.
+pub fn add(a: &Value, b: &Value) -> Option<Value> {
+    binary(
+        a,
+        b,
+        |x, y| x.checked_add(y),
+        |x, y| x.checked_add(&y),
+        |x, y| x.checked_add(&y),
+        |x, y| x.checked_add(y),
+        |x, y| Some(x + y),
+    )
+}
+
+/// Subtracts the second numeric value from the first.
+pub fn sub(a: &Value, b: &Value) -> Option<Value> {
+    binary(
+        a,
+        b,
+        |x, y| x.checked_sub(y),
+        |x, y| x.checked_sub(&y),
+        |x, y| x.checked_sub(&y),
+        |x, y| x.checked_sub(y),
+        |x, y| Some(x - y),
+    )
+}
+
+/// Multiplies two numeric values.
+pub fn mul(a: &Value, b: &Value) -> Option<Value> {
+    binary(
+        a,
+        b,
+        |x, y| x.checked_mul(y),
+        |x, y| x.checked_mul(&y),
+        |x, y| x.checked_mul(&y),
+        |x, y| x.checked_mul(y),
+        |x, y| Some(x * y),
+    )
+}
+
+/// Divides the first numeric value by the second. Dividing two
+/// `Number`s yields an exact `Rational32`, or a `Rational64` if either
+/// operand does not fit into 32 bits.
+pub fn div(a: &Value, b: &Value) -> Option<Value> {
+    match (a, b) {
+        (&Value::Number(_), &Value::Number(0)) => None,
+        (&Value::Number(x), &Value::Number(y)) => {
+            if fits_i32(x) && fits_i32(y) {
+                Some(Value::Rational32(Rational32::new(x as i32, y as i32)))
+            } else {
+                Rational64::from_integer(x)
+                    .checked_div(&Rational64::from_integer(y))
+                    .map(Value::Rational64)
+            }
+        }
+        _ => binary(
+            a,
+            b,
+            |_, _| None,
+            |x, y| x.checked_div(&y),
+            |x, y| x.checked_div(&y),
+            |x, y| x.checked_div(y),
+            |x, y| if y == 0.0 { None } else { Some(x / y) },
+        ),
+    }
+}
+
+/// Returns the remainder of dividing the first numeric value by the
+/// second, carrying the sign of the first.
+pub fn rem(a: &Value, b: &Value) -> Option<Value> {
+    binary(
+        a,
+        b,
+        |x, y| x.checked_rem(y),
+        |x, y| if y.is_zero() { None } else { Some(x % y) },
+        |x, y| if y.is_zero() { None } else { Some(x % y) },
+        |x, y| x.checked_rem(y),
+        |x, y| if y == 0.0 { None } else { Some(x % y) },
+    )
+}
+
+/// Returns the absolute value of a numeric value.
+pub fn abs(value: &Value) -> Option<Value> {
+    match value {
+        &Value::Number(x) => x.checked_abs().map(Value::Number),
+        &Value::Rational32(x) => x
+            .numer()
+            .checked_abs()
+            .map(|numer| Value::Rational32(Rational32::new_raw(numer, *x.denom()))),
+        &Value::Rational64(x) => x
+            .numer()
+            .checked_abs()
+            .map(|numer| Value::Rational64(Rational64::new_raw(numer, *x.denom()))),
+        &Value::Decimal(x) => Some(Value::Decimal(x.abs())),
+        &Value::Real(x) => Some(Value::Real(OrderedFloat(x.into_inner().abs()))),
+        _ => None,
+    }
+}
+
+/// Compares two numeric values by magnitude, irrespective of their
+/// types. Returns `None` for non-numeric operands and NaN.
+pub fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match promote(a, b)? {
+        (Num::Number(x), Num::Number(y)) => Some(x.cmp(&y)),
+        (Num::Rational(x), Num::Rational(y)) => Some(x.cmp(&y)),
+        (Num::Rational64(x), Num::Rational64(y)) => Some(x.cmp(&y)),
+        (Num::Decimal(x), Num::Decimal(y)) => Some(x.cmp(&y)),
+        (Num::Real(x), Num::Real(y)) => x.partial_cmp(&y),
+        _ => None,
+    }
+}
+
+/// Converts a numeric value into a float.
+pub fn to_f64(value: &Value) -> Option<f64> {
+    match Num::from_value(value)?.to_rank(4)? {
+        Num::Real(x) => Some(x),
+        _ => None,
+    }
+}
+
+/// Returns the exact ratio of two 128 bit integers as a `Rational64`
+/// if it fits, and as a `Decimal` otherwise.
+pub fn ratio_i128(numer: i128, denom: i128) -> Option<Value> {
+    if denom == 0 {
+        return None;
+    }
+
+    let divisor = gcd(numer, denom);
+    let (mut numer, mut denom) = (numer / divisor, denom / divisor);
+
+    if denom < 0 {
+        numer = numer.checked_neg()?;
+        denom = denom.checked_neg()?;
+    }
+
+    let fits = |x: i128| x >= i64::min_value() as i128 && x <= i64::max_value() as i128;
+
+    if fits(numer) && fits(denom) {
+        Some(Value::Rational64(Rational64::new_raw(numer as i64, denom as i64)))
+    } else {
+        let numer = numer.to_string().parse::<Decimal>().ok()?;
+        let denom = denom.to_string().parse::<Decimal>().ok()?;
+
+        numer.checked_div(denom).map(Value::Decimal)
+    }
+}
+
+fn gcd(mut a: i128, mut b: i128) -> i128 {
+    while b != 0 {
+        let t = a % b;
+        a = b;
+        b = t;
+    }
+
+    if a == 0 {
+        1
+    } else {
+        a.abs()
+    }
+}
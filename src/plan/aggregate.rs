@@ -1,18 +1,21 @@
 //! Aggregate expression plan.
 
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
 use timely::communication::Allocate;
+use timely::dataflow::operators::ToStream;
 use timely::dataflow::scopes::child::{Child, Iterative};
 use timely::worker::Worker;
 
 use differential_dataflow::difference::DiffPair;
-use differential_dataflow::operators::{Consolidate, Count, Group, Threshold};
-use differential_dataflow::operators::Join as JoinMap;
+use differential_dataflow::operators::{Count, Group, Threshold};
+use differential_dataflow::AsCollection;
 
+use numeric;
 use plan::Implementable;
 use Relation;
-use {QueryMap, RelationMap, SimpleRelation, Value, Var};
-
-use num_rational::{Ratio, Rational32};
+use {Decimal, OrderedFloat, QueryMap, RelationMap, SimpleRelation, Value, Var};
 
 /// Permitted aggregation function.
 #[derive(Deserialize, Clone, Debug)]
@@ -25,20 +28,266 @@ pub enum AggregationFn {
     MEDIAN,
     /// Count
     COUNT,
-    /// Sum
+    /// Sum of numeric values
     SUM,
-    /// Average
+    /// Average of numeric values
     AVG,
-    ///Varianve
+    /// Variance of numeric values
     VARIANCE,
-    // /// Standard deviation
-    // STDDEV,
+    /// Standard deviation of numeric values
+    STDDEV,
+    /// Number of distinct values, disregarding with-symbols
+    COUNT_DISTINCT,
+    /// Nearest-rank percentile, for a fraction between 0 and 1
+    PERCENTILE(f64),
+    /// The set of the k largest distinct values
+    TOP_K(usize),
+    /// The set of the k smallest distinct values
+    BOTTOM_K(usize),
+    /// The set of all distinct values
+    DISTINCT,
+    /// A deterministic sample of n distinct values, stable under
+    /// changes to the other values of a group
+    SAMPLE(usize),
+}
+
+impl AggregationFn {
+    /// The result of the aggregation on an empty input. Functions
+    /// without an identity produce `Value::Nil`.
+    fn identity(&self) -> Value {
+        match self {
+            &AggregationFn::COUNT | &AggregationFn::COUNT_DISTINCT | &AggregationFn::SUM => {
+                Value::Number(0)
+            }
+            _ => Value::Nil,
+        }
+    }
+
+    /// Applies the aggregation to the arguments of a group, given in
+    /// order with one entry per distinct combination of argument and
+    /// with-values. Single-valued functions produce exactly one
+    /// result, set-valued ones produce one result per member. COUNT,
+    /// SUM, and AVG are maintained incrementally instead.
+    fn apply(&self, values: &[&Value]) -> Vec<Value> {
+        match self {
+            &AggregationFn::MIN => vec![values[0].clone()],
+            &AggregationFn::MAX => vec![values[values.len() - 1].clone()],
+            &AggregationFn::MEDIAN => vec![values[values.len() / 2].clone()],
+            &AggregationFn::COUNT | &AggregationFn::SUM | &AggregationFn::AVG => {
+                unreachable!("{:?} is maintained incrementally.", self)
+            }
+            &AggregationFn::VARIANCE => vec![Moments::from_values(values, "VARIANCE").variance()],
+            &AggregationFn::STDDEV => vec![Moments::from_values(values, "STDDEV").stddev()],
+            &AggregationFn::COUNT_DISTINCT => {
+                vec![Value::Number(distinct_values(values).len() as i64)]
+            }
+            &AggregationFn::PERCENTILE(p) => {
+                let values = distinct_values(values);
+                let rank = (p * values.len() as f64).ceil() as usize;
+                let index = rank.max(1).min(values.len()) - 1;
+                vec![values[index].clone()]
+            }
+            &AggregationFn::TOP_K(k) => distinct_values(values)
+                .into_iter()
+                .rev()
+                .take(k)
+                .cloned()
+                .collect(),
+            &AggregationFn::BOTTOM_K(k) => distinct_values(values)
+                .into_iter()
+                .take(k)
+                .cloned()
+                .collect(),
+            &AggregationFn::DISTINCT => distinct_values(values).into_iter().cloned().collect(),
+            &AggregationFn::SAMPLE(n) => {
+                // Choosing the values with the smallest hashes keeps the
+                // sample stable as long as none of them is retracted.
+                let mut hashed: Vec<(u64, &Value)> = distinct_values(values)
+                    .into_iter()
+                    .map(|value| {
+                        let mut hasher = DefaultHasher::new();
+                        value.hash(&mut hasher);
+                        (hasher.finish(), value)
+                    })
+                    .collect();
+                hashed.sort();
+
+                hashed.into_iter().take(n).map(|(_hash, value)| value.clone()).collect()
+            }
+        }
+    }
+}
+
+/// Behaviour of aggregations on groups without inputs.
+#[derive(Deserialize, Clone, Debug)]
+pub enum EmptyGroups {
+    /// Groups without inputs produce no tuples. This is the default.
+    Retract,
+    /// Global aggregations (i.e. those without key symbols) produce a
+    /// single tuple of identity values whenever their input is empty,
+    /// including initially. Not supported for grouped aggregations,
+    /// as there is no key left to report once a group is empty.
+    Identity,
+}
+
+impl Default for EmptyGroups {
+    fn default() -> Self {
+        EmptyGroups::Retract
+    }
+}
+
+/// True iff the value is a `Number`, which can be summed as a
+/// difference.
+fn is_integer(value: &Value) -> bool {
+    match value {
+        &Value::Number(_) => true,
+        _ => false,
+    }
+}
+
+/// Returns the distinct aggregation arguments of a group, in order.
+fn distinct_values<'a>(values: &[&'a Value]) -> Vec<&'a Value> {
+    let mut distinct = values.to_vec();
+    distinct.dedup();
+    distinct
+}
+
+/// Returns the non-Nil arguments at the given offset of a group's
+/// tuples, distinct w.r.t. their with-values and in order.
+fn arguments_of<'a>(vals: &[(&'a Vec<Value>, isize)], value_offset: usize, with_length: usize) -> Vec<&'a Value> {
+    let mut arguments: Vec<(&Value, &[Value])> = vals
+        .iter()
+        .map(|&(tuple, _)| (&tuple[value_offset], &tuple[tuple.len() - with_length..]))
+        .filter(|&(value, _)| *value != Value::Nil)
+        .collect();
+    arguments.sort();
+    arguments.dedup();
+
+    arguments.into_iter().map(|(value, _)| value).collect()
+}
+
+/// An overflow-checked running sum. Sums over `Number`s are
+/// accumulated with 128 bits, other numeric types fall back to
+/// promoting arithmetic.
+enum Sum {
+    Integer(i128),
+    Value(Value),
+}
+
+impl Sum {
+    fn add(self, value: &Value) -> Option<Sum> {
+        match (self, value) {
+            (Sum::Integer(acc), &Value::Number(x)) => acc.checked_add(x as i128).map(Sum::Integer),
+            (acc, value) => numeric::add(&acc.into_value()?, value).map(Sum::Value),
+        }
+    }
+
+    fn add_square(self, value: &Value) -> Option<Sum> {
+        match (self, value) {
+            (Sum::Integer(acc), &Value::Number(x)) => (x as i128)
+                .checked_mul(x as i128)
+                .and_then(|square| acc.checked_add(square))
+                .map(Sum::Integer),
+            (acc, value) => acc.add(&numeric::mul(value, value)?),
+        }
+    }
+
+    fn into_value(self) -> Option<Value> {
+        match self {
+            Sum::Integer(acc) => {
+                if acc >= i64::min_value() as i128 && acc <= i64::max_value() as i128 {
+                    Some(Value::Number(acc as i64))
+                } else {
+                    acc.to_string().parse::<Decimal>().ok().map(Value::Decimal)
+                }
+            }
+            Sum::Value(acc) => Some(acc),
+        }
+    }
+}
+
+/// Count, sum, and sum of squares over the distinct values of a
+/// group. Panics if values are not numeric or on overflow.
+struct Moments {
+    name: &'static str,
+    count: i64,
+    sum: Sum,
+    sum_squares: Sum,
+}
+
+impl Moments {
+    fn from_values(values: &[&Value], name: &'static str) -> Moments {
+        let mut sum = Sum::Integer(0);
+        let mut sum_squares = Sum::Integer(0);
+
+        for value in values.iter() {
+            sum = sum.add(value).unwrap_or_else(|| {
+                panic!("{} can only be applied on numeric types and overflowed on {:?}.", name, value)
+            });
+            sum_squares = sum_squares.add_square(value).unwrap_or_else(|| {
+                panic!("{} can only be applied on numeric types and overflowed on {:?}.", name, value)
+            });
+        }
+
+        Moments {
+            name,
+            count: values.len() as i64,
+            sum,
+            sum_squares,
+        }
+    }
+
+    fn sum(self) -> Value {
+        let name = self.name;
+        self.sum
+            .into_value()
+            .unwrap_or_else(|| panic!("{} overflowed.", name))
+    }
+
+    fn variance(self) -> Value {
+        let Moments { name, count, sum, sum_squares } = self;
+        let variance = match (sum, sum_squares) {
+            (Sum::Integer(sum), Sum::Integer(sum_squares)) => {
+                // (n * sum(x^2) - sum(x)^2) / n^2
+                let count = count as i128;
+                count
+                    .checked_mul(sum_squares)
+                    .and_then(|x| sum.checked_mul(sum).and_then(|y| x.checked_sub(y)))
+                    .and_then(|numer| numeric::ratio_i128(numer, count.checked_mul(count)?))
+            }
+            (sum, sum_squares) => {
+                let count = Value::Number(count);
+                sum.into_value()
+                    .and_then(|sum| numeric::div(&sum, &count))
+                    .and_then(|mean| {
+                        let mean_square = numeric::mul(&mean, &mean)?;
+                        let sum_squares = sum_squares.into_value()?;
+                        numeric::sub(&numeric::div(&sum_squares, &count)?, &mean_square)
+                    })
+            }
+        };
+
+        variance.unwrap_or_else(|| panic!("{} overflowed.", name))
+    }
+
+    fn stddev(self) -> Value {
+        let name = self.name;
+        let stddev = numeric::to_f64(&self.variance())
+            .unwrap_or_else(|| panic!("{} could not be represented.", name))
+            .sqrt();
+
+        Value::Real(OrderedFloat(stddev))
+    }
 }
 
 /// [WIP]
 /// A plan stage applying the specified aggregation functions to
 /// bindings for the specified symbols.
-/// Given multiple aggregations we iterate and n-1 joins are applied to the results.
+/// COUNT, SUM, and AVG are maintained incrementally, all other
+/// aggregations are computed in a single pass over each group.
+/// Bindings to `Value::Nil` are ignored by all aggregation functions.
+/// Set-valued aggregations produce one tuple per value, thus combining
+/// them with other aggregations yields all combinations of results.
 #[derive(Deserialize, Clone, Debug)]
 pub struct Aggregate<P: Implementable> {
     /// TODO
@@ -53,6 +302,31 @@ pub struct Aggregate<P: Implementable> {
     pub aggregation_symbols: Vec<Var>,
     /// With symbols
     pub with_symbols: Vec<Var>,
+    /// Behaviour on groups without inputs
+    #[serde(default)]
+    pub empty_groups: EmptyGroups,
+}
+
+impl<P: Implementable> Aggregate<P> {
+    /// Checks the arguments of the aggregation functions, and that
+    /// identity values are only requested for global aggregations.
+    pub fn validate(&self) -> Result<(), String> {
+        for aggregation_fn in self.aggregation_fns.iter() {
+            if let &AggregationFn::PERCENTILE(p) = aggregation_fn {
+                if !(0.0..=1.0).contains(&p) {
+                    return Err(format!("PERCENTILE must be between 0 and 1, got {}", p));
+                }
+            }
+        }
+
+        if let EmptyGroups::Identity = self.empty_groups {
+            if !self.key_symbols.is_empty() {
+                return Err("Identity values for empty groups require an aggregation without keys".to_string());
+            }
+        }
+
+        Ok(())
+    }
 }
 
 impl<P: Implementable> Implementable for Aggregate<P> {
@@ -98,170 +372,292 @@ impl<P: Implementable> Implementable for Aggregate<P> {
             variables[output_index] = 0;
         }
 
-        let mut collections = Vec::new();
+        let with_length = self.with_symbols.len();
+
+        // COUNT, SUM, and AVG are maintained as differences over the
+        // distinct arguments at their value offsets (their terms). All
+        // other aggregations, as well as the sums of non-integer
+        // arguments (their partials), are computed together in a
+        // single pass over each group.
+        let mut term_offsets = Vec::new();
+        let mut partial_offsets = Vec::new();
+        let mut grouped = Vec::new();
+        let mut aggregations = Vec::new();
 
-        // We iterate over all aggregations and keep track of the
-        // resulting collections, s.t. they can be joined afterwards.
         for (i, aggregation_fn) in self.aggregation_fns.iter().enumerate() {
             let value_offset = value_offsets[i];
-            let with_length = self.with_symbols.len();
 
-            // Access the right value for the given iteration loop and extend possible with-values.
-            let prepare_unary = move |(key, tuple): (Vec<Value>, Vec<Value>)| {
-                let value = &tuple[value_offset];
-                let mut v = vec![value.clone()];
+            match aggregation_fn {
+                &AggregationFn::COUNT | &AggregationFn::SUM | &AggregationFn::AVG => {
+                    if !term_offsets.contains(&value_offset) {
+                        term_offsets.push(value_offset);
+                    }
+                    let term = term_offsets.iter().position(|&offset| offset == value_offset);
+
+                    let partial = match aggregation_fn {
+                        &AggregationFn::COUNT => None,
+                        _ => {
+                            if !partial_offsets.contains(&value_offset) {
+                                partial_offsets.push(value_offset);
+                            }
+                            partial_offsets.iter().position(|&offset| offset == value_offset)
+                        }
+                    };
 
-                // With-symbols are always the last elements in the value part of each tuple, given they are specified.
-                // We append these, s.t. we consolidate correctly.
-                if with_length > 0 {
-                    v.extend(tuple.iter().rev().take(with_length).cloned());
+                    aggregations.push((aggregation_fn.clone(), term, partial));
                 }
+                _ => {
+                    grouped.push((aggregation_fn.clone(), value_offset));
+                    aggregations.push((aggregation_fn.clone(), None, None));
+                }
+            }
+        }
+
+        // Each row starts with the partials at each partial offset (Nil
+        // if there are no non-integer arguments), followed by the
+        // results of the grouped aggregations.
+        let partial_count = partial_offsets.len();
+        let grouping = !grouped.is_empty();
+
+        let rows = if grouped.is_empty() && partial_offsets.is_empty() {
+            None
+        } else {
+            // Without grouped aggregations, only tuples holding
+            // non-integer arguments have to be grouped.
+            let filter_offsets = partial_offsets.clone();
 
-                (key, v)
+            let rows = tuples
+                .filter(move |(_key, tuple)| {
+                    grouping
+                        || filter_offsets
+                            .iter()
+                            .any(|&offset| tuple[offset] != Value::Nil && !is_integer(&tuple[offset]))
+                })
+                .group(move |_key, vals, output| {
+                    let mut partials = Vec::with_capacity(partial_offsets.len());
+
+                    for &offset in partial_offsets.iter() {
+                        let others: Vec<&Value> = arguments_of(vals, offset, with_length)
+                            .into_iter()
+                            .filter(|value| !is_integer(value))
+                            .collect();
+
+                        if others.is_empty() {
+                            partials.push(Value::Nil);
+                        } else {
+                            partials.push(Moments::from_values(&others, "SUM").sum());
+                        }
+                    }
+
+                    let mut rows = vec![partials];
+
+                    for &(ref aggregation_fn, value_offset) in grouped.iter() {
+                        let values = arguments_of(vals, value_offset, with_length);
+                        if values.is_empty() {
+                            return;
+                        }
+
+                        let results = aggregation_fn.apply(&values);
+
+                        // Set-valued aggregations yield all combinations
+                        // of results.
+                        rows = rows
+                            .iter()
+                            .flat_map(|row| {
+                                results.iter().map(move |result| {
+                                    let mut row = row.clone();
+                                    row.push(result.clone());
+                                    row
+                                })
+                            })
+                            .collect();
+                    }
+
+                    for row in rows.into_iter() {
+                        output.push((row, 1));
+                    }
+                });
+
+            Some(rows)
+        };
+
+        let aggregates = if term_offsets.is_empty() {
+            // Only grouped aggregations, whose results are in order.
+            rows.expect("aggregations are never empty")
+        } else {
+            let term_count = term_offsets.len();
+
+            // A single explode maintains, for each key and term offset,
+            // the sum of the integer arguments split into halves s.t.
+            // the accumulators can't overflow, the number of arguments,
+            // and the number of integer arguments. Arguments are
+            // distinct w.r.t. their with-values and Nil values do not
+            // take part in any aggregation.
+            let terms = tuples
+                .flat_map(move |(key, tuple)| {
+                    let with = tuple[tuple.len() - with_length..].to_vec();
+
+                    term_offsets
+                        .iter()
+                        .enumerate()
+                        .filter(|&(_, &offset)| tuple[offset] != Value::Nil)
+                        .map(|(term, &offset)| ((key.clone(), term), (tuple[offset].clone(), with.clone())))
+                        .collect::<Vec<_>>()
+                })
+                .distinct()
+                .explode(|((key, term), (value, _with))| {
+                    let (halves, integers) = match value {
+                        Value::Number(x) => (DiffPair::new((x >> 32) as isize, (x & 0xffff_ffff) as isize), 1),
+                        _ => (DiffPair::new(0, 0), 0),
+                    };
+
+                    Some(((key, term), DiffPair::new(halves, DiffPair::new(1 as isize, integers as isize))))
+                })
+                .count()
+                .map(|((key, term), diffs)| {
+                    let sum = ((diffs.element1.element1 as i128) << 32) + diffs.element1.element2 as i128;
+                    let sum = Sum::Integer(sum)
+                        .into_value()
+                        .unwrap_or_else(|| panic!("SUM overflowed."));
+
+                    let counts = vec![
+                        sum,
+                        Value::Number(diffs.element2.element1 as i64),
+                        Value::Number(diffs.element2.element2 as i64),
+                    ];
+
+                    (key, (Some(term), counts))
+                });
+
+            let parts = match rows {
+                None => terms,
+                Some(rows) => terms.concat(&rows.map(|(key, row)| (key, (None, row)))),
             };
 
-            match aggregation_fn {
-                AggregationFn::MIN =>  {
-                    let tuples = tuples
-                        .map(prepare_unary)
-                        .group(|_key, vals, output| {
-                            let min = &vals[0].0[0];
-                            output.push((min.clone(), 1));
-                        })
-                        .map(move |(key, min)| {
-                            (key, vec![min])
-                        });
-                    collections.push(tuples);
-                },
-                AggregationFn::MAX => {
-                    let tuples = tuples
-                        .map(prepare_unary)
-                        .group(|_key, vals, output| {
-                            let max = &vals[vals.len() - 1].0[0];
-                            output.push((max.clone(), 1));
-                        })
-                        .map(move |(key, max)| {
-                            (key, vec![max])
-                        });
-                    collections.push(tuples);
-                },
-                AggregationFn::MEDIAN =>  {
-                    let tuples = tuples
-                        .map(prepare_unary)
-                        .group(|_key, vals, output| {
-                            let median = &vals[vals.len() / 2].0[0];
-                            output.push((median.clone(), 1));
-                        })
-                        .map(move |(key, med)| {
-                            (key, vec![med])
-                        });
-                    collections.push(tuples);
-                },
-                AggregationFn::COUNT =>  {
-                    let tuples = tuples
-                        .map(prepare_unary)
-                        .group(|_key, input, output| output.push((input.len(), 1)))
-                        .map(move |(key, count)| {
-                            (key, vec![Value::Number(count as i64)])
-                        });
-                    collections.push(tuples);
-                },
-                AggregationFn::SUM =>  {
-                    let tuples = tuples
-                        .map(prepare_unary)
-                        .consolidate()
-                        .distinct()
-                        .explode(|(key, val)| {
-                            let v = match val[0] {
-                                Value::Number(num) => num,
-                                _ => panic!("SUM can only be applied on type Number."),
+            // There is one part per term and one per row of the shared
+            // group, thus combining them is cheap to recompute.
+            parts.group(move |_key, parts, output| {
+                let mut terms: Vec<Option<&Vec<Value>>> = vec![None; term_count];
+                let mut rows = Vec::new();
+
+                for &(&(term, ref values), _) in parts.iter() {
+                    match term {
+                        None => rows.push(values.clone()),
+                        Some(term) => terms[term] = Some(values),
+                    }
+                }
+
+                // Some aggregation has no arguments in this group.
+                if terms.iter().any(|term| term.is_none()) || (grouping && rows.is_empty()) {
+                    return;
+                }
+
+                if rows.is_empty() {
+                    rows.push(vec![Value::Nil; partial_count]);
+                }
+
+                for row in rows.into_iter() {
+                    let (partials, results) = row.split_at(partial_count);
+                    let mut results = results.iter();
+
+                    let vals: Vec<Value> = aggregations
+                        .iter()
+                        .map(|&(ref aggregation_fn, term, partial)| {
+                            let term = match term {
+                                None => return results.next().expect("missing grouped result").clone(),
+                                Some(term) => terms[term].expect("missing term"),
                             };
-                            Some((key, v as isize))
-                        })
-                        .count()
-                        .map(move |(key, count)| {
-                            (key, vec![Value::Number(count as i64)])
-                        });
-                    collections.push(tuples);
-                },
-                AggregationFn::AVG =>  {
-                    let tuples = tuples
-                        .map(prepare_unary)
-                        .consolidate()
-                        .distinct()
-                        .explode(move |(key, val)| {
-                            let v = match val[0] {
-                                Value::Number(num) => num,
-                                _ => panic!("AVG can only be applied on type Number."),
+
+                            let count = match term[1] {
+                                Value::Number(count) => count,
+                                _ => unreachable!(),
                             };
-                            Some((key, DiffPair::new(v as isize, 1)))
-                        })
-                        .count()
-                        .map(move |(key, diff_pair)| {
-                            (key, vec![Value::Rational32(Ratio::new(
-                                diff_pair.element1 as i32,
-                                diff_pair.element2 as i32))])
-                        });
-                    collections.push(tuples);
-                },
-                AggregationFn::VARIANCE => {
-                    let tuples = tuples
-                        .map(prepare_unary)
-                        .consolidate()
-                        .distinct()
-                        .explode(move |(key, val)| {
-                            let v = match val[0] {
-                                Value::Number(num) => num,
-                                _ => panic!("VARIANCE can only be applied on type Number."),
+
+                            if let &AggregationFn::COUNT = aggregation_fn {
+                                return Value::Number(count);
+                            }
+
+                            let name = match aggregation_fn {
+                                &AggregationFn::AVG => "AVG",
+                                _ => "SUM",
+                            };
+
+                            let integers = match term[2] {
+                                Value::Number(0) => None,
+                                _ => Some(&term[0]),
                             };
-                            Some((key, DiffPair::new(DiffPair::new(v as isize * v as isize, v as isize), 1)))
+
+                            let sum = match (integers, &partials[partial.expect("missing partial")]) {
+                                (Some(integers), &Value::Nil) => integers.clone(),
+                                (None, others) => others.clone(),
+                                (Some(integers), others) => numeric::add(integers, others)
+                                    .unwrap_or_else(|| panic!("{} overflowed.", name)),
+                            };
+
+                            match aggregation_fn {
+                                &AggregationFn::AVG => {
+                                    let avg = match sum {
+                                        Value::Number(sum) => numeric::ratio_i128(sum as i128, count as i128),
+                                        ref sum => numeric::div(sum, &Value::Number(count)),
+                                    };
+
+                                    avg.unwrap_or_else(|| panic!("{} overflowed.", name))
+                                }
+                                _ => sum,
+                            }
                         })
-                        .count()
-                        .map(move |(key, diff_pair)| {
-                            let sum_square = diff_pair.element1.element1 as i32;
-                            let sum = diff_pair.element1.element2 as i32;
-                            let c = diff_pair.element2 as i32;
-                            (key, vec![Value::Rational32(
-                                Rational32::new(sum_square, c) - Rational32::new(sum, c).pow(2))])
-                        });
-                    collections.push(tuples);
-                },
-            };
-        }
+                        .collect();
 
-        if collections.len() == 1 {
-            let output_index = output_offsets[0];
-            SimpleRelation{
-                symbols: self.variables.to_vec(),
-                tuples: collections[0]
-                    .map(move |(key, val)| {
-                        let mut k = key.clone();
-                        let v = val[0].clone();
-                        k.insert(output_index, v);
-                        k
-                    })
+                    output.push((vals, 1));
+                }
+            })
+        };
+
+        let tuples = {
+            // Aggregates are inserted in the order of their output
+            // offsets, s.t. each offset is within bounds.
+            let mut insertions: Vec<(usize, usize)> = output_offsets.iter().cloned().zip(0..).collect();
+            insertions.sort();
+
+            aggregates.map(move |(key, vals)| {
+                let mut v = key;
+                for &(output_offset, i) in insertions.iter() {
+                    v.insert(output_offset, vals[i].clone())
+                }
+                v
+            })
+        };
+
+        match self.empty_groups {
+            EmptyGroups::Identity if self.key_symbols.is_empty() => {
+                // A global aggregation always has exactly one group, thus
+                // we fill in identity values for as long as it has no
+                // inputs. Groups of grouped aggregations only exist by
+                // virtue of their inputs.
+                let mut identity = vec![Value::Nil; self.variables.len()];
+                for (i, aggregation_fn) in self.aggregation_fns.iter().enumerate() {
+                    identity[output_offsets[i]] = aggregation_fn.identity();
+                }
+
+                let mut parent = nested.parent.clone();
+                let initial = Some(((), 0u64, 1isize))
+                    .to_stream(&mut parent)
+                    .as_collection()
+                    .enter(nested);
+
+                let empty = initial
+                    .concat(&tuples.map(|_| ()).distinct().negate())
+                    .map(move |()| identity.clone());
+
+                SimpleRelation {
+                    symbols: self.variables.to_vec(),
+                    tuples: tuples.concat(&empty),
+                }
             }
-        }
-        else {
-            // @TODO replace this with a join application
-            let left = collections.remove(0);
-            let tuples = collections.iter()
-                .fold(left, |coll, next| coll.join_map(&next, |key, v1, v2| {
-                    let mut val = v1.clone();
-                    val.append(&mut v2.clone());
-                    (key.clone(), val)
-                }));
-            
-            SimpleRelation {
+            _ => SimpleRelation {
                 symbols: self.variables.to_vec(),
-                tuples: tuples.map(move |(key, vals)|{
-                    let mut v = key.clone();
-                    for (i, val) in vals.iter().enumerate(){
-                        v.insert(output_offsets[i], val.clone())
-                    }
-                    v
-                })
-            }
+                tuples,
+            },
         }
     }
 }
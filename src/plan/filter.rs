@@ -1,14 +1,19 @@
 //! Predicate expression plan.
 
+use std::cell::Cell;
+use std::cmp::Ordering;
 use std::collections::HashMap;
 
 use timely::communication::Allocate;
 use timely::dataflow::scopes::child::{Child, Iterative};
 use timely::worker::Worker;
 
+use regex::Regex;
+
+use numeric;
 use plan::Implementable;
 use Relation;
-use {QueryMap, RelationMap, SimpleRelation, Value, Var};
+use {QueryMap, RelationMap, SimpleRelation, Value, ValueType, Var};
 
 /// Permitted comparison predicates.
 #[derive(Deserialize, Clone, Debug)]
@@ -27,28 +32,67 @@ pub enum Predicate {
     NEQ,
 }
 
-fn lt(a: &Value, b: &Value) -> bool {
-    a < b
-}
-fn lte(a: &Value, b: &Value) -> bool {
-    a <= b
-}
-fn gt(a: &Value, b: &Value) -> bool {
-    a > b
+/// Orderings of strings.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Collation {
+    /// By code points
+    Binary,
+    /// By code points of the lower-cased strings
+    CaseInsensitive,
 }
-fn gte(a: &Value, b: &Value) -> bool {
-    a >= b
+
+impl Default for Collation {
+    fn default() -> Self {
+        Collation::Binary
+    }
 }
-fn eq(a: &Value, b: &Value) -> bool {
-    a == b
+
+/// Compares two values, if they are comparable. Numbers of all types
+/// are compared by magnitude, strings w.r.t. the collation, and any
+/// other values only with values of the same type. `Value::Nil` is
+/// not comparable.
+pub fn compare_values(a: &Value, b: &Value, collation: Collation) -> Option<Ordering> {
+    match (a, b) {
+        (&Value::Nil, _) | (_, &Value::Nil) => None,
+        (&Value::String(ref a), &Value::String(ref b)) => match collation {
+            Collation::Binary => Some(a.cmp(b)),
+            Collation::CaseInsensitive => Some(a.to_lowercase().cmp(&b.to_lowercase())),
+        },
+        _ => {
+            if numeric::is_numeric(a) && numeric::is_numeric(b) {
+                numeric::compare(a, b)
+            } else if ValueType::of(a) == ValueType::of(b) {
+                Some(a.cmp(b))
+            } else {
+                None
+            }
+        }
+    }
 }
-fn neq(a: &Value, b: &Value) -> bool {
-    a != b
+
+/// Applies a comparison predicate. Comparisons between incomparable
+/// values (e.g. involving `Value::Nil`, or a string and a number)
+/// never hold, not even `NEQ`.
+fn compare(predicate: &Predicate, a: &Value, b: &Value, collation: Collation) -> bool {
+    match compare_values(a, b, collation) {
+        None => false,
+        Some(ordering) => match predicate {
+            &Predicate::LT => ordering == Ordering::Less,
+            &Predicate::LTE => ordering != Ordering::Greater,
+            &Predicate::GT => ordering == Ordering::Greater,
+            &Predicate::GTE => ordering != Ordering::Less,
+            &Predicate::EQ => ordering == Ordering::Equal,
+            &Predicate::NEQ => ordering != Ordering::Equal,
+        },
+    }
 }
 
 /// A plan stage filtering source tuples by the specified
 /// predicate. Frontends are responsible for ensuring that the source
-/// binds the argument symbols.
+/// binds the argument symbols. Numbers are compared by magnitude
+/// irrespective of their types. Comparisons between incomparable
+/// values never hold, thus e.g. tuples with missing values are
+/// filtered out.
 #[derive(Deserialize, Clone, Debug)]
 pub struct Filter<P: Implementable> {
     /// TODO
@@ -59,6 +103,9 @@ pub struct Filter<P: Implementable> {
     pub plan: Box<P>,
     /// Constant intputs
     pub constants: HashMap<u32, Value>,
+    /// Ordering of strings
+    #[serde(default)]
+    pub collation: Collation,
 }
 
 impl<P: Implementable> Implementable for Filter<P> {
@@ -81,14 +128,9 @@ impl<P: Implementable> Implementable for Filter<P> {
             })
             .collect();
 
-        let binary_predicate = match self.predicate {
-            Predicate::LT => lt,
-            Predicate::LTE => lte,
-            Predicate::GT => gt,
-            Predicate::GTE => gte,
-            Predicate::EQ => eq,
-            Predicate::NEQ => neq,
-        };
+        let predicate = self.predicate.clone();
+        let collation = self.collation;
+        let binary_predicate = move |a: &Value, b: &Value| compare(&predicate, a, b, collation);
 
         if self.constants.contains_key(&0) {
             let constant = self.constants.get(&0).unwrap().clone();
@@ -114,3 +156,258 @@ impl<P: Implementable> Implementable for Filter<P> {
         }
     }
 }
+
+/// An argument to a predicate expression.
+#[derive(Deserialize, Clone, Debug)]
+pub enum Operand {
+    /// The value bound to a symbol
+    Var(Var),
+    /// A constant value
+    Const(Value),
+}
+
+/// A boolean expression over the bindings of a tuple. Comparisons
+/// follow the same rules as those of `Filter`, string predicates
+/// hold only for strings.
+#[derive(Deserialize, Clone, Debug)]
+pub enum PredicateExpr {
+    /// Holds iff all sub-expressions hold
+    And(Vec<PredicateExpr>),
+    /// Holds iff any sub-expression holds
+    Or(Vec<PredicateExpr>),
+    /// Holds iff the sub-expression does not hold
+    Not(Box<PredicateExpr>),
+    /// Compares two operands
+    Compare(Predicate, Operand, Operand),
+    /// Holds iff the operand is equal to one of the values
+    In(Operand, Vec<Value>),
+    /// Holds iff the first operand lies within the (inclusive) bounds
+    /// given by the second and third
+    Between(Operand, Operand, Operand),
+    /// Holds iff the first string starts with the second
+    StartsWith(Operand, Operand),
+    /// Holds iff the first string contains the second
+    Contains(Operand, Operand),
+    /// Holds iff the string matches the regular expression
+    Matches(Operand, String),
+    /// Holds iff the operand is of the specified type
+    IsType(Operand, ValueType),
+    /// Holds iff the operand is `Value::Nil`
+    IsNil(Operand),
+}
+
+#[derive(Clone, Debug)]
+enum Slot {
+    Offset(usize),
+    Const(Value),
+}
+
+impl Slot {
+    fn get<'a>(&'a self, tuple: &'a [Value]) -> &'a Value {
+        match self {
+            &Slot::Offset(offset) => &tuple[offset],
+            &Slot::Const(ref value) => value,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Compiled {
+    And(Vec<Compiled>),
+    Or(Vec<Compiled>),
+    Not(Box<Compiled>),
+    Compare(Predicate, Slot, Slot),
+    In(Slot, Vec<Value>),
+    Between(Slot, Slot, Slot),
+    StartsWith(Slot, Slot),
+    Contains(Slot, Slot),
+    Matches(Slot, Regex),
+    IsType(Slot, ValueType),
+    IsNil(Slot),
+}
+
+impl Compiled {
+    fn eval(&self, tuple: &[Value], collation: Collation) -> bool {
+        match self {
+            &Compiled::And(ref exprs) => exprs.iter().all(|expr| expr.eval(tuple, collation)),
+            &Compiled::Or(ref exprs) => exprs.iter().any(|expr| expr.eval(tuple, collation)),
+            &Compiled::Not(ref expr) => !expr.eval(tuple, collation),
+            &Compiled::Compare(ref predicate, ref a, ref b) => {
+                compare(predicate, a.get(tuple), b.get(tuple), collation)
+            }
+            &Compiled::In(ref slot, ref values) => {
+                let value = slot.get(tuple);
+                values
+                    .iter()
+                    .any(|other| compare(&Predicate::EQ, value, other, collation))
+            }
+            &Compiled::Between(ref slot, ref low, ref high) => {
+                let value = slot.get(tuple);
+                compare(&Predicate::GTE, value, low.get(tuple), collation)
+                    && compare(&Predicate::LTE, value, high.get(tuple), collation)
+            }
+            &Compiled::StartsWith(ref a, ref b) => match (a.get(tuple), b.get(tuple)) {
+                (&Value::String(ref a), &Value::String(ref b)) => match collation {
+                    Collation::Binary => a.starts_with(b.as_str()),
+                    Collation::CaseInsensitive => a.to_lowercase().starts_with(&b.to_lowercase()),
+                },
+                _ => false,
+            },
+            &Compiled::Contains(ref a, ref b) => match (a.get(tuple), b.get(tuple)) {
+                (&Value::String(ref a), &Value::String(ref b)) => match collation {
+                    Collation::Binary => a.contains(b.as_str()),
+                    Collation::CaseInsensitive => a.to_lowercase().contains(&b.to_lowercase()),
+                },
+                _ => false,
+            },
+            &Compiled::Matches(ref slot, ref regex) => match slot.get(tuple) {
+                &Value::String(ref string) => regex.is_match(string),
+                _ => false,
+            },
+            &Compiled::IsType(ref slot, value_type) => {
+                ValueType::of(slot.get(tuple)) == Some(value_type)
+            }
+            &Compiled::IsNil(ref slot) => *slot.get(tuple) == Value::Nil,
+        }
+    }
+}
+
+/// A predicate expression with its symbols resolved to offsets into
+/// the tuples it is evaluated on.
+#[derive(Clone, Debug)]
+pub struct CompiledPredicate {
+    expr: Compiled,
+    collation: Collation,
+    arity: usize,
+}
+
+impl CompiledPredicate {
+    /// Returns true iff the predicate holds for the tuple. Tuples too
+    /// short to bind all offsets never satisfy the predicate.
+    pub fn eval(&self, tuple: &[Value]) -> bool {
+        tuple.len() >= self.arity && self.expr.eval(tuple, self.collation)
+    }
+}
+
+impl PredicateExpr {
+    /// Resolves symbols to their offsets in tuples binding the
+    /// specified symbols, and compiles regular expressions. Strings
+    /// are compared w.r.t. the specified collation.
+    pub fn compile(
+        &self,
+        symbols: &[Var],
+        collation: Collation,
+    ) -> Result<CompiledPredicate, String> {
+        self.compile_with(
+            &|sym| {
+                symbols
+                    .iter()
+                    .position(|&v| v == sym)
+                    .ok_or_else(|| format!("Symbol {} not found", sym))
+            },
+            collation,
+        )
+    }
+
+    /// Compiles the expression treating symbols as offsets into the
+    /// tuples it is evaluated on, e.g. to constrain the tuples of a
+    /// published relation.
+    pub fn compile_positional(&self, collation: Collation) -> Result<CompiledPredicate, String> {
+        self.compile_with(&|sym| Ok(sym as usize), collation)
+    }
+
+    fn compile_with(
+        &self,
+        resolve: &dyn Fn(Var) -> Result<usize, String>,
+        collation: Collation,
+    ) -> Result<CompiledPredicate, String> {
+        let arity = Cell::new(0);
+        let expr = self.compile_expr(&|sym| {
+            let offset = resolve(sym)?;
+            arity.set(arity.get().max(offset + 1));
+            Ok(offset)
+        })?;
+
+        Ok(CompiledPredicate {
+            expr,
+            collation,
+            arity: arity.get(),
+        })
+    }
+
+    fn compile_expr(&self, resolve: &dyn Fn(Var) -> Result<usize, String>) -> Result<Compiled, String> {
+        let slot = |operand: &Operand| match operand {
+            &Operand::Var(sym) => resolve(sym).map(Slot::Offset),
+            &Operand::Const(ref value) => Ok(Slot::Const(value.clone())),
+        };
+
+        let compiled = match self {
+            &PredicateExpr::And(ref exprs) => Compiled::And(
+                exprs
+                    .iter()
+                    .map(|expr| expr.compile_expr(resolve))
+                    .collect::<Result<_, _>>()?,
+            ),
+            &PredicateExpr::Or(ref exprs) => Compiled::Or(
+                exprs
+                    .iter()
+                    .map(|expr| expr.compile_expr(resolve))
+                    .collect::<Result<_, _>>()?,
+            ),
+            &PredicateExpr::Not(ref expr) => Compiled::Not(Box::new(expr.compile_expr(resolve)?)),
+            &PredicateExpr::Compare(ref predicate, ref a, ref b) => {
+                Compiled::Compare(predicate.clone(), slot(a)?, slot(b)?)
+            }
+            &PredicateExpr::In(ref a, ref values) => {
+                Compiled::In(slot(a)?, values.clone())
+            }
+            &PredicateExpr::Between(ref a, ref low, ref high) => {
+                Compiled::Between(slot(a)?, slot(low)?, slot(high)?)
+            }
+            &PredicateExpr::StartsWith(ref a, ref b) => Compiled::StartsWith(slot(a)?, slot(b)?),
+            &PredicateExpr::Contains(ref a, ref b) => Compiled::Contains(slot(a)?, slot(b)?),
+            &PredicateExpr::Matches(ref a, ref pattern) => {
+                let regex = Regex::new(pattern).map_err(|error| error.to_string())?;
+                Compiled::Matches(slot(a)?, regex)
+            }
+            &PredicateExpr::IsType(ref a, value_type) => Compiled::IsType(slot(a)?, value_type),
+            &PredicateExpr::IsNil(ref a) => Compiled::IsNil(slot(a)?),
+        };
+
+        Ok(compiled)
+    }
+}
+
+/// A plan stage filtering source tuples by an arbitrary predicate
+/// expression over their bindings.
+#[derive(Deserialize, Clone, Debug)]
+pub struct FilterExpr<P: Implementable> {
+    /// Predicate expression to apply.
+    pub expr: PredicateExpr,
+    /// Plan for the data source.
+    pub plan: Box<P>,
+    /// Ordering of strings
+    #[serde(default)]
+    pub collation: Collation,
+}
+
+impl<P: Implementable> Implementable for FilterExpr<P> {
+    fn implement<'a, 'b, A: Allocate>(
+        &self,
+        nested: &mut Iterative<'b, Child<'a, Worker<A>, u64>, u64>,
+        local_arrangements: &RelationMap<Iterative<'b, Child<'a, Worker<A>, u64>, u64>>,
+        global_arrangements: &mut QueryMap<isize>,
+    ) -> SimpleRelation<'b, Child<'a, Worker<A>, u64>> {
+        let rel = self.plan
+            .implement(nested, local_arrangements, global_arrangements);
+
+        let predicate = self.expr
+            .compile(rel.symbols(), self.collation)
+            .unwrap_or_else(|msg| panic!("Invalid predicate expression: {}", msg));
+
+        SimpleRelation {
+            symbols: rel.symbols().to_vec(),
+            tuples: rel.tuples().filter(move |tuple| predicate.eval(tuple)),
+        }
+    }
+}
@@ -1,9 +1,12 @@
 //! Types and traits for implementing query plans.
 
+use std::collections::HashSet;
+
 use timely::communication::Allocate;
 use timely::dataflow::scopes::child::{Child, Iterative};
 use timely::worker::Worker;
 
+use functions::FunctionRegistry;
 use {Attribute, Entity, Value, Var};
 use {QueryMap, Relation, RelationMap, SimpleRelation};
 
@@ -15,9 +18,11 @@ pub mod project;
 pub mod transform;
 pub mod union;
 
-pub use self::aggregate::{Aggregate, AggregationFn};
+pub use self::aggregate::{Aggregate, AggregationFn, EmptyGroups};
 pub use self::antijoin::Antijoin;
-pub use self::filter::{Filter, Predicate};
+pub use self::filter::{
+    Collation, CompiledPredicate, Filter, FilterExpr, Operand, Predicate, PredicateExpr,
+};
 pub use self::join::Join;
 pub use self::project::Project;
 pub use self::transform::{Function, Transform};
@@ -51,6 +56,8 @@ pub enum Plan {
     Negate(Box<Plan>),
     /// Filters bindings by one of the built-in predicates
     Filter(Filter<Plan>),
+    /// Filters bindings by a predicate expression
+    FilterExpr(FilterExpr<Plan>),
     /// Transforms a binding by a function expression
     Transform(Transform<Plan>),
     // /// Data pattern of the form [e ?a ?v]
@@ -65,6 +72,185 @@ pub enum Plan {
     RuleExpr(Vec<Var>, String),
     /// Sources data from a published relation
     NameExpr(Vec<Var>, String),
+    /// Binds a symbol to the values of a named query parameter
+    Param(Var, String),
+    /// Binds a client symbol and a symbol to the values of a named
+    /// lookup parameter, as submitted by each client
+    Lookup(Var, Var, String),
+}
+
+impl Plan {
+    /// Returns the plans this plan is composed of.
+    fn children(&self) -> Vec<&Plan> {
+        match self {
+            &Plan::Project(ref projection) => vec![&*projection.plan],
+            &Plan::Aggregate(ref aggregate) => vec![&*aggregate.plan],
+            &Plan::Union(ref union) => union.plans.iter().collect(),
+            &Plan::Join(ref join) => vec![&*join.left_plan, &*join.right_plan],
+            &Plan::Antijoin(ref antijoin) => vec![&*antijoin.left_plan, &*antijoin.right_plan],
+            &Plan::Negate(ref plan) => vec![&**plan],
+            &Plan::Filter(ref filter) => vec![&*filter.plan],
+            &Plan::FilterExpr(ref filter) => vec![&*filter.plan],
+            &Plan::Transform(ref transform) => vec![&*transform.plan],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Returns the plans this plan is composed of, mutably.
+    fn children_mut(&mut self) -> Vec<&mut Plan> {
+        match self {
+            &mut Plan::Project(ref mut projection) => vec![&mut *projection.plan],
+            &mut Plan::Aggregate(ref mut aggregate) => vec![&mut *aggregate.plan],
+            &mut Plan::Union(ref mut union) => union.plans.iter_mut().collect(),
+            &mut Plan::Join(ref mut join) => vec![&mut *join.left_plan, &mut *join.right_plan],
+            &mut Plan::Antijoin(ref mut antijoin) => {
+                vec![&mut *antijoin.left_plan, &mut *antijoin.right_plan]
+            }
+            &mut Plan::Negate(ref mut plan) => vec![&mut **plan],
+            &mut Plan::Filter(ref mut filter) => vec![&mut *filter.plan],
+            &mut Plan::FilterExpr(ref mut filter) => vec![&mut *filter.plan],
+            &mut Plan::Transform(ref mut transform) => vec![&mut *transform.plan],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Resolves all user-defined functions referenced by this plan
+    /// against the registry, failing on unknown functions or on
+    /// arguments not matching their signature.
+    pub fn bind_functions(&mut self, registry: &FunctionRegistry) -> Result<(), String> {
+        if let &mut Plan::Transform(ref mut transform) = self {
+            transform.bind_functions(registry)?;
+        }
+
+        for plan in self.children_mut() {
+            plan.bind_functions(registry)?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks the parts of this plan that would otherwise only fail
+    /// once implemented, e.g. the arguments of aggregation functions,
+    /// or the symbols and patterns of filter predicates.
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            &Plan::Aggregate(ref aggregate) => aggregate.validate()?,
+            &Plan::Filter(ref filter) => {
+                let symbols = filter.plan.symbols();
+                if let Some(sym) = filter.variables.iter().find(|sym| !symbols.contains(*sym)) {
+                    return Err(format!("Symbol {} not found", sym));
+                }
+            }
+            &Plan::FilterExpr(ref filter) => {
+                filter
+                    .expr
+                    .compile(&filter.plan.symbols(), filter.collation)
+                    .map_err(|msg| format!("Invalid predicate expression: {}", msg))?;
+            }
+            _ => {}
+        }
+
+        for plan in self.children() {
+            plan.validate()?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the symbols bound by the tuples of this plan, in order.
+    pub fn symbols(&self) -> Vec<Var> {
+        match self {
+            &Plan::Project(ref projection) => projection.variables.clone(),
+            &Plan::Aggregate(ref aggregate) => aggregate.variables.clone(),
+            &Plan::Union(ref union) => union.variables.clone(),
+            &Plan::Join(ref join) => {
+                let mut symbols = join.variables.clone();
+                for plan in [&join.left_plan, &join.right_plan].iter() {
+                    for sym in plan.symbols() {
+                        if !join.variables.contains(&sym) {
+                            symbols.push(sym);
+                        }
+                    }
+                }
+                symbols
+            }
+            &Plan::Antijoin(ref antijoin) => {
+                let mut symbols = antijoin.variables.clone();
+                for sym in antijoin.left_plan.symbols() {
+                    if !antijoin.variables.contains(&sym) {
+                        symbols.push(sym);
+                    }
+                }
+                symbols
+            }
+            &Plan::Negate(ref plan) => plan.symbols(),
+            &Plan::Filter(ref filter) => filter.plan.symbols(),
+            &Plan::FilterExpr(ref filter) => filter.plan.symbols(),
+            &Plan::Transform(ref transform) => {
+                let mut symbols = transform.plan.symbols();
+                symbols.push(transform.result_sym);
+                symbols
+            }
+            &Plan::MatchA(sym1, _, sym2) => vec![sym1, sym2],
+            &Plan::MatchEA(_, _, sym) => vec![sym],
+            &Plan::MatchAV(sym, _, _) => vec![sym],
+            &Plan::RuleExpr(ref syms, _) => syms.clone(),
+            &Plan::NameExpr(ref syms, _) => syms.clone(),
+            &Plan::Param(sym, _) => vec![sym],
+            &Plan::Lookup(client_sym, sym, _) => vec![client_sym, sym],
+        }
+    }
+
+    /// Returns the names of all rules this plan refers to via
+    /// `RuleExpr`.
+    pub fn dependencies(&self) -> HashSet<String> {
+        let mut names = HashSet::new();
+
+        if let &Plan::RuleExpr(_, ref name) = self {
+            names.insert(name.clone());
+        }
+
+        for plan in self.children() {
+            names.extend(plan.dependencies());
+        }
+
+        names
+    }
+
+    /// Returns the names of all lookup parameters of this plan.
+    pub fn lookups(&self) -> HashSet<String> {
+        let mut names = HashSet::new();
+
+        if let &Plan::Lookup(_, _, ref name) = self {
+            names.insert(name.clone());
+        }
+
+        for plan in self.children() {
+            names.extend(plan.lookups());
+        }
+
+        names
+    }
+
+    /// Qualifies the names of all parameters with the name of the
+    /// query they belong to, returning the qualified names.
+    pub fn qualify_params(&mut self, query: &str) -> Vec<String> {
+        let mut params = Vec::new();
+
+        match self {
+            &mut Plan::Param(_, ref mut name) | &mut Plan::Lookup(_, _, ref mut name) => {
+                *name = format!("{}/{}", query, name);
+                params.push(name.clone());
+            }
+            _ => {}
+        }
+
+        for plan in self.children_mut() {
+            params.extend(plan.qualify_params(query));
+        }
+
+        params
+    }
 }
 
 impl Implementable for Plan {
@@ -105,6 +291,9 @@ impl Implementable for Plan {
             &Plan::Filter(ref filter) => {
                 filter.implement(nested, local_arrangements, global_arrangements)
             }
+            &Plan::FilterExpr(ref filter) => {
+                filter.implement(nested, local_arrangements, global_arrangements)
+            }
             &Plan::Transform(ref transform) => {
                 transform.implement(nested, local_arrangements, global_arrangements)
             }
@@ -158,12 +347,42 @@ impl Implementable for Plan {
                 }
             }
             &Plan::RuleExpr(ref syms, ref name) => match local_arrangements.get(name) {
-                None => panic!("{:?} not in relation map", name),
+                // rules published by previous registrations are read as is
+                None => match global_arrangements.get_mut(name) {
+                    None => panic!("{:?} not in relation map", name),
+                    Some(named) => SimpleRelation {
+                        symbols: syms.clone(),
+                        tuples: named
+                            .import(&nested.parent)
+                            .enter(nested)
+                            .as_collection(|tuple, _| tuple.clone()),
+                    },
+                },
                 Some(named) => SimpleRelation {
                     symbols: syms.clone(),
                     tuples: named.map(|tuple| tuple.clone()),
                 },
             },
+            &Plan::Param(sym, ref name) => match global_arrangements.get_mut(name) {
+                None => panic!("Parameter {:?} is not bound", name),
+                Some(named) => SimpleRelation {
+                    symbols: vec![sym],
+                    tuples: named
+                        .import(&nested.parent)
+                        .enter(nested)
+                        .as_collection(|tuple, _| tuple.clone()),
+                },
+            },
+            &Plan::Lookup(client_sym, sym, ref name) => match global_arrangements.get_mut(name) {
+                None => panic!("Lookup parameter {:?} is not bound", name),
+                Some(named) => SimpleRelation {
+                    symbols: vec![client_sym, sym],
+                    tuples: named
+                        .import(&nested.parent)
+                        .enter(nested)
+                        .as_collection(|tuple, _| tuple.clone()),
+                },
+            },
             &Plan::NameExpr(ref syms, ref name) => match global_arrangements.get_mut(name) {
                 None => panic!("{:?} not in query map", name),
                 Some(named) => SimpleRelation {
@@ -1,29 +1,488 @@
 //! Function expression plan.
+use std::cmp::Ordering;
 use std::collections::HashMap;
 
 use timely::communication::Allocate;
 use timely::dataflow::scopes::child::{Child, Iterative};
 use timely::worker::Worker;
 
+use chrono_tz::Tz;
+use regex::Regex;
+
+use functions::{FunctionError, FunctionRegistry, FunctionResult, ScalarFunction};
+use numeric;
+use plan::filter::{compare_values, Collation};
 use plan::Implementable;
+use time;
 use Relation;
-use {QueryMap, RelationMap, SimpleRelation, Value, Var};
+use {QueryMap, Rational32, RelationMap, SimpleRelation, Value, Var};
 
 /// Permitted functions.
 #[derive(Deserialize, Clone, Debug)]
 pub enum Function {
-    /// Truncates a unix timestamp into an hourly interval
+    /// Truncates a unix timestamp into an hourly interval, or into
+    /// the unit of time (`:second`, `:minute`, `:hour`, `:day`,
+    /// `:week`, `:month`, `:quarter`, `:year`) provided as the second
+    /// argument, in UTC or in the time zone provided as the third
     TRUNCATE,
-    /// Adds one or more numbers to the first provided
+    /// Adds one or more numbers to the first provided, promoting
+    /// mixed operands to the wider numeric type
     ADD,
-    /// Subtracts one or more numbers from the first provided
+    /// Subtracts one or more numbers from the first provided,
+    /// promoting mixed operands to the wider numeric type
     SUBTRACT,
+    /// Multiplies all provided numbers
+    MULTIPLY,
+    /// Divides the first provided number by all others
+    DIVIDE,
+    /// Remainder of dividing the first number by the second
+    MOD,
+    /// Absolute value of a number
+    ABS,
+    /// Smallest of the provided values, which must be comparable
+    MIN,
+    /// Largest of the provided values, which must be comparable
+    MAX,
+    /// Concatenates the string representations of all provided values
+    CONCAT,
+    /// Lower-cases a string
+    LOWER,
+    /// Upper-cases a string
+    UPPER,
+    /// Characters of a string starting at a zero-based offset,
+    /// optionally limited to a number of characters
+    SUBSTRING,
+    /// Number of characters in a string
+    STR_LENGTH,
+    /// Whether a string matches a regular expression
+    REGEX_MATCH,
+    /// First capture group of a regular expression in a string (or
+    /// the whole match, if the expression has no groups), `Nil` if
+    /// the string does not match
+    REGEX_EXTRACT,
+    /// Parses a string into a `Number`, a `Decimal`, or a `Real`,
+    /// whichever is the first to represent it
+    PARSE_NUMBER,
+    /// Converts into a `Number`, truncating fractions
+    TO_NUMBER,
+    /// Converts into a `Rational32`
+    TO_RATIONAL32,
+    /// Converts into a `String`
+    TO_STRING,
+    /// Converts milliseconds since the epoch into an `Instant`
+    TO_INSTANT,
+    /// Adds an amount of units of time to an instant, optionally in a
+    /// time zone
+    DATE_ADD,
+    /// Number of whole units of time from the first instant to the
+    /// second, optionally in a time zone
+    DATE_DIFF,
+    /// Extracts a field (`:year`, `:quarter`, `:month`, `:day`,
+    /// `:weekday`, `:hour`, `:minute`, `:second`) from an instant,
+    /// optionally in a time zone
+    DATE_PART,
+    /// Parses an ISO 8601 string into an instant, interpreting
+    /// strings without offset in UTC or in the provided time zone
+    PARSE_INSTANT,
+    /// Formats an instant as an ISO 8601 string, in UTC or in the
+    /// provided time zone
+    FORMAT_INSTANT,
+    /// Applies a user-defined function registered under the given
+    /// name
+    UDF(String),
+}
+
+impl Function {
+    /// The minimum and (optional) maximum number of arguments.
+    fn arity(&self) -> (usize, Option<usize>) {
+        match self {
+            &Function::ADD
+            | &Function::SUBTRACT
+            | &Function::MULTIPLY
+            | &Function::DIVIDE
+            | &Function::MIN
+            | &Function::MAX => (1, None),
+            &Function::CONCAT | &Function::UDF(_) => (0, None),
+            &Function::TRUNCATE => (1, Some(3)),
+            &Function::SUBSTRING | &Function::DATE_PART => (2, Some(3)),
+            &Function::DATE_ADD | &Function::DATE_DIFF => (3, Some(4)),
+            &Function::PARSE_INSTANT | &Function::FORMAT_INSTANT => (1, Some(2)),
+            &Function::MOD | &Function::REGEX_MATCH | &Function::REGEX_EXTRACT => (2, Some(2)),
+            _ => (1, Some(1)),
+        }
+    }
+
+    /// Applies a built-in function. Regular expressions may be
+    /// compiled ahead of time, otherwise they are compiled from the
+    /// respective argument.
+    fn apply(&self, args: &[Value], regex: Option<&Regex>) -> FunctionResult {
+        let (min, max) = self.arity();
+        if args.len() < min || max.map(|max| args.len() > max).unwrap_or(false) {
+            return Err(FunctionError::Arity(args.len()));
+        }
+
+        match self {
+            &Function::TRUNCATE => truncate(args),
+            &Function::ADD => fold_numeric(args, numeric::add),
+            &Function::SUBTRACT => fold_numeric(args, numeric::sub),
+            &Function::MULTIPLY => fold_numeric(args, numeric::mul),
+            &Function::DIVIDE => fold_numeric(args, numeric::div),
+            &Function::MOD => fold_numeric(args, numeric::rem),
+            &Function::ABS => {
+                numeric::abs(numeric_argument(args, 0)?).ok_or(FunctionError::Overflow)
+            }
+            &Function::MIN => extremum(args, Ordering::Less),
+            &Function::MAX => extremum(args, Ordering::Greater),
+            &Function::CONCAT => {
+                let mut result = String::new();
+                for position in 0..args.len() {
+                    result.push_str(&to_string(args, position)?);
+                }
+                Ok(Value::String(result))
+            }
+            &Function::LOWER => Ok(Value::String(string_argument(args, 0)?.to_lowercase())),
+            &Function::UPPER => Ok(Value::String(string_argument(args, 0)?.to_uppercase())),
+            &Function::SUBSTRING => {
+                let string = string_argument(args, 0)?;
+                let start = count_argument(args, 1)?;
+                let substring: String = match args.get(2) {
+                    None => string.chars().skip(start).collect(),
+                    Some(_) => string.chars().skip(start).take(count_argument(args, 2)?).collect(),
+                };
+                Ok(Value::String(substring))
+            }
+            &Function::STR_LENGTH => {
+                Ok(Value::Number(string_argument(args, 0)?.chars().count() as i64))
+            }
+            &Function::REGEX_MATCH => {
+                let string = string_argument(args, 0)?;
+                match regex {
+                    Some(regex) => Ok(Value::Bool(regex.is_match(string))),
+                    None => Ok(Value::Bool(regex_argument(args, 1)?.is_match(string))),
+                }
+            }
+            &Function::REGEX_EXTRACT => {
+                let string = string_argument(args, 0)?;
+                let compiled;
+                let regex = match regex {
+                    Some(regex) => regex,
+                    None => {
+                        compiled = regex_argument(args, 1)?;
+                        &compiled
+                    }
+                };
+
+                match regex.captures(string) {
+                    None => Ok(Value::Nil),
+                    Some(captures) => {
+                        let capture = captures.get(1).or_else(|| captures.get(0)).unwrap();
+                        Ok(Value::String(capture.as_str().to_string()))
+                    }
+                }
+            }
+            &Function::PARSE_NUMBER => {
+                let string = string_argument(args, 0)?.trim();
+                if let Ok(x) = string.parse::<i64>() {
+                    Ok(Value::Number(x))
+                } else if let Ok(x) = string.parse() {
+                    Ok(Value::Decimal(x))
+                } else if let Ok(x) = string.parse::<f64>() {
+                    Ok(Value::Real(x.into()))
+                } else {
+                    Err(FunctionError::Invalid(0, format!("{:?} is not a number", string)))
+                }
+            }
+            &Function::TO_NUMBER => to_number(args),
+            &Function::TO_RATIONAL32 => to_rational32(args),
+            &Function::TO_STRING => Ok(Value::String(to_string(args, 0)?)),
+            &Function::TO_INSTANT => match &args[0] {
+                &Value::Instant(t) => Ok(Value::Instant(t)),
+                &Value::Number(x) if x >= 0 => Ok(Value::Instant(x as u64)),
+                &Value::Number(_) => Err(FunctionError::Invalid(0, "Negative timestamp".to_string())),
+                &Value::String(ref string) => string
+                    .trim()
+                    .parse::<u64>()
+                    .map(Value::Instant)
+                    .map_err(|_| FunctionError::Invalid(0, format!("{:?} is not a timestamp", string))),
+                other => Err(FunctionError::Type(0, other.clone())),
+            },
+            &Function::DATE_ADD => {
+                let t = instant_argument(args, 0)?;
+                let amount = match &args[1] {
+                    &Value::Number(amount) => amount,
+                    other => return Err(FunctionError::Type(1, other.clone())),
+                };
+                let unit = unit_argument(args, 2)?;
+                let tz = tz_argument(args, 3)?;
+
+                time::add(t, amount, unit, &tz)
+                    .map(Value::Instant)
+                    .ok_or(FunctionError::Overflow)
+            }
+            &Function::DATE_DIFF => {
+                let from = instant_argument(args, 0)?;
+                let to = instant_argument(args, 1)?;
+                let unit = unit_argument(args, 2)?;
+                let tz = tz_argument(args, 3)?;
+
+                time::diff(from, to, unit, &tz)
+                    .map(Value::Number)
+                    .ok_or(FunctionError::Overflow)
+            }
+            &Function::DATE_PART => {
+                let t = instant_argument(args, 0)?;
+                let field = match &args[1] {
+                    &Value::String(ref keyword) => time::Field::parse(keyword).ok_or_else(|| {
+                        FunctionError::Invalid(1, format!("Unknown field {}", keyword))
+                    })?,
+                    other => return Err(FunctionError::Type(1, other.clone())),
+                };
+                let tz = tz_argument(args, 2)?;
+
+                time::part(t, field, &tz)
+                    .map(Value::Number)
+                    .ok_or(FunctionError::Overflow)
+            }
+            &Function::PARSE_INSTANT => {
+                let string = string_argument(args, 0)?.trim();
+                let tz = tz_argument(args, 1)?;
+
+                time::parse(string, &tz).map(Value::Instant).ok_or_else(|| {
+                    FunctionError::Invalid(0, format!("{:?} is not an ISO 8601 date", string))
+                })
+            }
+            &Function::FORMAT_INSTANT => {
+                let t = instant_argument(args, 0)?;
+                let tz = tz_argument(args, 1)?;
+
+                time::format(t, &tz)
+                    .map(Value::String)
+                    .ok_or(FunctionError::Overflow)
+            }
+            &Function::UDF(ref name) => Err(FunctionError::Other(format!(
+                "Function {} has not been bound",
+                name
+            ))),
+        }
+    }
+}
+
+fn numeric_argument(args: &[Value], position: usize) -> Result<&Value, FunctionError> {
+    if numeric::is_numeric(&args[position]) {
+        Ok(&args[position])
+    } else {
+        Err(FunctionError::Type(position, args[position].clone()))
+    }
+}
+
+fn string_argument(args: &[Value], position: usize) -> Result<&str, FunctionError> {
+    match &args[position] {
+        &Value::String(ref string) => Ok(string),
+        other => Err(FunctionError::Type(position, other.clone())),
+    }
+}
+
+fn count_argument(args: &[Value], position: usize) -> Result<usize, FunctionError> {
+    match &args[position] {
+        &Value::Number(x) if x >= 0 => Ok(x as usize),
+        &Value::Number(_) => Err(FunctionError::Invalid(position, "Negative count".to_string())),
+        other => Err(FunctionError::Type(position, other.clone())),
+    }
+}
+
+fn regex_argument(args: &[Value], position: usize) -> Result<Regex, FunctionError> {
+    Regex::new(string_argument(args, position)?)
+        .map_err(|error| FunctionError::Invalid(position, error.to_string()))
+}
+
+/// Folds numeric arguments from the left.
+fn fold_numeric<F>(args: &[Value], op: F) -> FunctionResult
+where
+    F: Fn(&Value, &Value) -> Option<Value>,
+{
+    let mut result = numeric_argument(args, 0)?.clone();
+
+    for position in 1..args.len() {
+        let operand = numeric_argument(args, position)?;
+        result = match op(&result, operand) {
+            Some(result) => result,
+            None => {
+                if numeric::compare(operand, &Value::Number(0)) == Some(Ordering::Equal) {
+                    return Err(FunctionError::DivisionByZero);
+                } else {
+                    return Err(FunctionError::Overflow);
+                }
+            }
+        };
+    }
+
+    Ok(result)
+}
+
+fn extremum(args: &[Value], ordering: Ordering) -> FunctionResult {
+    let mut result = &args[0];
+
+    for position in 1..args.len() {
+        let value = &args[position];
+        match compare_values(value, result, Collation::Binary) {
+            None => return Err(FunctionError::Type(position, value.clone())),
+            Some(cmp) => {
+                if cmp == ordering {
+                    result = value;
+                }
+            }
+        }
+    }
+
+    Ok(result.clone())
+}
+
+fn instant_argument(args: &[Value], position: usize) -> Result<u64, FunctionError> {
+    match &args[position] {
+        &Value::Instant(t) => Ok(t),
+        other => Err(FunctionError::Type(position, other.clone())),
+    }
+}
+
+fn unit_argument(args: &[Value], position: usize) -> Result<time::Unit, FunctionError> {
+    let keyword = string_argument(args, position)?;
+    time::Unit::parse(keyword)
+        .ok_or_else(|| FunctionError::Invalid(position, format!("Unknown interval {}", keyword)))
+}
+
+/// Time zones are optional and default to UTC.
+fn tz_argument(args: &[Value], position: usize) -> Result<Tz, FunctionError> {
+    match args.get(position) {
+        None => Ok(Tz::UTC),
+        Some(&Value::String(ref name)) => name
+            .parse::<Tz>()
+            .map_err(|_| FunctionError::Invalid(position, format!("Unknown time zone {}", name))),
+        Some(other) => Err(FunctionError::Type(position, other.clone())),
+    }
+}
+
+fn truncate(args: &[Value]) -> FunctionResult {
+    let t = instant_argument(args, 0)?;
+
+    let unit = match args.get(1) {
+        None => time::Unit::Hour,
+        Some(_) => unit_argument(args, 1)?,
+    };
+
+    let tz = tz_argument(args, 2)?;
+
+    time::truncate(t, unit, &tz)
+        .map(Value::Instant)
+        .ok_or(FunctionError::Overflow)
+}
+
+fn to_string(args: &[Value], position: usize) -> Result<String, FunctionError> {
+    match &args[position] {
+        &Value::Attribute(ref a) => Ok(a.clone()),
+        &Value::String(ref s) => Ok(s.clone()),
+        &Value::Bool(b) => Ok(b.to_string()),
+        &Value::Number(x) => Ok(x.to_string()),
+        &Value::Rational32(x) => Ok(x.to_string()),
+        &Value::Rational64(x) => Ok(x.to_string()),
+        &Value::Real(x) => Ok(x.into_inner().to_string()),
+        &Value::Decimal(x) => Ok(x.to_string()),
+        &Value::Eid(e) => Ok(e.to_string()),
+        &Value::Instant(t) => Ok(t.to_string()),
+        &Value::Uuid(ref bytes) => Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect()),
+        &Value::Nil => Err(FunctionError::Type(position, Value::Nil)),
+    }
+}
+
+fn to_number(args: &[Value]) -> FunctionResult {
+    match &args[0] {
+        &Value::Number(x) => Ok(Value::Number(x)),
+        &Value::Rational32(x) => Ok(Value::Number(x.to_integer() as i64)),
+        &Value::Rational64(x) => Ok(Value::Number(x.to_integer())),
+        &Value::Instant(t) => {
+            if t <= i64::max_value() as u64 {
+                Ok(Value::Number(t as i64))
+            } else {
+                Err(FunctionError::Overflow)
+            }
+        }
+        &Value::String(ref string) => string
+            .trim()
+            .parse::<i64>()
+            .map(Value::Number)
+            .map_err(|_| FunctionError::Invalid(0, format!("{:?} is not an integer", string))),
+        other => Err(FunctionError::Type(0, other.clone())),
+    }
+}
+
+fn to_rational32(args: &[Value]) -> FunctionResult {
+    let fits = |x: i64| x >= i32::min_value() as i64 && x <= i32::max_value() as i64;
+
+    match &args[0] {
+        &Value::Rational32(x) => Ok(Value::Rational32(x)),
+        &Value::Number(x) => {
+            if fits(x) {
+                Ok(Value::Rational32(Rational32::from_integer(x as i32)))
+            } else {
+                Err(FunctionError::Overflow)
+            }
+        }
+        &Value::Rational64(x) => {
+            if fits(*x.numer()) && fits(*x.denom()) {
+                Ok(Value::Rational32(Rational32::new_raw(*x.numer() as i32, *x.denom() as i32)))
+            } else {
+                Err(FunctionError::Overflow)
+            }
+        }
+        &Value::Instant(t) => {
+            if t <= i32::max_value() as u64 {
+                Ok(Value::Rational32(Rational32::from_integer(t as i32)))
+            } else {
+                Err(FunctionError::Overflow)
+            }
+        }
+        &Value::String(ref string) => string
+            .trim()
+            .parse::<Rational32>()
+            .map(Value::Rational32)
+            .map_err(|_| FunctionError::Invalid(0, format!("{:?} is not a rational", string))),
+        other => Err(FunctionError::Type(0, other.clone())),
+    }
+}
+
+/// Collects the arguments of a function application, taken from the
+/// constants where specified, and from the variables in order
+/// otherwise.
+fn arguments(
+    tuple: &[Value],
+    offsets: &[usize],
+    constants: &HashMap<u32, Value>,
+) -> Result<Vec<Value>, FunctionError> {
+    let arity = offsets.len() + constants.len();
+    let mut variables = offsets.iter();
+    let mut arguments = Vec::with_capacity(arity);
+
+    for position in 0..arity {
+        match constants.get(&(position as u32)) {
+            Some(constant) => arguments.push(constant.clone()),
+            None => match variables.next() {
+                Some(offset) => arguments.push(tuple[*offset].clone()),
+                None => return Err(FunctionError::Arity(arity)),
+            },
+        }
+    }
+
+    Ok(arguments)
 }
 
 /// A plan stage applying a built-in function to source tuples.
 /// Frontends are responsible for ensuring that the source
 /// binds the argument symbols and that the result is projected onto
 /// the right symbol.
+///
+/// Arguments are taken from the constants at their respective
+/// positions, and from the variables in order otherwise. Tuples for
+/// which the function can not be applied (e.g. due to arguments of
+/// the wrong type) are dropped.
 #[derive(Deserialize, Clone, Debug)]
 pub struct Transform<P: Implementable> {
     /// TODO
@@ -36,6 +495,70 @@ pub struct Transform<P: Implementable> {
     pub function: Function,
     /// Constant intputs
     pub constants: HashMap<u32, Value>,
+    /// User-defined function, bound by the server at registration
+    #[serde(skip)]
+    pub udf: Option<ScalarFunction>,
+}
+
+impl<P: Implementable> Transform<P> {
+    /// Checks the number of arguments and the constant arguments of
+    /// the function, resolving user-defined functions against the
+    /// registry.
+    pub fn bind_functions(&mut self, registry: &FunctionRegistry) -> Result<(), String> {
+        let arity = self.variables.len() + self.constants.len();
+
+        for position in self.constants.keys() {
+            if *position as usize >= arity {
+                return Err(format!("Unexpected constant at position {}", position));
+            }
+        }
+
+        match self.function {
+            Function::UDF(ref name) => {
+                let udf = registry
+                    .get(name)
+                    .ok_or_else(|| format!("Unknown function {}", name))?;
+
+                if arity != udf.arity() {
+                    return Err(format!(
+                        "Function {} expects {} arguments, got {}",
+                        name,
+                        udf.arity(),
+                        arity
+                    ));
+                }
+
+                for (position, value) in self.constants.iter() {
+                    udf.check_argument(*position as usize, value)
+                        .map_err(|error| format!("Function {}: {}", name, error))?;
+                }
+
+                self.udf = Some(udf.clone());
+            }
+            ref function => {
+                let (min, max) = function.arity();
+                if arity < min || max.map(|max| arity > max).unwrap_or(false) {
+                    return Err(format!("Function {:?} got {} arguments", function, arity));
+                }
+
+                if let Some(pattern) = self.regex_constant() {
+                    Regex::new(pattern).map_err(|error| error.to_string())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the regular expression of a regex function, if it is
+    /// given as a constant.
+    fn regex_constant(&self) -> Option<&str> {
+        match (&self.function, self.constants.get(&1)) {
+            (&Function::REGEX_MATCH, Some(&Value::String(ref pattern)))
+            | (&Function::REGEX_EXTRACT, Some(&Value::String(ref pattern))) => Some(pattern),
+            _ => None,
+        }
+    }
 }
 
 impl<P: Implementable> Implementable for Transform<P> {
@@ -64,110 +587,42 @@ impl<P: Implementable> Implementable for Transform<P> {
 
         let constants_local = self.constants.clone();
 
-        match self.function {
-            Function::TRUNCATE => SimpleRelation {
-                symbols: symbols,
-                tuples: rel.tuples().map(move |tuple| {
-                    let mut t = match tuple[key_offsets[0]] {
-                        Value::Instant(inst) => inst as u64,
-                        _ => panic!("TRUNCATE can only be applied to timestamps"),
-                    };
-                    let default_interval = String::from(":hour");
-                    let interval_param = match constants_local.get(&1) {
-                        Some(Value::String(interval)) => interval as &String,
-                        None => &default_interval,
-                        _ => panic!("Parameter for TRUNCATE must be a string"),
-                    };
-
-                    let mod_val = match interval_param.as_ref() {
-                        ":minute" => 60000,
-                        ":hour" => 3600000,
-                        ":day" => 86400000,
-                        ":week" => 604800000,
-                        _ => panic!("Unknown interval for TRUNCATE"),
-                    };
-
-                    t = t - (t % mod_val);
-                    let mut v = tuple.clone();
-                    v.push(Value::Instant(t));
-                    v
-                }),
-            },
-            Function::ADD => SimpleRelation {
-                symbols: symbols,
-                tuples: rel.tuples().map(move |tuple| {
-                    let mut result = 0;
-
-                    // summands (vars)
-                    for offset in &key_offsets {
-                        let summand = match tuple[*offset] {
-                            Value::Number(s) => s as i64,
-                            _ => panic!("ADD can only be applied to numbers"),
-                        };
-
-                        result = result + summand;
-                    }
+        if let Function::UDF(ref name) = self.function {
+            if self.udf.is_none() {
+                panic!("Function {} has not been bound", name);
+            }
+        }
 
-                    // summands (constants)
-                    for (_key, val) in &constants_local {
-                        let summand = match val {
-                            Value::Number(s) => *s as i64,
-                            _ => panic!("ADD can only be applied to numbers"),
-                        };
+        // Constant patterns are compiled only once.
+        let regex = self
+            .regex_constant()
+            .map(|pattern| Regex::new(pattern).expect("Invalid regular expression"));
 
-                        result = result + summand;
-                    }
+        let function = self.function.clone();
+        let udf = self.udf.clone();
 
-                    let mut v = tuple.clone();
-                    v.push(Value::Number(result));
-                    v
-                }),
-            },
-            Function::SUBTRACT => SimpleRelation {
-                symbols: symbols,
-                tuples: rel.tuples().map(move |tuple| {
-                    // minuend is either symbol or variable, depending on
-                    // position in transform
-
-                    let mut result = match constants_local.get(&0) {
-                        Some(constant) => match constant {
-                            Value::Number(minuend) => *minuend as i64,
-                            _ => panic!("SUBTRACT can only be applied to numbers")
-                        },
-                        None => match tuple[key_offsets[0]] {
-                            Value::Number(minuend) => minuend as i64,
-                            _ => panic!("SUBTRACT can only be applied to numbers"),
-                        }
-                    };
-
-                    // avoid filtering out the minuend by doubling it
-                    result = result + result;
-
-                    // subtrahends (vars)
-                    for offset in &key_offsets {
-                        let subtrahend = match tuple[*offset] {
-                            Value::Number(s) => s as i64,
-                            _ => panic!("SUBTRACT can only be applied to numbers"),
-                        };
-
-                        result = result - subtrahend;
+        SimpleRelation {
+            symbols: symbols,
+            tuples: rel.tuples().flat_map(move |tuple| {
+                let result = arguments(&tuple, &key_offsets, &constants_local).and_then(|args| {
+                    match udf {
+                        Some(ref udf) => udf.apply(&args),
+                        None => function.apply(&args, regex.as_ref()),
                     }
+                });
 
-                    // subtrahends (constants)
-                    for (_key, val) in &constants_local {
-                        let subtrahend = match val {
-                            Value::Number(s) => *s as i64,
-                            _ => panic!("SUBTRACT can only be applied to numbers"),
-                        };
-
-                        result = result - subtrahend;
+                match result {
+                    Ok(result) => {
+                        let mut v = tuple.clone();
+                        v.push(result);
+                        Some(v)
                     }
-
-                    let mut v = tuple.clone();
-                    v.push(Value::Number(result));
-                    v
-                }),
-            },
+                    Err(error) => {
+                        debug!("Dropping tuple {:?} in {:?}: {}", tuple, function, error);
+                        None
+                    }
+                }
+            }),
         }
     }
 }
@@ -0,0 +1,156 @@
+//! Authentication of clients and authorization of their requests.
+
+use std::collections::HashMap;
+
+use server::Request;
+
+/// Identifies clients and decides which requests they may issue.
+/// Requests are checked before they are sequenced, s.t. denied
+/// requests never reach any worker.
+pub trait Guard {
+    /// Returns the principal identified by a credential, if any.
+    fn authenticate(&self, credential: &str) -> Option<String>;
+
+    /// Returns the principal of clients that did not authenticate, if
+    /// such clients are admitted at all.
+    fn anonymous(&self) -> Option<String>;
+
+    /// Checks whether a principal may issue the specified request.
+    fn authorize(&self, principal: &str, request: &Request) -> Result<(), String>;
+}
+
+/// A guard admitting every client to every request. Used unless a
+/// policy is configured.
+pub struct AllowAll;
+
+impl Guard for AllowAll {
+    fn authenticate(&self, _credential: &str) -> Option<String> {
+        Some("anonymous".to_string())
+    }
+
+    fn anonymous(&self) -> Option<String> {
+        Some("anonymous".to_string())
+    }
+
+    fn authorize(&self, _principal: &str, _request: &Request) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// What a principal may do. Names may end in `*` to match all names
+/// with the preceding prefix, s.t. `*` alone matches everything.
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct Permissions {
+    /// Attributes and inputs the principal may transact on.
+    #[serde(default)]
+    pub transact: Vec<String>,
+    /// Relations the principal may express interest in.
+    #[serde(default)]
+    pub subscribe: Vec<String>,
+    /// May the principal register rules, sources, and inputs?
+    #[serde(default)]
+    pub register: bool,
+}
+
+/// A static policy mapping tokens to principals and principals to
+/// their permissions, e.g.
+///
+/// ```json
+/// {
+///   "tokens": { "s3cr3t": "loader" },
+///   "principals": {
+///     "loader": { "transact": [":person/*"], "register": true },
+///     "viewer": { "subscribe": ["adults"] }
+///   },
+///   "anonymous": "viewer"
+/// }
+/// ```
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct Policy {
+    /// The principal identified by each token.
+    #[serde(default)]
+    pub tokens: HashMap<String, String>,
+    /// Permissions of each principal.
+    #[serde(default)]
+    pub principals: HashMap<String, Permissions>,
+    /// The principal of clients that did not authenticate, if they
+    /// are admitted.
+    #[serde(default)]
+    pub anonymous: Option<String>,
+}
+
+/// True iff one of the patterns matches the name.
+fn allows(patterns: &[String], name: &str) -> bool {
+    patterns.iter().any(|pattern| {
+        if pattern.ends_with('*') {
+            name.starts_with(&pattern[..pattern.len() - 1])
+        } else {
+            pattern == name
+        }
+    })
+}
+
+fn may_transact(principal: &str, permissions: &Permissions, name: &str) -> Result<(), String> {
+    if allows(&permissions.transact, name) {
+        Ok(())
+    } else {
+        Err(format!("{} may not transact on {:?}", principal, name))
+    }
+}
+
+fn may_subscribe(principal: &str, permissions: &Permissions, name: &str) -> Result<(), String> {
+    if allows(&permissions.subscribe, name) {
+        Ok(())
+    } else {
+        Err(format!("{} may not subscribe to {:?}", principal, name))
+    }
+}
+
+fn may_register(principal: &str, permissions: &Permissions) -> Result<(), String> {
+    if permissions.register {
+        Ok(())
+    } else {
+        Err(format!("{} may not register", principal))
+    }
+}
+
+impl Guard for Policy {
+    fn authenticate(&self, credential: &str) -> Option<String> {
+        self.tokens.get(credential).cloned()
+    }
+
+    fn anonymous(&self) -> Option<String> {
+        self.anonymous.clone()
+    }
+
+    fn authorize(&self, principal: &str, request: &Request) -> Result<(), String> {
+        let permissions = match self.principals.get(principal) {
+            None => return Err(format!("unknown principal {}", principal)),
+            Some(permissions) => permissions,
+        };
+
+        match request {
+            &Request::Datom(_, ref a, _, _, _) => may_transact(principal, permissions, a),
+            &Request::Transact(ref req) => req.tx_data
+                .iter()
+                .map(|datum| may_transact(principal, permissions, &datum.2))
+                .collect(),
+            &Request::Interest(ref req) => may_subscribe(principal, permissions, &req.name),
+            &Request::Lookup(ref req) => may_subscribe(principal, permissions, &req.query),
+            &Request::SetParams(ref req) => req.bindings
+                .iter()
+                .map(|binding| {
+                    let name = format!("{}/{}", req.query, binding.1);
+                    may_transact(principal, permissions, &name)
+                })
+                .collect(),
+            &Request::AdvanceInput(Some(ref name), _) => may_transact(principal, permissions, name),
+            &Request::AdvanceInput(None, _)
+            | &Request::Register(_)
+            | &Request::RegisterSource(_)
+            | &Request::CreateInput(_)
+            | &Request::CloseInput(_) => may_register(principal, permissions),
+            &Request::Disconnect => Ok(()),
+        }
+    }
+}
@@ -3,11 +3,15 @@
 extern crate differential_dataflow;
 extern crate timely;
 
-use std::collections::HashMap;
+pub mod auth;
+
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr};
 
 use timely::communication::Allocate;
 use timely::dataflow::scopes::Child;
 // use timely::dataflow::operators::Inspect;
+use timely::dataflow::operators::Probe;
 use timely::dataflow::ProbeHandle;
 use timely::worker::Worker;
 
@@ -17,26 +21,68 @@ use differential_dataflow::operators::arrange::ArrangeBySelf;
 use differential_dataflow::trace::TraceReader;
 use differential_dataflow::AsCollection;
 
+use functions::{FunctionRegistry, ScalarFunction};
+use plan::PredicateExpr;
 use sources::{Source, Sourceable};
-use {implement, Attribute, Entity, QueryMap, Rule, TraceKeyHandle, Value};
+use {implement, Attribute, Entity, Limits, QueryMap, Rule, TraceKeyHandle, Value};
 
 /// Server configuration.
 #[derive(Clone, Debug)]
 pub struct Config {
+    /// Address at which this server will listen.
+    pub bind_address: IpAddr,
     /// Port at which this server will listen at.
     pub port: u16,
+    /// Port at which this server will accept plain TCP connections,
+    /// if any.
+    pub tcp_port: Option<u16>,
+    /// Port at which this server will accept HTTP requests, if any.
+    pub http_port: Option<u16>,
     /// Should inputs via CLI be accepted?
     pub enable_cli: bool,
     /// Should as-of queries be possible?
     pub enable_history: bool,
+    /// Maximum number of results queued for a single client.
+    pub max_queued_results: usize,
+    /// What to do when a client exceeds its queue.
+    pub overflow_policy: OverflowPolicy,
 }
 
 impl Default for Config {
     fn default() -> Config {
         Config {
+            bind_address: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
             port: 6262,
+            tcp_port: None,
+            http_port: None,
             enable_cli: false,
             enable_history: false,
+            max_queued_results: 100_000,
+            overflow_policy: OverflowPolicy::Resync,
+        }
+    }
+}
+
+/// Policies for handling clients that fall behind on receiving
+/// results.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Drop queued results and ask the client to resubscribe.
+    Resync,
+    /// Disconnect the client.
+    Disconnect,
+    /// Stop accepting inputs until the client has caught up.
+    Block,
+}
+
+impl OverflowPolicy {
+    /// Parses a policy name, e.g. `resync`.
+    pub fn parse(name: &str) -> Option<OverflowPolicy> {
+        match name {
+            "resync" => Some(OverflowPolicy::Resync),
+            "disconnect" => Some(OverflowPolicy::Disconnect),
+            "block" => Some(OverflowPolicy::Block),
+            _ => None,
         }
     }
 }
@@ -50,10 +96,16 @@ pub struct TxData(pub isize, pub Entity, pub Attribute, pub Value);
 /// collections. Optionally a timestamp may be specified.
 #[derive(Deserialize, Debug)]
 pub struct Transact {
-    /// The timestamp at which this transaction occured.
+    /// The timestamp at which this transaction occured. Timestamps
+    /// that are sealed already, e.g. by parameter bindings, are
+    /// treated as the current epoch.
     pub tx: Option<u64>,
     /// A sequence of additions and retractions.
     pub tx_data: Vec<TxData>,
+    /// Names of published relations that should reflect this
+    /// transaction before it is acknowledged.
+    #[serde(default)]
+    pub wait_for: Vec<String>,
 }
 
 /// A request expressing interest in receiving results published under
@@ -62,6 +114,10 @@ pub struct Transact {
 pub struct Interest {
     /// The name of a previously registered dataflow.
     pub name: String,
+    /// An optional constraint on the tuples of interest, with symbols
+    /// referring to positions in the tuple.
+    #[serde(default)]
+    pub constraint: Option<PredicateExpr>,
 }
 
 /// A request with the intent of synthesising one or more new rules
@@ -72,6 +128,9 @@ pub struct Register {
     pub rules: Vec<Rule>,
     /// The names of rules that should be published.
     pub publish: Vec<String>,
+    /// Bounds on the evaluation of the rules.
+    #[serde(default)]
+    pub limits: Limits,
 }
 
 /// A request with the intent of attaching to an external data source
@@ -93,6 +152,31 @@ pub struct CreateInput {
     pub name: String,
 }
 
+/// A parameter binding. Conceptually a pair (binding, diff), kept
+/// flat like `TxData`.
+#[derive(Deserialize, Debug)]
+pub struct Binding(pub isize, pub String, pub Value);
+
+/// A request expressing changes to the values bound to the parameters
+/// of a registered query.
+#[derive(Deserialize, Debug)]
+pub struct SetParams {
+    /// The name of a registered rule using `Param` plans.
+    pub query: String,
+    /// A sequence of bindings and retractions of parameter values.
+    pub bindings: Vec<Binding>,
+}
+
+/// A request binding values to the lookup parameters of a registered
+/// query on behalf of the issuing client.
+#[derive(Deserialize, Debug)]
+pub struct Lookup {
+    /// The name of a registered rule using `Lookup` plans.
+    pub query: String,
+    /// A sequence of bindings and retractions of parameter values.
+    pub bindings: Vec<Binding>,
+}
+
 /// Possible request types.
 #[derive(Deserialize, Debug)]
 pub enum Request {
@@ -112,6 +196,82 @@ pub enum Request {
     AdvanceInput(Option<String>, u64),
     /// Closes a named input handle.
     CloseInput(String),
+    /// Binds values to the parameters of a registered query, as of
+    /// the current epoch, which is sealed.
+    SetParams(SetParams),
+    /// Binds values to the lookup parameters of a registered query
+    /// on behalf of the issuing client, as of the current epoch,
+    /// which is sealed.
+    Lookup(Lookup),
+    /// Retracts all lookup bindings of the issuing client, as of the
+    /// current epoch, which is sealed if any query serves lookups.
+    Disconnect,
+}
+
+/// Returns the value identifying a client connected to the specified
+/// worker in lookup parameters.
+pub fn client_key(owner: usize, client: usize) -> Value {
+    Value::Number(((owner as i64) << 32) | (client as i64 & 0xffff_ffff))
+}
+
+/// Returns the owning worker and client identified by a lookup
+/// parameter value.
+pub fn client_of(key: &Value) -> Option<(usize, usize)> {
+    match key {
+        &Value::Number(key) if key >= 0 => Some(((key >> 32) as usize, (key & 0xffff_ffff) as usize)),
+        _ => None,
+    }
+}
+
+/// Checks that all bindings refer to known parameters of the query.
+fn validate_bindings(
+    params: &HashMap<String, HashSet<String>>,
+    query: &str,
+    bindings: &[Binding],
+    kind: &str,
+) -> Result<(), String> {
+    let names = params
+        .get(query)
+        .ok_or_else(|| format!("Query {} has no {}s", query, kind))?;
+
+    for &Binding(_, ref param, _) in bindings.iter() {
+        if !names.contains(&format!("{}/{}", query, param)) {
+            return Err(format!("Unknown {} {} of query {}", kind, param, query));
+        }
+    }
+
+    Ok(())
+}
+
+/// Rules registered together, s.t. they may refer to each other. A
+/// program can be extended by subsequent registrations.
+#[derive(Clone, Debug)]
+struct Program {
+    rules: Vec<Rule>,
+    publish: Vec<String>,
+    limits: Limits,
+}
+
+impl Program {
+    /// True iff the program refers to any of the names, without
+    /// defining them itself.
+    fn reads_any(&self, names: &HashSet<String>) -> bool {
+        self.rules.iter().any(|rule| {
+            rule.plan
+                .dependencies()
+                .iter()
+                .any(|name| names.contains(name) && !self.rules.iter().any(|other| other.name == *name))
+        })
+    }
+}
+
+/// The stricter of two optional bounds.
+fn stricter<T: Ord>(x: Option<T>, y: Option<T>) -> Option<T> {
+    match (x, y) {
+        (Some(x), Some(y)) => Some(::std::cmp::min(x, y)),
+        (x, None) => x,
+        (None, y) => y,
+    }
 }
 
 /// Server context maintaining globally registered arrangements and
@@ -125,6 +285,20 @@ pub struct Server {
     pub global_arrangements: QueryMap<isize>,
     /// A probe for the transaction id time domain.
     pub probe: ProbeHandle<u64>,
+    /// Probes for each published relation.
+    pub probes: HashMap<String, ProbeHandle<u64>>,
+    /// User-defined functions available to `Transform` plans.
+    pub functions: FunctionRegistry,
+    /// Programs registered so far.
+    programs: Vec<Program>,
+    /// How often each relation has been published.
+    generations: HashMap<String, u64>,
+    /// Qualified names of the parameters of each query.
+    params: HashMap<String, HashSet<String>>,
+    /// Qualified names of the lookup parameters of each query.
+    lookup_params: HashMap<String, HashSet<String>>,
+    /// Lookup bindings per client, maintained by the owning worker.
+    lookups: HashMap<(usize, usize), HashMap<(String, Value), isize>>,
 }
 
 impl Server {
@@ -135,9 +309,27 @@ impl Server {
             input_handles: HashMap::new(),
             global_arrangements: HashMap::new(),
             probe: ProbeHandle::new(),
+            probes: HashMap::new(),
+            functions: HashMap::new(),
+            programs: Vec::new(),
+            generations: HashMap::new(),
+            params: HashMap::new(),
+            lookup_params: HashMap::new(),
+            lookups: HashMap::new(),
         }
     }
 
+    /// Registers a user-defined function under the specified name,
+    /// s.t. it can be referenced from subsequently registered
+    /// `Transform` plans.
+    pub fn register_function(&mut self, name: String, function: ScalarFunction) {
+        if self.functions.contains_key(&name) {
+            panic!("Function {} is already registered", name);
+        }
+
+        self.functions.insert(name, function);
+    }
+
     fn register_global_arrangement(
         &mut self,
         name: String,
@@ -150,6 +342,12 @@ impl Server {
         self.global_arrangements.insert(name, trace);
     }
 
+    /// Returns how often the named relation has been published, s.t.
+    /// consumers can tell whether it was replaced in the meantime.
+    pub fn generation(&self, name: &str) -> u64 {
+        self.generations.get(name).cloned().unwrap_or(0)
+    }
+
     /// Returns true iff the probe is behind any input handle. Mostly
     /// used as a convenience method during testing.
     pub fn is_any_outdated(&self) -> bool {
@@ -162,6 +360,16 @@ impl Server {
         false
     }
 
+    /// Returns the epoch inputs are currently accepting data at. All
+    /// earlier epochs are sealed.
+    pub fn epoch(&self) -> u64 {
+        self.input_handles
+            .values()
+            .map(|handle| handle.epoch())
+            .max()
+            .unwrap_or(0)
+    }
+
     /// Handle a Datom request.
     pub fn datom(
         &mut self,
@@ -184,9 +392,21 @@ impl Server {
         }
     }
 
-    /// Handle a Transact request.
-    pub fn transact(&mut self, req: Transact, owner: usize, worker_index: usize) {
-        let Transact { tx, tx_data } = req;
+    /// Returns true iff the results of the specified transaction are
+    /// complete in the named relation.
+    pub fn is_complete(&self, name: &str, tx: u64) -> bool {
+        let probe = self.probes
+            .get(name)
+            .expect(&format!("Could not find relation {:?}", name));
+
+        !probe.less_equal(&tx)
+    }
+
+    /// Handle a Transact request, returning the transaction the
+    /// inputs were introduced at.
+    pub fn transact(&mut self, req: Transact, owner: usize, worker_index: usize) -> u64 {
+        let Transact { tx, tx_data, .. } = req;
+        let assigned = self.epoch();
 
         if owner == worker_index {
             // only the owner should actually introduce new inputs
@@ -201,10 +421,20 @@ impl Server {
             }
         }
 
+        self.advance_inputs(tx);
+
+        assigned
+    }
+
+    /// Advances all inputs past the specified timestamp, or to their
+    /// next epoch if none is given (or the timestamp is sealed
+    /// already), and allows traces to compact if history is not
+    /// required.
+    fn advance_inputs(&mut self, tx: Option<u64>) {
         for handle in self.input_handles.values_mut() {
             let next_tx = match tx {
                 None => handle.epoch() + 1,
-                Some(tx) => tx + 1,
+                Some(tx) => ::std::cmp::max(tx, handle.epoch()) + 1,
             };
 
             handle.advance_to(next_tx);
@@ -228,6 +458,97 @@ impl Server {
         }
     }
 
+    /// Checks that a SetParams request only binds parameters of a
+    /// registered query.
+    pub fn validate_params(&self, req: &SetParams) -> Result<(), String> {
+        validate_bindings(&self.params, &req.query, &req.bindings, "parameter")
+    }
+
+    /// Handle a SetParams request. Bindings are introduced at the
+    /// current epoch, which is sealed s.t. they take effect right away.
+    pub fn set_params(&mut self, req: SetParams, owner: usize, worker_index: usize) {
+        let SetParams { query, bindings } = req;
+
+        if owner == worker_index {
+            // only the owner should actually introduce new inputs
+
+            for Binding(op, param, value) in bindings {
+                let name = format!("{}/{}", query, param);
+                let handle = self.input_handles
+                    .get_mut(&name)
+                    .expect(&format!("Parameter {} does not exist.", name));
+
+                handle.update(vec![value], op);
+            }
+        }
+
+        self.advance_inputs(None);
+    }
+
+    /// Checks that a Lookup request only binds lookup parameters of a
+    /// registered query.
+    pub fn validate_lookup(&self, req: &Lookup) -> Result<(), String> {
+        validate_bindings(&self.lookup_params, &req.query, &req.bindings, "lookup parameter")
+    }
+
+    /// Handle a Lookup request. Like parameter bindings, lookups take
+    /// effect right away.
+    pub fn lookup(&mut self, req: Lookup, owner: usize, client: usize, worker_index: usize) {
+        let Lookup { query, bindings } = req;
+
+        if owner == worker_index {
+            // only the owner should actually introduce new inputs
+
+            let key = client_key(owner, client);
+            let lookups = self.lookups.entry((owner, client)).or_insert_with(HashMap::new);
+
+            for Binding(op, param, value) in bindings {
+                let name = format!("{}/{}", query, param);
+                let handle = self.input_handles
+                    .get_mut(&name)
+                    .expect(&format!("Lookup parameter {} does not exist.", name));
+
+                handle.update(vec![key.clone(), value.clone()], op);
+
+                *lookups.entry((name, value)).or_insert(0) += op;
+            }
+
+            lookups.retain(|_, diff| *diff != 0);
+        }
+
+        self.advance_inputs(None);
+    }
+
+    /// Handle a Disconnect request. Like lookups, the retractions
+    /// take effect right away, sealing the current epoch if any query
+    /// serves lookups. Returns true iff the client had lookup bindings
+    /// to retract, which are complete along with the epoch that was
+    /// current before the request.
+    pub fn disconnect(&mut self, owner: usize, client: usize, worker_index: usize) -> bool {
+        let mut retracted = false;
+
+        if owner == worker_index {
+            if let Some(lookups) = self.lookups.remove(&(owner, client)) {
+                let key = client_key(owner, client);
+
+                for ((name, value), diff) in lookups.into_iter() {
+                    if let Some(handle) = self.input_handles.get_mut(&name) {
+                        handle.update(vec![key.clone(), value], -diff);
+                        retracted = true;
+                    }
+                }
+            }
+        }
+
+        // all workers have to agree on the epoch, thus the decision
+        // can't depend on the lookups of an individual client
+        if !self.lookup_params.is_empty() {
+            self.advance_inputs(None);
+        }
+
+        retracted
+    }
+
     /// Handle an Interest request.
     pub fn interest<'a, A: Allocate>(
         &mut self,
@@ -242,25 +563,178 @@ impl Server {
             .probe_with(&mut self.probe)
     }
 
+    /// Validates the rules of a registration, binding the
+    /// user-defined functions they refer to. `register` panics on
+    /// registrations failing validation, thus requests from clients
+    /// should be validated first.
+    pub fn validate(&self, req: &mut Register) -> Result<(), String> {
+        for rule in req.rules.iter_mut() {
+            self.validate_rule(rule)?;
+        }
+
+        Ok(())
+    }
+
+    fn validate_rule(&self, rule: &mut Rule) -> Result<(), String> {
+        rule.plan
+            .bind_functions(&self.functions)
+            .and_then(|_| rule.plan.validate())
+            .map_err(|msg| format!("Invalid rule {}: {}", rule.name, msg))
+    }
+
     /// Handle a Register request.
-    pub fn register<A: Allocate>(&mut self, req: Register, scope: &mut Child<Worker<A>, u64>) {
-        let Register { rules, publish } = req;
+    ///
+    /// Rules defining a name published by a previous registration
+    /// extend the program that published it, subject to the stricter
+    /// of both registrations' limits. Rules merely referring to such
+    /// a name (via `RuleExpr` or `NameExpr`) read the published
+    /// relation as is. The extended program is planned anew and its
+    /// published relations replace the previous ones. Programs reading
+    /// replaced relations are planned anew as well, in the order they
+    /// were registered in, while all other arrangements stay live.
+    /// Clients have to express interest in replaced relations again
+    /// in order to observe the extension.
+    ///
+    /// The probes and trace handles of replaced relations are dropped,
+    /// s.t. they no longer hold back compaction. Their dataflows can't
+    /// be shut down individually though, and keep maintaining the
+    /// replaced relations for as long as the server runs. Replacing
+    /// relations thus leaks the resources of their previous plans.
+    ///
+    /// `Param` plans are bound to an input per query and parameter
+    /// name, which is created on first use and subsequently updated
+    /// via `SetParams`. `Lookup` plans are bound likewise, but updated
+    /// via `Lookup` requests on behalf of individual clients. Queries
+    /// serving lookups should output the client symbol first, s.t.
+    /// results can be routed back to their clients.
+    ///
+    /// Returns a collection of error messages, one for each rule
+    /// failing for exceeding the registration's limits.
+    pub fn register<'a, A: Allocate>(
+        &mut self,
+        req: Register,
+        scope: &mut Child<'a, Worker<A>, u64>,
+    ) -> Collection<Child<'a, Worker<A>, u64>, String, isize> {
+        let Register {
+            mut rules,
+            mut publish,
+            limits,
+        } = req;
+
+        // Plans are validated before any part of them is implemented.
+        for rule in rules.iter_mut() {
+            if let Err(msg) = self.validate_rule(rule) {
+                panic!("{}", msg);
+            }
+        }
+
+        let defined: HashSet<String> = rules.iter().map(|rule| rule.name.clone()).collect();
+
+        let (extended, unaffected): (Vec<Program>, Vec<Program>) = self.programs
+            .drain(..)
+            .partition(|program| program.publish.iter().any(|name| defined.contains(name)));
+
+        self.programs = unaffected;
+
+        let mut limits = limits;
+        let mut replaced = HashSet::new();
+        for program in extended.into_iter() {
+            rules.extend(program.rules);
+
+            limits.max_iterations = stricter(limits.max_iterations, program.limits.max_iterations);
+            limits.max_tuples = stricter(limits.max_tuples, program.limits.max_tuples);
+
+            for name in program.publish.into_iter() {
+                if !publish.contains(&name) {
+                    publish.push(name.clone());
+                }
+
+                replaced.insert(name);
+            }
+        }
+
+        let program = Program { rules, publish, limits };
+        let mut failures = self.implement_program(&program, &replaced, scope);
+
+        // Dependents are registered after the programs they read
+        // from, s.t. a single pass in registration order suffices.
+        let mut stale = replaced;
+        for dependent in ::std::mem::replace(&mut self.programs, Vec::new()).into_iter() {
+            if dependent.reads_any(&stale) {
+                stale.extend(dependent.publish.iter().cloned());
+                failures = failures.concat(&self.implement_program(&dependent, &stale, scope));
+            }
+
+            self.programs.push(dependent);
+        }
 
-        let rel_map = implement(
+        self.programs.push(program);
+
+        failures
+    }
+
+    /// Plans a program and publishes its relations, replacing those
+    /// of the specified names.
+    fn implement_program<'a, A: Allocate>(
+        &mut self,
+        program: &Program,
+        replaced: &HashSet<String>,
+        scope: &mut Child<'a, Worker<A>, u64>,
+    ) -> Collection<Child<'a, Worker<A>, u64>, String, isize> {
+        let mut rules = program.rules.clone();
+
+        // Parameters are bound per query, each backed by an input
+        // that is shared by all registrations of the query.
+        for rule in rules.iter_mut() {
+            let params = rule.plan.qualify_params(&rule.name);
+            let lookups = rule.plan.lookups();
+
+            for param in params.into_iter() {
+                {
+                    let names = if lookups.contains(&param) {
+                        &mut self.lookup_params
+                    } else {
+                        &mut self.params
+                    };
+
+                    names
+                        .entry(rule.name.clone())
+                        .or_insert_with(HashSet::new)
+                        .insert(param.clone());
+                }
+
+                if !self.input_handles.contains_key(&param) {
+                    self.create_input(param, scope);
+                }
+            }
+        }
+
+        let (rel_map, failures) = implement(
             rules,
-            publish,
+            program.publish.clone(),
+            &program.limits,
             scope,
             &mut self.global_arrangements,
             &mut self.probe,
         );
 
         for (name, mut trace) in rel_map.into_iter() {
-            if self.global_arrangements.contains_key(&name) {
+            if self.global_arrangements.contains_key(&name) && !replaced.contains(&name) {
                 panic!("Attempted to re-register a named relation");
             } else {
+                let mut probe = ProbeHandle::new();
+                trace.import(scope).stream.probe_with(&mut probe);
+
+                // handles to replaced relations are dropped, s.t.
+                // their traces no longer hold back compaction, while
+                // the dataflows maintaining them keep running
+                self.probes.insert(name.clone(), probe);
+                *self.generations.entry(name.clone()).or_insert(0) += 1;
                 self.register_global_arrangement(name, trace);
             }
         }
+
+        failures
     }
 
     /// Handle a RegisterSource request.
@@ -272,8 +746,8 @@ impl Server {
         let RegisterSource { mut names, source } = req;
 
         if names.len() == 1 {
-            let name = names.pop().unwrap();
             let datoms = source.source(scope, names.clone()).as_collection();
+            let name = names.pop().unwrap();
 
             if self.global_arrangements.contains_key(&name) {
                 panic!("Source name clashes with registered relation.");
@@ -313,7 +787,11 @@ impl Server {
         if self.global_arrangements.contains_key(&name) {
             panic!("Input name clashes with existing trace.");
         } else {
-            let (handle, tuples) = scope.new_collection::<Vec<Value>, isize>();
+            // new inputs start out at the epoch of existing ones
+            let epoch = self.epoch();
+
+            let (mut handle, tuples) = scope.new_collection::<Vec<Value>, isize>();
+            handle.advance_to(epoch);
             let trace = tuples.arrange_by_self().trace;
 
             self.register_global_arrangement(name.clone(), trace);
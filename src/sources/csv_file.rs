@@ -9,7 +9,7 @@ use std::path::Path;
 use timely::dataflow::operators::generic;
 use timely::dataflow::{Scope, Stream};
 
-use {Value, Entity};
+use {Decimal, Entity, OrderedFloat, Value};
 
 use sources::Sourceable;
 
@@ -21,7 +21,8 @@ pub struct CsvFile {
     /// Separator to use.
     pub separator: char,
     /// Specifies the column offsets and their value types, that
-    /// should be introduced.
+    /// should be introduced. Empty fields are introduced as
+    /// `Value::Nil`.
     pub schema: Vec<(usize, Value)>,
 }
 
@@ -63,10 +64,22 @@ impl Sourceable for CsvFile {
 
                             for (name_idx, (offset, type_hint)) in schema.iter().enumerate() {
                                 let eid = Value::Eid(datum_index as Entity);
-                                let v = match type_hint {
-                                    Value::String(_) => Value::String(columns[*offset].trim().trim_matches('"').to_string()),
-                                    Value::Number(_) => Value::Number(columns[*offset].trim().trim_matches('"').parse::<i64>().expect("not a number")),
-                                    _ => panic!("Only String and Number are supported at the moment."),
+
+                                // Empty or missing fields are introduced as Nil.
+                                let field = columns.get(*offset)
+                                    .map(|column| column.trim().trim_matches('"'))
+                                    .unwrap_or("");
+
+                                let v = if field.is_empty() {
+                                    Value::Nil
+                                } else {
+                                    match type_hint {
+                                        Value::String(_) => Value::String(field.to_string()),
+                                        Value::Number(_) => Value::Number(field.parse::<i64>().expect("not a number")),
+                                        Value::Real(_) => Value::Real(OrderedFloat(field.parse::<f64>().expect("not a real"))),
+                                        Value::Decimal(_) => Value::Decimal(field.parse::<Decimal>().expect("not a decimal")),
+                                        _ => panic!("Only String, Number, Real, and Decimal are supported at the moment."),
+                                    }
                                 };
 
                                 session.give(((name_idx, vec![eid, v]), 0, 1));
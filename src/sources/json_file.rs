@@ -13,20 +13,27 @@ use timely::dataflow::{Scope, Stream};
 
 // use sources::json_file::flate2::read::GzDecoder;
 
-use {Value, Entity};
+use {Decimal, Entity, OrderedFloat, Value};
 
 use sources::Sourceable;
 
-/// A local filesystem data source containing JSON objects.
+/// A local filesystem data source containing JSON objects. Explicit
+/// `null` values are introduced as `Value::Nil`, absent keys are
+/// skipped.
 #[derive(Deserialize, Clone, Debug)]
 pub struct JsonFile {
     /// Path to a file on each workers local filesystem.
     pub path: String,
+    /// Should non-integer numbers be introduced as fixed-precision
+    /// decimals, rather than as floats?
+    #[serde(default)]
+    pub decimals: bool,
 }
 
 impl Sourceable for JsonFile {
     fn source<G: Scope>(&self, scope: &G, names: Vec<String>) -> Stream<G, ((usize, Vec<Value>), u64, isize)> {
         let filename = self.path.clone();
+        let decimals = self.decimals;
 
         generic::operator::source(scope, &format!("File({})", filename), move |capability| {
 
@@ -76,12 +83,14 @@ impl Sourceable for JsonFile {
                                             serde_json::Value::String(ref s) => Value::String(s.to_string()),
                                             serde_json::Value::Number(ref num) => {
                                                 match num.as_i64() {
-                                                    None => panic!("only i64 supported at the moment"),
                                                     Some(num) => Value::Number(num),
+                                                    None if decimals => Value::Decimal(num.to_string().parse::<Decimal>().expect("not a decimal")),
+                                                    None => Value::Real(OrderedFloat(num.as_f64().expect("not a real"))),
                                                 }
                                             },
                                             serde_json::Value::Bool(ref b) => Value::Bool(*b),
-                                            _ => panic!("only strings, booleans, and i64 types supported at the moment"),
+                                            serde_json::Value::Null => Value::Nil,
+                                            _ => panic!("only strings, booleans, null, and numbers supported at the moment"),
                                         };
 
                                         session.give(((name_idx, vec![Value::Eid(object_index as Entity), v]), 0, 1));
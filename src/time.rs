@@ -0,0 +1,314 @@
+//! Calendar arithmetic on instants.
+//!
+//! Instants are milliseconds since the epoch, UTC. Calendar-aware
+//! operations are carried out in the local time of a time zone, s.t.
+//! e.g. truncating to days yields local midnight. All operations
+//! return `None` if their result can not be represented as an
+//! instant, or does not exist in local time.
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+
+/// Units of time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Unit {
+    /// A millisecond
+    Millisecond,
+    /// A second
+    Second,
+    /// A minute
+    Minute,
+    /// An hour
+    Hour,
+    /// A (local) day
+    Day,
+    /// A (local) week
+    Week,
+    /// A calendar month
+    Month,
+    /// A calendar quarter
+    Quarter,
+    /// A calendar year
+    Year,
+}
+
+impl Unit {
+    /// Parses a unit keyword, e.g. `:month`.
+    pub fn parse(keyword: &str) -> Option<Unit> {
+        match keyword {
+            ":millisecond" => Some(Unit::Millisecond),
+            ":second" => Some(Unit::Second),
+            ":minute" => Some(Unit::Minute),
+            ":hour" => Some(Unit::Hour),
+            ":day" => Some(Unit::Day),
+            ":week" => Some(Unit::Week),
+            ":month" => Some(Unit::Month),
+            ":quarter" => Some(Unit::Quarter),
+            ":year" => Some(Unit::Year),
+            _ => None,
+        }
+    }
+
+    /// The width of fixed-width units in milliseconds.
+    fn width(&self) -> Option<i64> {
+        match self {
+            &Unit::Millisecond => Some(1),
+            &Unit::Second => Some(1000),
+            &Unit::Minute => Some(60000),
+            &Unit::Hour => Some(3600000),
+            &Unit::Day => Some(86400000),
+            &Unit::Week => Some(604800000),
+            _ => None,
+        }
+    }
+
+    /// The number of months in calendar units.
+    fn months(&self) -> Option<i64> {
+        match self {
+            &Unit::Month => Some(1),
+            &Unit::Quarter => Some(3),
+            &Unit::Year => Some(12),
+            _ => None,
+        }
+    }
+}
+
+/// Fields that can be extracted from an instant.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    /// The calendar year
+    Year,
+    /// The quarter of the year, starting at 1
+    Quarter,
+    /// The month of the year, starting at 1
+    Month,
+    /// The day of the month, starting at 1
+    Day,
+    /// The ISO 8601 day of the week, from 1 (Monday) to 7 (Sunday)
+    Weekday,
+    /// The hour of the day
+    Hour,
+    /// The minute of the hour
+    Minute,
+    /// The second of the minute
+    Second,
+}
+
+impl Field {
+    /// Parses a field keyword, e.g. `:weekday`.
+    pub fn parse(keyword: &str) -> Option<Field> {
+        match keyword {
+            ":year" => Some(Field::Year),
+            ":quarter" => Some(Field::Quarter),
+            ":month" => Some(Field::Month),
+            ":day" => Some(Field::Day),
+            ":weekday" => Some(Field::Weekday),
+            ":hour" => Some(Field::Hour),
+            ":minute" => Some(Field::Minute),
+            ":second" => Some(Field::Second),
+            _ => None,
+        }
+    }
+}
+
+fn to_utc(t: u64) -> Option<DateTime<Utc>> {
+    if t > i64::max_value() as u64 {
+        return None;
+    }
+
+    let nanos = (t % 1000) as u32 * 1_000_000;
+    Utc.timestamp_opt((t / 1000) as i64, nanos).single()
+}
+
+fn to_local(t: u64, tz: &Tz) -> Option<NaiveDateTime> {
+    Some(to_utc(t)?.with_timezone(tz).naive_local())
+}
+
+fn from_local(local: NaiveDateTime, tz: &Tz) -> Option<u64> {
+    let t = tz.from_local_datetime(&local).earliest()?.timestamp_millis();
+
+    if t < 0 {
+        None
+    } else {
+        Some(t as u64)
+    }
+}
+
+fn from_millis(t: i64) -> Option<NaiveDateTime> {
+    let seconds = if t < 0 && t % 1000 != 0 {
+        t / 1000 - 1
+    } else {
+        t / 1000
+    };
+    let nanos = (t - seconds * 1000) as u32 * 1_000_000;
+
+    NaiveDateTime::from_timestamp_opt(seconds, nanos)
+}
+
+/// Adds a number of months to a local time, clamping the day to the
+/// last of the resulting month.
+fn add_months(local: NaiveDateTime, months: i64) -> Option<NaiveDateTime> {
+    let total = (local.year() as i64 * 12 + local.month0() as i64).checked_add(months)?;
+    let (mut year, mut month0) = (total / 12, total % 12);
+    if month0 < 0 {
+        year -= 1;
+        month0 += 12;
+    }
+
+    if year < i32::min_value() as i64 || year > i32::max_value() as i64 {
+        return None;
+    }
+
+    let mut day = local.day();
+    loop {
+        if let Some(date) = NaiveDate::from_ymd_opt(year as i32, month0 as u32 + 1, day) {
+            return Some(date.and_time(local.time()));
+        } else if day <= 28 {
+            return None;
+        }
+
+        day -= 1;
+    }
+}
+
+/// Truncates an instant to the start of its unit of time. Weeks start
+/// on local Monday midnight, other fixed-width units are aligned to
+/// the epoch in local time, and calendar units to the start of the
+/// local month, quarter, or year.
+pub fn truncate(t: u64, unit: Unit, tz: &Tz) -> Option<u64> {
+    let local = to_local(t, tz)?;
+
+    let truncated = match unit.width() {
+        Some(_) if unit == Unit::Week => {
+            let monday = local.date() - Duration::days(local.weekday().num_days_from_monday() as i64);
+            monday.and_hms(0, 0, 0)
+        }
+        Some(width) => {
+            let millis = local.timestamp_millis();
+            let remainder = ((millis % width) + width) % width;
+            from_millis(millis - remainder)?
+        }
+        None => {
+            let month0 = match unit {
+                Unit::Month => local.month0(),
+                Unit::Quarter => local.month0() / 3 * 3,
+                _ => 0,
+            };
+
+            NaiveDate::from_ymd_opt(local.year(), month0 + 1, 1)?.and_hms(0, 0, 0)
+        }
+    };
+
+    from_local(truncated, tz)
+}
+
+/// Adds an amount of units to an instant. Hours and smaller units are
+/// added in absolute time, days and larger units in local time.
+pub fn add(t: u64, amount: i64, unit: Unit, tz: &Tz) -> Option<u64> {
+    match unit {
+        Unit::Millisecond | Unit::Second | Unit::Minute | Unit::Hour => {
+            let delta = amount.checked_mul(unit.width()?)?;
+            let t = (t as i128) + (delta as i128);
+
+            if t < 0 || t > i64::max_value() as i128 {
+                None
+            } else {
+                Some(t as u64)
+            }
+        }
+        Unit::Day | Unit::Week => {
+            let days = amount.checked_mul(if unit == Unit::Week { 7 } else { 1 })?;
+            if days.checked_abs()? > 1_000_000_000 {
+                return None;
+            }
+
+            let local = to_local(t, tz)?.checked_add_signed(Duration::days(days))?;
+            from_local(local, tz)
+        }
+        Unit::Month | Unit::Quarter | Unit::Year => {
+            let months = amount.checked_mul(unit.months()?)?;
+            from_local(add_months(to_local(t, tz)?, months)?, tz)
+        }
+    }
+}
+
+/// Returns the number of whole units from the first instant to the
+/// second, negative if the second precedes the first.
+pub fn diff(from: u64, to: u64, unit: Unit, tz: &Tz) -> Option<i64> {
+    match unit {
+        Unit::Millisecond | Unit::Second | Unit::Minute | Unit::Hour => {
+            let delta = (to as i128) - (from as i128);
+            let units = delta / unit.width()? as i128;
+
+            if units < i64::min_value() as i128 || units > i64::max_value() as i128 {
+                None
+            } else {
+                Some(units as i64)
+            }
+        }
+        Unit::Day | Unit::Week => {
+            let days = to_local(to, tz)?
+                .signed_duration_since(to_local(from, tz)?)
+                .num_days();
+
+            Some(if unit == Unit::Week { days / 7 } else { days })
+        }
+        Unit::Month | Unit::Quarter | Unit::Year => {
+            let from = to_local(from, tz)?;
+            let to = to_local(to, tz)?;
+
+            let mut months = (to.year() as i64 * 12 + to.month0() as i64)
+                - (from.year() as i64 * 12 + from.month0() as i64);
+
+            // only whole months count
+            if months > 0 && add_months(from, months)? > to {
+                months -= 1;
+            } else if months < 0 && add_months(from, months)? < to {
+                months += 1;
+            }
+
+            Some(months / unit.months()?)
+        }
+    }
+}
+
+/// Extracts a field from the local time of an instant.
+pub fn part(t: u64, field: Field, tz: &Tz) -> Option<i64> {
+    let local = to_local(t, tz)?;
+
+    let value = match field {
+        Field::Year => local.year() as i64,
+        Field::Quarter => (local.month0() / 3 + 1) as i64,
+        Field::Month => local.month() as i64,
+        Field::Day => local.day() as i64,
+        Field::Weekday => local.weekday().number_from_monday() as i64,
+        Field::Hour => local.hour() as i64,
+        Field::Minute => local.minute() as i64,
+        Field::Second => local.second() as i64,
+    };
+
+    Some(value)
+}
+
+/// Parses an ISO 8601 date or date-time. Strings without an offset
+/// are interpreted in the local time of the given time zone.
+pub fn parse(string: &str, tz: &Tz) -> Option<u64> {
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(string) {
+        let t = datetime.timestamp_millis();
+        return if t < 0 { None } else { Some(t as u64) };
+    }
+
+    let local = NaiveDateTime::parse_from_str(string, "%Y-%m-%dT%H:%M:%S%.f")
+        .or_else(|_| NaiveDateTime::parse_from_str(string, "%Y-%m-%d %H:%M:%S%.f"))
+        .or_else(|_| NaiveDate::parse_from_str(string, "%Y-%m-%d").map(|date| date.and_hms(0, 0, 0)))
+        .ok()?;
+
+    from_local(local, tz)
+}
+
+/// Formats an instant as an ISO 8601 date-time with millisecond
+/// precision, in the local time of the given time zone.
+pub fn format(t: u64, tz: &Tz) -> Option<String> {
+    let datetime = to_utc(t)?.with_timezone(tz);
+    Some(datetime.format("%Y-%m-%dT%H:%M:%S%.3f%:z").to_string())
+}
@@ -7,9 +7,9 @@ use std::thread;
 
 use timely::Configuration;
 
-use declarative_dataflow::plan::{Aggregate, AggregationFn, Join, Project};
+use declarative_dataflow::plan::{Aggregate, AggregationFn, EmptyGroups, Join, Project};
 use declarative_dataflow::server::{Register, Server, Transact, TxData};
-use declarative_dataflow::{Plan, Rule, Value};
+use declarative_dataflow::{Decimal, OrderedFloat, Plan, Rule, Value};
 
 use num_rational::Ratio;
 
@@ -33,6 +33,7 @@ fn count() {
             key_symbols: vec![],
             aggregation_symbols: vec![amount],
             with_symbols: vec![],
+            empty_groups: EmptyGroups::Identity,
         });
 
         // [:find ?e (count ?amount) :where [?e :amount ?amount]]
@@ -44,6 +45,7 @@ fn count() {
             key_symbols: vec![e],
             aggregation_symbols: vec![amount],
             with_symbols: vec![],
+            empty_groups: EmptyGroups::Identity,
         });
 
         worker.dataflow::<u64, _, _>(|mut scope| {
@@ -57,6 +59,7 @@ fn count() {
                         plan: plan,
                     }],
                     publish: vec![query_name.to_string()],
+                    limits: Default::default(),
                 },
                 &mut scope,
             );
@@ -75,6 +78,7 @@ fn count() {
                         plan: plan_group,
                     }],
                     publish: vec![query_name.to_string()],
+                    limits: Default::default(),
                 },
                 &mut scope,
             );
@@ -98,6 +102,7 @@ fn count() {
                     TxData(1, 1, ":amount".to_string(), Value::Number(4)),
                     TxData(1, 1, ":amount".to_string(), Value::Number(6)),
                 ],
+                wait_for: vec![],
             },
             0,
             0,
@@ -139,6 +144,7 @@ fn max() {
             key_symbols: vec![],
             aggregation_symbols: vec![amount],
             with_symbols: vec![],
+            empty_groups: EmptyGroups::Identity,
         });
 
         // [:find ?e (max ?amount) :where [?e :amount ?amount]]
@@ -149,6 +155,7 @@ fn max() {
             key_symbols: vec![e],
             aggregation_symbols: vec![amount],
             with_symbols: vec![],
+            empty_groups: EmptyGroups::Identity,
         });
 
         worker.dataflow::<u64, _, _>(|mut scope| {
@@ -162,6 +169,7 @@ fn max() {
                         plan: plan,
                     }],
                     publish: vec![query_name.to_string()],
+                    limits: Default::default(),
                 },
                 &mut scope,
             );
@@ -180,6 +188,7 @@ fn max() {
                         plan: plan_group,
                     }],
                     publish: vec![query_name.to_string()],
+                    limits: Default::default(),
                 },
                 &mut scope,
             );
@@ -202,6 +211,7 @@ fn max() {
                     TxData(1, 1, ":amount".to_string(), Value::Number(4)),
                     TxData(1, 1, ":amount".to_string(), Value::Number(6)),
                 ],
+                wait_for: vec![],
             },
             0,
             0,
@@ -243,6 +253,7 @@ fn min() {
             key_symbols: vec![],
             aggregation_symbols: vec![amount],
             with_symbols: vec![],
+            empty_groups: EmptyGroups::Identity,
         });
 
         // [:find ?e (min ?amount) :where [?e :amount ?amount]]
@@ -253,6 +264,7 @@ fn min() {
             key_symbols: vec![e],
             aggregation_symbols: vec![amount],
             with_symbols: vec![],
+            empty_groups: EmptyGroups::Identity,
         });
 
         worker.dataflow::<u64, _, _>(|mut scope| {
@@ -266,6 +278,7 @@ fn min() {
                         plan: plan,
                     }],
                     publish: vec![query_name.to_string()],
+                    limits: Default::default(),
                 },
                 &mut scope,
             );
@@ -284,6 +297,7 @@ fn min() {
                         plan: plan_group,
                     }],
                     publish: vec![query_name.to_string()],
+                    limits: Default::default(),
                 },
                 &mut scope,
             );
@@ -306,6 +320,7 @@ fn min() {
                     TxData(1, 1, ":amount".to_string(), Value::Number(4)),
                     TxData(1, 1, ":amount".to_string(), Value::Number(6)),
                 ],
+                wait_for: vec![],
             },
             0,
             0,
@@ -347,6 +362,7 @@ fn sum() {
             key_symbols: vec![],
             aggregation_symbols: vec![amount],
             with_symbols: vec![],
+            empty_groups: EmptyGroups::Identity,
         });
 
         // [:find ?e (sum ?amount) :where [?e :amount ?amount]]
@@ -357,6 +373,7 @@ fn sum() {
             key_symbols: vec![e],
             aggregation_symbols: vec![amount],
             with_symbols: vec![],
+            empty_groups: EmptyGroups::Identity,
         });
 
         worker.dataflow::<u64, _, _>(|mut scope| {
@@ -370,6 +387,7 @@ fn sum() {
                         plan: plan,
                     }],
                     publish: vec![query_name.to_string()],
+                    limits: Default::default(),
                 },
                 &mut scope,
             );
@@ -388,6 +406,7 @@ fn sum() {
                         plan: plan_group,
                     }],
                     publish: vec![query_name.to_string()],
+                    limits: Default::default(),
                 },
                 &mut scope,
             );
@@ -410,6 +429,7 @@ fn sum() {
                     TxData(1, 1, ":amount".to_string(), Value::Number(4)),
                     TxData(1, 1, ":amount".to_string(), Value::Number(6)),
                 ],
+                wait_for: vec![],
             },
             0,
             0,
@@ -451,6 +471,7 @@ fn avg() {
             key_symbols: vec![],
             aggregation_symbols: vec![amount],
             with_symbols: vec![],
+            empty_groups: EmptyGroups::Identity,
         });
 
         // [:find ?e (avg ?amount) :where [?e :amount ?amount]]
@@ -461,6 +482,7 @@ fn avg() {
             key_symbols: vec![e],
             aggregation_symbols: vec![amount],
             with_symbols: vec![],
+            empty_groups: EmptyGroups::Identity,
         });
 
         worker.dataflow::<u64, _, _>(|mut scope| {
@@ -474,6 +496,7 @@ fn avg() {
                         plan: plan,
                     }],
                     publish: vec![query_name.to_string()],
+                    limits: Default::default(),
                 },
                 &mut scope,
             );
@@ -492,6 +515,7 @@ fn avg() {
                         plan: plan_group,
                     }],
                     publish: vec![query_name.to_string()],
+                    limits: Default::default(),
                 },
                 &mut scope,
             );
@@ -514,6 +538,7 @@ fn avg() {
                     TxData(1, 1, ":amount".to_string(), Value::Number(4)),
                     TxData(1, 1, ":amount".to_string(), Value::Number(6)),
                 ],
+                wait_for: vec![],
             },
             0,
             0,
@@ -524,126 +549,135 @@ fn avg() {
         thread::spawn(move || {
             assert_eq!(
                 results.recv().unwrap(),
-                (vec![Value::Eid(1), Value::Rational32(Ratio::new(17, 4))], 1)
+                (vec![Value::Eid(1), Value::Rational64(Ratio::new(17, 4))], 1)
             );
             assert_eq!(
                 results.recv().unwrap(),
-                (vec![Value::Eid(2), Value::Rational32(Ratio::new(10, 1))], 1)
+                (vec![Value::Eid(2), Value::Rational64(Ratio::new(10, 1))], 1)
             );
             assert_eq!(
                 results.recv().unwrap(),
-                (vec![Value::Rational32(Ratio::new(27, 5))], 1)
+                (vec![Value::Rational64(Ratio::new(27, 5))], 1)
             );
         }).join()
             .unwrap();
     }).unwrap();
 }
 
-// #[test]
-// fn var() {
-//     timely::execute(Configuration::Thread, move |worker| {
-//         let mut server = Server::new(Default::default());
-//         let (send_results, results) = channel();
-//         let send_results_copy = send_results.clone();
-
-//         // [:find (variance ?amount) :where [?e :amount ?amount]]
-//         let (e, amount) = (1, 2);
-//         let plan = Plan::Aggregate(Aggregate {
-//             variables: vec![e, amount, amount],
-//             plan: Box::new(Plan::Project(Project {
-//                 variables: vec![amount],
-//                 plan: Box::new(Plan::MatchA(e, ":amount".to_string(), amount)),
-//             })),
-//             aggregation_fns: vec![AggregationFn::VARIANCE],
-//             key_symbols: vec![],
-//         });
-
-//         // [:find ?e (variance ?amount) :where [?e :amount ?amount]]
-//         let plan_group = Plan::Aggregate(Aggregate {
-//             variables: vec![e, amount],
-//             plan: Box::new(Plan::MatchA(e, ":amount".to_string(), amount)),
-//             aggregation_fn: AggregationFn::VARIANCE,
-//             key_symbols: vec![e],
-//         });
-
-//         worker.dataflow::<u64, _, _>(|mut scope| {
-//             server.create_input(":amount".to_string(), scope);
-
-//             let query_name = "var";
-//             server.register(
-//                 Register {
-//                     rules: vec![Rule {
-//                         name: query_name.to_string(),
-//                         plan: plan,
-//                     }],
-//                     publish: vec![query_name.to_string()],
-//                 },
-//                 &mut scope,
-//             );
-
-//             server
-//                 .interest(query_name.to_string(), &mut scope)
-//                 .inspect(move |x| {
-//                     send_results.send((x.0.clone(), x.2)).unwrap();
-//                 });
-
-//             let query_name = "var_group";
-//             server.register(
-//                 Register {
-//                     rules: vec![Rule {
-//                         name: query_name.to_string(),
-//                         plan: plan_group,
-//                     }],
-//                     publish: vec![query_name.to_string()],
-//                 },
-//                 &mut scope,
-//             );
-
-//             server
-//                 .interest(query_name.to_string(), &mut scope)
-//                 .inspect(move |x| {
-//                     send_results_copy.send((x.0.clone(), x.2)).unwrap();
-//                 });
-//         });
-
-//         server.transact(
-//             Transact {
-//                 tx: Some(0),
-//                 tx_data: vec![
-//                     TxData(1, 1, ":amount".to_string(), Value::Number(5)),
-//                     TxData(1, 2, ":amount".to_string(), Value::Number(10)),
-//                     TxData(1, 2, ":amount".to_string(), Value::Number(10)),
-//                     TxData(1, 1, ":amount".to_string(), Value::Number(2)),
-//                     TxData(1, 1, ":amount".to_string(), Value::Number(4)),
-//                     TxData(1, 1, ":amount".to_string(), Value::Number(6)),
-//                 ],
-//             },
-//             0,
-//             0,
-//         );
-
-//         worker.step_while(|| server.is_any_outdated());
-
-//         thread::spawn(move || {
-//             assert_eq!(
-//                 results.recv().unwrap(),
-//                 (
-//                     vec![Value::Eid(1), Value::Rational32(Ratio::new(35, 16))],
-//                     1
-//                 )
-//             );
-//             assert_eq!(
-//                 results.recv().unwrap(),
-//                 (vec![Value::Eid(2), Value::Rational32(Ratio::new(0, 1))], 1)
-//             );
-//             assert_eq!(
-//                 results.recv().unwrap(),
-//                 (vec![Value::Rational32(Ratio::new(176, 25))], 1)
-//             );
-//         }).join()
-//             .unwrap();
-//     }).unwrap();
-// }
+#[test]
+fn var() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::new(Default::default());
+        let (send_results, results) = channel();
+        let send_results_copy = send_results.clone();
+
+        // [:find (variance ?amount) :where [?e :amount ?amount]]
+        let (e, amount) = (1, 2);
+        let plan = Plan::Aggregate(Aggregate {
+            variables: vec![amount],
+            plan: Box::new(Plan::Project(Project {
+                variables: vec![amount],
+                plan: Box::new(Plan::MatchA(e, ":amount".to_string(), amount)),
+            })),
+            aggregation_fns: vec![AggregationFn::VARIANCE],
+            key_symbols: vec![],
+            aggregation_symbols: vec![amount],
+            with_symbols: vec![],
+            empty_groups: EmptyGroups::Identity,
+        });
+
+        // [:find ?e (variance ?amount) :where [?e :amount ?amount]]
+        let plan_group = Plan::Aggregate(Aggregate {
+            variables: vec![e, amount],
+            plan: Box::new(Plan::MatchA(e, ":amount".to_string(), amount)),
+            aggregation_fns: vec![AggregationFn::VARIANCE],
+            key_symbols: vec![e],
+            aggregation_symbols: vec![amount],
+            with_symbols: vec![],
+            empty_groups: EmptyGroups::Identity,
+        });
+
+        worker.dataflow::<u64, _, _>(|mut scope| {
+            server.create_input(":amount".to_string(), scope);
+
+            let query_name = "var";
+            server.register(
+                Register {
+                    rules: vec![Rule {
+                        name: query_name.to_string(),
+                        plan: plan,
+                    }],
+                    publish: vec![query_name.to_string()],
+                    limits: Default::default(),
+                },
+                &mut scope,
+            );
+
+            server
+                .interest(query_name.to_string(), &mut scope)
+                .inspect(move |x| {
+                    send_results.send((x.0.clone(), x.2)).unwrap();
+                });
+
+            let query_name = "var_group";
+            server.register(
+                Register {
+                    rules: vec![Rule {
+                        name: query_name.to_string(),
+                        plan: plan_group,
+                    }],
+                    publish: vec![query_name.to_string()],
+                    limits: Default::default(),
+                },
+                &mut scope,
+            );
+
+            server
+                .interest(query_name.to_string(), &mut scope)
+                .inspect(move |x| {
+                    send_results_copy.send((x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        server.transact(
+            Transact {
+                tx: Some(0),
+                tx_data: vec![
+                    TxData(1, 1, ":amount".to_string(), Value::Number(5)),
+                    TxData(1, 2, ":amount".to_string(), Value::Number(10)),
+                    TxData(1, 2, ":amount".to_string(), Value::Number(10)),
+                    TxData(1, 1, ":amount".to_string(), Value::Number(2)),
+                    TxData(1, 1, ":amount".to_string(), Value::Number(4)),
+                    TxData(1, 1, ":amount".to_string(), Value::Number(6)),
+                ],
+                wait_for: vec![],
+            },
+            0,
+            0,
+        );
+
+        worker.step_while(|| server.is_any_outdated());
+
+        thread::spawn(move || {
+            assert_eq!(
+                results.recv().unwrap(),
+                (
+                    vec![Value::Eid(1), Value::Rational64(Ratio::new(35, 16))],
+                    1
+                )
+            );
+            assert_eq!(
+                results.recv().unwrap(),
+                (vec![Value::Eid(2), Value::Rational64(Ratio::new(0, 1))], 1)
+            );
+            assert_eq!(
+                results.recv().unwrap(),
+                (vec![Value::Rational64(Ratio::new(176, 25))], 1)
+            );
+        }).join()
+            .unwrap();
+    }).unwrap();
+}
 
 #[test]
 fn median() {
@@ -664,6 +698,7 @@ fn median() {
             key_symbols: vec![],
             aggregation_symbols: vec![amount],
             with_symbols: vec![],
+            empty_groups: EmptyGroups::Identity,
         });
 
         // [:find ?e (median ?amount) :where [?e :amount ?amount]]
@@ -674,6 +709,7 @@ fn median() {
             key_symbols: vec![e],
             aggregation_symbols: vec![amount],
             with_symbols: vec![],
+            empty_groups: EmptyGroups::Identity,
         });
 
         worker.dataflow::<u64, _, _>(|mut scope| {
@@ -687,6 +723,7 @@ fn median() {
                         plan: plan,
                     }],
                     publish: vec![query_name.to_string()],
+                    limits: Default::default(),
                 },
                 &mut scope,
             );
@@ -705,6 +742,7 @@ fn median() {
                         plan: plan_group,
                     }],
                     publish: vec![query_name.to_string()],
+                    limits: Default::default(),
                 },
                 &mut scope,
             );
@@ -727,6 +765,7 @@ fn median() {
                     TxData(1, 1, ":amount".to_string(), Value::Number(4)),
                     TxData(1, 1, ":amount".to_string(), Value::Number(6)),
                 ],
+                wait_for: vec![],
             },
             0,
             0,
@@ -779,6 +818,7 @@ fn multi() {
             key_symbols: vec![],
             aggregation_symbols: vec![amount, debt, amount, debt],
             with_symbols: vec![],
+            empty_groups: EmptyGroups::Identity,
         });
 
         // [:find ?e (min ?amount) (max ?amount) (median ?amount) (count ?amount) (min ?debt)
@@ -806,6 +846,7 @@ fn multi() {
             key_symbols: vec![e],
             aggregation_symbols: vec![amount, amount, amount, amount, debt, debt, debt, debt],
             with_symbols: vec![],
+            empty_groups: EmptyGroups::Identity,
         });
 
         worker.dataflow::<u64, _, _>(|mut scope| {
@@ -820,6 +861,7 @@ fn multi() {
                         plan: plan,
                     }],
                     publish: vec![query_name.to_string()],
+                    limits: Default::default(),
                 },
                 &mut scope,
             );
@@ -838,6 +880,7 @@ fn multi() {
                         plan: plan_group,
                     }],
                     publish: vec![query_name.to_string()],
+                    limits: Default::default(),
                 },
                 &mut scope,
             );
@@ -868,6 +911,7 @@ fn multi() {
                     TxData(1, 2, ":debt".to_string(), Value::Number(5)),
                     TxData(1, 2, ":debt".to_string(), Value::Number(42)),
                 ],
+                wait_for: vec![],
             },
             0,
             0,
@@ -883,7 +927,7 @@ fn multi() {
                         Value::Number(10),
                         Value::Number(4),
                         Value::Number(36),
-                        Value::Rational32(Ratio::new(14, 1)),
+                        Value::Rational64(Ratio::new(14, 1)),
                     ],
                     1
                 )
@@ -952,6 +996,7 @@ fn with() {
             key_symbols: vec![],
             aggregation_symbols: vec![heads],
             with_symbols: vec![monster],
+            empty_groups: EmptyGroups::Identity,
         });
 
         worker.dataflow::<u64, _, _>(|mut scope| {
@@ -966,6 +1011,7 @@ fn with() {
                         plan: plan,
                     }],
                     publish: vec![query_name.to_string()],
+                    limits: Default::default(),
                 },
                 &mut scope,
             );
@@ -990,6 +1036,7 @@ fn with() {
                     TxData(1, 4, ":monster".to_string(), Value::String("Chimera".to_string())),
                     TxData(1, 4, ":heads".to_string(), Value::Number(1)),
                 ],
+                wait_for: vec![],
             },
             0,
             0,
@@ -1011,3 +1058,816 @@ fn with() {
             .unwrap();
     }).unwrap();
 }
+
+#[test]
+fn nil() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::new(Default::default());
+        let (send_results, results) = channel();
+
+        // [:find ?e (sum ?amount) (count ?amount) :where [?e :amount ?amount]]
+        let (e, amount) = (1, 2);
+        let plan = Plan::Aggregate(Aggregate {
+            variables: vec![e, amount, amount],
+            plan: Box::new(Plan::MatchA(e, ":amount".to_string(), amount)),
+            aggregation_fns: vec![AggregationFn::SUM, AggregationFn::COUNT],
+            key_symbols: vec![e],
+            aggregation_symbols: vec![amount, amount],
+            with_symbols: vec![],
+            empty_groups: EmptyGroups::Identity,
+        });
+
+        worker.dataflow::<u64, _, _>(|mut scope| {
+            server.create_input(":amount".to_string(), scope);
+
+            let query_name = "nil";
+            server.register(
+                Register {
+                    rules: vec![Rule {
+                        name: query_name.to_string(),
+                        plan: plan,
+                    }],
+                    publish: vec![query_name.to_string()],
+                    limits: Default::default(),
+                },
+                &mut scope,
+            );
+
+            server
+                .interest(query_name.to_string(), &mut scope)
+                .inspect(move |x| {
+                    send_results.send((x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        server.transact(
+            Transact {
+                tx: Some(0),
+                tx_data: vec![
+                    TxData(1, 1, ":amount".to_string(), Value::Number(5)),
+                    TxData(1, 1, ":amount".to_string(), Value::Nil),
+                    TxData(1, 1, ":amount".to_string(), Value::Number(2)),
+                    TxData(1, 2, ":amount".to_string(), Value::Nil),
+                ],
+                wait_for: vec![],
+            },
+            0,
+            0,
+        );
+
+        worker.step_while(|| server.is_any_outdated());
+
+        thread::spawn(move || {
+            assert_eq!(
+                results.recv().unwrap(),
+                (vec![Value::Eid(1), Value::Number(7), Value::Number(2)], 1)
+            );
+            assert!(results.try_recv().is_err());
+        }).join()
+            .unwrap();
+    }).unwrap();
+}
+
+#[test]
+fn real_and_decimal() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::new(Default::default());
+        let (send_results, results) = channel();
+
+        // [:find ?e (sum ?amount) (avg ?amount) :where [?e :amount ?amount]]
+        let (e, amount) = (1, 2);
+        let plan = Plan::Aggregate(Aggregate {
+            variables: vec![e, amount, amount],
+            plan: Box::new(Plan::MatchA(e, ":amount".to_string(), amount)),
+            aggregation_fns: vec![AggregationFn::SUM, AggregationFn::AVG],
+            key_symbols: vec![e],
+            aggregation_symbols: vec![amount, amount],
+            with_symbols: vec![],
+            empty_groups: EmptyGroups::Identity,
+        });
+
+        worker.dataflow::<u64, _, _>(|mut scope| {
+            server.create_input(":amount".to_string(), scope);
+
+            let query_name = "real_and_decimal";
+            server.register(
+                Register {
+                    rules: vec![Rule {
+                        name: query_name.to_string(),
+                        plan: plan,
+                    }],
+                    publish: vec![query_name.to_string()],
+                    limits: Default::default(),
+                },
+                &mut scope,
+            );
+
+            server
+                .interest(query_name.to_string(), &mut scope)
+                .inspect(move |x| {
+                    send_results.send((x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        server.transact(
+            Transact {
+                tx: Some(0),
+                tx_data: vec![
+                    TxData(1, 1, ":amount".to_string(), Value::Real(OrderedFloat(1.5))),
+                    TxData(1, 1, ":amount".to_string(), Value::Real(OrderedFloat(2.5))),
+                    TxData(1, 2, ":amount".to_string(), Value::Decimal(Decimal::new(110, 2))),
+                    TxData(1, 2, ":amount".to_string(), Value::Decimal(Decimal::new(220, 2))),
+                ],
+                wait_for: vec![],
+            },
+            0,
+            0,
+        );
+
+        worker.step_while(|| server.is_any_outdated());
+
+        thread::spawn(move || {
+            assert_eq!(
+                results.recv().unwrap(),
+                (
+                    vec![
+                        Value::Eid(1),
+                        Value::Real(OrderedFloat(4.0)),
+                        Value::Real(OrderedFloat(2.0)),
+                    ],
+                    1
+                )
+            );
+            assert_eq!(
+                results.recv().unwrap(),
+                (
+                    vec![
+                        Value::Eid(2),
+                        Value::Decimal(Decimal::new(330, 2)),
+                        Value::Decimal(Decimal::new(165, 2)),
+                    ],
+                    1
+                )
+            );
+        }).join()
+            .unwrap();
+    }).unwrap();
+}
+
+#[test]
+fn mixed_numbers() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::new(Default::default());
+        let (send_results, results) = channel();
+
+        // [:find ?e (sum ?amount) (avg ?amount) :where [?e :amount ?amount]]
+        let (e, amount) = (1, 2);
+        let plan = Plan::Aggregate(Aggregate {
+            variables: vec![e, amount, amount],
+            plan: Box::new(Plan::MatchA(e, ":amount".to_string(), amount)),
+            aggregation_fns: vec![AggregationFn::SUM, AggregationFn::AVG],
+            key_symbols: vec![e],
+            aggregation_symbols: vec![amount, amount],
+            with_symbols: vec![],
+            empty_groups: EmptyGroups::Retract,
+        });
+
+        worker.dataflow::<u64, _, _>(|mut scope| {
+            server.create_input(":amount".to_string(), scope);
+
+            let query_name = "mixed_numbers";
+            server.register(
+                Register {
+                    rules: vec![Rule {
+                        name: query_name.to_string(),
+                        plan: plan,
+                    }],
+                    publish: vec![query_name.to_string()],
+                    limits: Default::default(),
+                },
+                &mut scope,
+            );
+
+            server
+                .interest(query_name.to_string(), &mut scope)
+                .inspect(move |x| {
+                    send_results.send((x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        server.transact(
+            Transact {
+                tx: Some(0),
+                tx_data: vec![
+                    TxData(1, 1, ":amount".to_string(), Value::Number(1)),
+                    TxData(1, 1, ":amount".to_string(), Value::Number(2)),
+                    TxData(1, 1, ":amount".to_string(), Value::Decimal(Decimal::new(150, 2))),
+                ],
+                wait_for: vec![],
+            },
+            0,
+            0,
+        );
+
+        worker.step_while(|| server.is_any_outdated());
+
+        server.transact(
+            Transact {
+                tx: Some(1),
+                tx_data: vec![TxData(-1, 1, ":amount".to_string(), Value::Decimal(Decimal::new(150, 2)))],
+                wait_for: vec![],
+            },
+            0,
+            0,
+        );
+
+        worker.step_while(|| server.is_any_outdated());
+
+        thread::spawn(move || {
+            let mixed = vec![
+                Value::Eid(1),
+                Value::Decimal(Decimal::new(450, 2)),
+                Value::Decimal(Decimal::new(150, 2)),
+            ];
+
+            assert_eq!(results.recv().unwrap(), (mixed.clone(), 1));
+
+            let mut retracted = vec![results.recv().unwrap(), results.recv().unwrap()];
+            retracted.sort_by_key(|&(_, diff)| diff);
+
+            assert_eq!(
+                retracted,
+                vec![
+                    (mixed, -1),
+                    (
+                        vec![Value::Eid(1), Value::Number(3), Value::Rational64(Ratio::new(3, 2))],
+                        1
+                    ),
+                ]
+            );
+        }).join()
+            .unwrap();
+    }).unwrap();
+}
+
+#[test]
+fn large() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::new(Default::default());
+        let (send_results, results) = channel();
+        let send_results_copy = send_results.clone();
+
+        // [:find ?e (sum ?amount) (avg ?amount) (variance ?amount) (stddev ?amount)
+        //  :where [?e :amount ?amount]]
+        let (e, amount) = (1, 2);
+        let plan = Plan::Aggregate(Aggregate {
+            variables: vec![e, amount, amount, amount, amount],
+            plan: Box::new(Plan::MatchA(e, ":amount".to_string(), amount)),
+            aggregation_fns: vec![
+                AggregationFn::SUM,
+                AggregationFn::AVG,
+                AggregationFn::VARIANCE,
+                AggregationFn::STDDEV,
+            ],
+            key_symbols: vec![e],
+            aggregation_symbols: vec![amount, amount, amount, amount],
+            with_symbols: vec![],
+            empty_groups: EmptyGroups::Identity,
+        });
+
+        // [:find (sum ?amount) :where [?e :balance ?amount]]
+        let plan_sum = Plan::Aggregate(Aggregate {
+            variables: vec![amount],
+            plan: Box::new(Plan::Project(Project {
+                variables: vec![amount],
+                plan: Box::new(Plan::MatchA(e, ":balance".to_string(), amount)),
+            })),
+            aggregation_fns: vec![AggregationFn::SUM],
+            key_symbols: vec![],
+            aggregation_symbols: vec![amount],
+            with_symbols: vec![],
+            empty_groups: EmptyGroups::Identity,
+        });
+
+        worker.dataflow::<u64, _, _>(|mut scope| {
+            server.create_input(":amount".to_string(), scope);
+            server.create_input(":balance".to_string(), scope);
+
+            server.register(
+                Register {
+                    rules: vec![
+                        Rule {
+                            name: "large".to_string(),
+                            plan: plan,
+                        },
+                        Rule {
+                            name: "large_sum".to_string(),
+                            plan: plan_sum,
+                        },
+                    ],
+                    publish: vec!["large".to_string(), "large_sum".to_string()],
+                    limits: Default::default(),
+                },
+                &mut scope,
+            );
+
+            server
+                .interest("large".to_string(), &mut scope)
+                .inspect(move |x| {
+                    send_results.send((x.0.clone(), x.2)).unwrap();
+                });
+
+            server
+                .interest("large_sum".to_string(), &mut scope)
+                .inspect(move |x| {
+                    send_results_copy.send((x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        server.transact(
+            Transact {
+                tx: Some(0),
+                tx_data: vec![
+                    TxData(1, 1, ":amount".to_string(), Value::Number(3_000_000_000)),
+                    TxData(1, 1, ":amount".to_string(), Value::Number(4_000_000_000)),
+                    TxData(1, 1, ":amount".to_string(), Value::Number(5_000_000_000)),
+                    TxData(1, 1, ":balance".to_string(), Value::Number(i64::max_value())),
+                    TxData(1, 2, ":balance".to_string(), Value::Number(1)),
+                ],
+                wait_for: vec![],
+            },
+            0,
+            0,
+        );
+
+        worker.step_while(|| server.is_any_outdated());
+
+        thread::spawn(move || {
+            let mut outputs: Vec<_> = (0..2).map(|_| results.recv().unwrap()).collect();
+            outputs.sort();
+
+            assert_eq!(
+                outputs,
+                vec![
+                    (
+                        vec![Value::Decimal("9223372036854775808".parse::<Decimal>().unwrap())],
+                        1
+                    ),
+                    (
+                        vec![
+                            Value::Eid(1),
+                            Value::Number(12_000_000_000),
+                            Value::Rational64(Ratio::new(4_000_000_000, 1)),
+                            Value::Rational64(Ratio::new(2_000_000_000_000_000_000, 3)),
+                            Value::Real(OrderedFloat((2e18f64 / 3.0).sqrt())),
+                        ],
+                        1
+                    ),
+                ]
+            );
+        }).join()
+            .unwrap();
+    }).unwrap();
+}
+
+#[test]
+fn sets() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::new(Default::default());
+        let (send_results, results) = channel();
+        let send_results_copy = send_results.clone();
+
+        // [:find ?e (count-distinct ?amount) (percentile ?amount 0.5) (top-k ?amount 2)
+        //  :where [?e :amount ?amount]]
+        let (e, amount) = (1, 2);
+        let plan = Plan::Aggregate(Aggregate {
+            variables: vec![e, amount, amount, amount],
+            plan: Box::new(Plan::MatchA(e, ":amount".to_string(), amount)),
+            aggregation_fns: vec![
+                AggregationFn::COUNT_DISTINCT,
+                AggregationFn::PERCENTILE(0.5),
+                AggregationFn::TOP_K(2),
+            ],
+            key_symbols: vec![e],
+            aggregation_symbols: vec![amount, amount, amount],
+            with_symbols: vec![],
+            empty_groups: EmptyGroups::Identity,
+        });
+
+        // [:find (bottom-k ?amount 2) :where [?e :amount ?amount]]
+        let plan_bottom = Plan::Aggregate(Aggregate {
+            variables: vec![amount],
+            plan: Box::new(Plan::Project(Project {
+                variables: vec![amount],
+                plan: Box::new(Plan::MatchA(e, ":amount".to_string(), amount)),
+            })),
+            aggregation_fns: vec![AggregationFn::BOTTOM_K(2)],
+            key_symbols: vec![],
+            aggregation_symbols: vec![amount],
+            with_symbols: vec![],
+            empty_groups: EmptyGroups::Identity,
+        });
+
+        worker.dataflow::<u64, _, _>(|mut scope| {
+            server.create_input(":amount".to_string(), scope);
+
+            server.register(
+                Register {
+                    rules: vec![
+                        Rule {
+                            name: "sets".to_string(),
+                            plan: plan,
+                        },
+                        Rule {
+                            name: "sets_bottom".to_string(),
+                            plan: plan_bottom,
+                        },
+                    ],
+                    publish: vec!["sets".to_string(), "sets_bottom".to_string()],
+                    limits: Default::default(),
+                },
+                &mut scope,
+            );
+
+            server
+                .interest("sets".to_string(), &mut scope)
+                .inspect(move |x| {
+                    send_results.send((x.1, x.0.clone(), x.2)).unwrap();
+                });
+
+            server
+                .interest("sets_bottom".to_string(), &mut scope)
+                .inspect(move |x| {
+                    send_results_copy.send((x.1, x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        server.transact(
+            Transact {
+                tx: Some(0),
+                tx_data: vec![
+                    TxData(1, 1, ":amount".to_string(), Value::Number(5)),
+                    TxData(1, 2, ":amount".to_string(), Value::Number(10)),
+                    TxData(1, 1, ":amount".to_string(), Value::Number(2)),
+                    TxData(1, 1, ":amount".to_string(), Value::Number(4)),
+                    TxData(1, 1, ":amount".to_string(), Value::Number(6)),
+                ],
+                wait_for: vec![],
+            },
+            0,
+            0,
+        );
+
+        worker.step_while(|| server.is_any_outdated());
+
+        server.transact(
+            Transact {
+                tx: Some(1),
+                tx_data: vec![TxData(-1, 1, ":amount".to_string(), Value::Number(6))],
+                wait_for: vec![],
+            },
+            0,
+            0,
+        );
+
+        worker.step_while(|| server.is_any_outdated());
+
+        thread::spawn(move || {
+            let mut outputs: Vec<_> = (0..9).map(|_| results.recv().unwrap()).collect();
+            outputs.sort();
+
+            let n = |x| Value::Number(x);
+
+            assert_eq!(
+                outputs,
+                vec![
+                    (0, vec![n(2)], 1),
+                    (0, vec![n(4)], 1),
+                    (0, vec![Value::Eid(1), n(4), n(4), n(5)], 1),
+                    (0, vec![Value::Eid(1), n(4), n(4), n(6)], 1),
+                    (0, vec![Value::Eid(2), n(1), n(10), n(10)], 1),
+                    (1, vec![Value::Eid(1), n(3), n(4), n(4)], 1),
+                    (1, vec![Value::Eid(1), n(3), n(4), n(5)], 1),
+                    (1, vec![Value::Eid(1), n(4), n(4), n(5)], -1),
+                    (1, vec![Value::Eid(1), n(4), n(4), n(6)], -1),
+                ]
+            );
+            assert!(results.try_recv().is_err());
+        }).join()
+            .unwrap();
+    }).unwrap();
+}
+
+#[test]
+fn sample() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::new(Default::default());
+        let (send_results, results) = channel();
+
+        // [:find ?e (sample 2 ?amount) :where [?e :amount ?amount]]
+        let (e, amount) = (1, 2);
+        let plan = Plan::Aggregate(Aggregate {
+            variables: vec![e, amount],
+            plan: Box::new(Plan::MatchA(e, ":amount".to_string(), amount)),
+            aggregation_fns: vec![AggregationFn::SAMPLE(2)],
+            key_symbols: vec![e],
+            aggregation_symbols: vec![amount],
+            with_symbols: vec![],
+            empty_groups: EmptyGroups::Identity,
+        });
+
+        worker.dataflow::<u64, _, _>(|mut scope| {
+            server.create_input(":amount".to_string(), scope);
+
+            let query_name = "sample";
+            server.register(
+                Register {
+                    rules: vec![Rule {
+                        name: query_name.to_string(),
+                        plan: plan,
+                    }],
+                    publish: vec![query_name.to_string()],
+                    limits: Default::default(),
+                },
+                &mut scope,
+            );
+
+            server
+                .interest(query_name.to_string(), &mut scope)
+                .inspect(move |x| {
+                    send_results.send((x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        server.transact(
+            Transact {
+                tx: Some(0),
+                tx_data: vec![
+                    TxData(1, 1, ":amount".to_string(), Value::Number(5)),
+                    TxData(1, 1, ":amount".to_string(), Value::Number(2)),
+                    TxData(1, 1, ":amount".to_string(), Value::Number(4)),
+                    TxData(1, 2, ":amount".to_string(), Value::Number(10)),
+                ],
+                wait_for: vec![],
+            },
+            0,
+            0,
+        );
+
+        worker.step_while(|| server.is_any_outdated());
+
+        thread::spawn(move || {
+            let outputs: Vec<_> = (0..3).map(|_| results.recv().unwrap()).collect();
+            assert!(results.try_recv().is_err());
+
+            let sampled: Vec<_> = outputs.iter().filter(|x| x.0[0] == Value::Eid(1)).collect();
+            assert_eq!(sampled.len(), 2);
+            for x in sampled {
+                assert!(vec![Value::Number(2), Value::Number(4), Value::Number(5)].contains(&x.0[1]));
+            }
+
+            assert!(outputs.contains(&(vec![Value::Eid(2), Value::Number(10)], 1)));
+        }).join()
+            .unwrap();
+    }).unwrap();
+}
+
+#[test]
+fn empty_groups() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::new(Default::default());
+        let (send_results, results) = channel();
+        let send_results_copy = send_results.clone();
+
+        // [:find (count ?amount) (sum ?amount) (min ?amount) :where [?e :amount ?amount]]
+        let (e, amount) = (1, 2);
+        let plan = |empty_groups| {
+            Plan::Aggregate(Aggregate {
+                variables: vec![amount, amount, amount],
+                plan: Box::new(Plan::Project(Project {
+                    variables: vec![amount],
+                    plan: Box::new(Plan::MatchA(e, ":amount".to_string(), amount)),
+                })),
+                aggregation_fns: vec![AggregationFn::COUNT, AggregationFn::SUM, AggregationFn::MIN],
+                key_symbols: vec![],
+                aggregation_symbols: vec![amount, amount, amount],
+                with_symbols: vec![],
+                empty_groups: empty_groups,
+            })
+        };
+
+        worker.dataflow::<u64, _, _>(|mut scope| {
+            server.create_input(":amount".to_string(), scope);
+
+            server.register(
+                Register {
+                    rules: vec![
+                        Rule {
+                            name: "identity".to_string(),
+                            plan: plan(EmptyGroups::Identity),
+                        },
+                        Rule {
+                            name: "retract".to_string(),
+                            plan: plan(EmptyGroups::Retract),
+                        },
+                    ],
+                    publish: vec!["identity".to_string(), "retract".to_string()],
+                    limits: Default::default(),
+                },
+                &mut scope,
+            );
+
+            server
+                .interest("identity".to_string(), &mut scope)
+                .inspect(move |x| {
+                    send_results.send(("identity", x.1, x.0.clone(), x.2)).unwrap();
+                });
+
+            server
+                .interest("retract".to_string(), &mut scope)
+                .inspect(move |x| {
+                    send_results_copy.send(("retract", x.1, x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        server.transact(Transact { tx: Some(0), tx_data: vec![], wait_for: vec![] }, 0, 0);
+        worker.step_while(|| server.is_any_outdated());
+
+        server.transact(
+            Transact {
+                tx: Some(1),
+                tx_data: vec![
+                    TxData(1, 1, ":amount".to_string(), Value::Number(5)),
+                    TxData(1, 2, ":amount".to_string(), Value::Number(3)),
+                ],
+                wait_for: vec![],
+            },
+            0,
+            0,
+        );
+        worker.step_while(|| server.is_any_outdated());
+
+        server.transact(
+            Transact {
+                tx: Some(2),
+                tx_data: vec![
+                    TxData(-1, 1, ":amount".to_string(), Value::Number(5)),
+                    TxData(-1, 2, ":amount".to_string(), Value::Number(3)),
+                ],
+                wait_for: vec![],
+            },
+            0,
+            0,
+        );
+        worker.step_while(|| server.is_any_outdated());
+
+        thread::spawn(move || {
+            let mut outputs: Vec<_> = (0..7).map(|_| results.recv().unwrap()).collect();
+            outputs.sort();
+
+            let identity = vec![Value::Number(0), Value::Number(0), Value::Nil];
+            let result = vec![Value::Number(2), Value::Number(8), Value::Number(3)];
+
+            assert_eq!(
+                outputs,
+                vec![
+                    ("identity", 0, identity.clone(), 1),
+                    ("identity", 1, identity.clone(), -1),
+                    ("identity", 1, result.clone(), 1),
+                    ("identity", 2, identity.clone(), 1),
+                    ("identity", 2, result.clone(), -1),
+                    ("retract", 1, result.clone(), 1),
+                    ("retract", 2, result.clone(), -1),
+                ]
+            );
+            assert!(results.try_recv().is_err());
+        }).join()
+            .unwrap();
+    }).unwrap();
+}
+
+#[test]
+fn empty_groups_out_of_order() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::new(Default::default());
+        let (send_results, results) = channel();
+
+        // [:find (min ?e) (sum ?amount) :where [?e :amount ?amount]]
+        let (e, amount) = (1, 2);
+        let plan = Plan::Aggregate(Aggregate {
+            variables: vec![e, amount],
+            plan: Box::new(Plan::MatchA(e, ":amount".to_string(), amount)),
+            aggregation_fns: vec![AggregationFn::SUM, AggregationFn::MIN],
+            key_symbols: vec![],
+            aggregation_symbols: vec![amount, e],
+            with_symbols: vec![],
+            empty_groups: EmptyGroups::Identity,
+        });
+
+        worker.dataflow::<u64, _, _>(|mut scope| {
+            server.create_input(":amount".to_string(), scope);
+
+            server.register(
+                Register {
+                    rules: vec![Rule {
+                        name: "totals".to_string(),
+                        plan: plan,
+                    }],
+                    publish: vec!["totals".to_string()],
+                    limits: Default::default(),
+                },
+                &mut scope,
+            );
+
+            server
+                .interest("totals".to_string(), &mut scope)
+                .inspect(move |x| {
+                    send_results.send((x.1, x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        server.transact(Transact { tx: Some(0), tx_data: vec![], wait_for: vec![] }, 0, 0);
+        worker.step_while(|| server.is_any_outdated());
+
+        server.transact(
+            Transact {
+                tx: Some(1),
+                tx_data: vec![TxData(1, 1, ":amount".to_string(), Value::Number(5))],
+                wait_for: vec![],
+            },
+            0,
+            0,
+        );
+        worker.step_while(|| server.is_any_outdated());
+
+        thread::spawn(move || {
+            let mut outputs: Vec<_> = (0..3).map(|_| results.recv().unwrap()).collect();
+            outputs.sort();
+
+            let identity = vec![Value::Nil, Value::Number(0)];
+
+            let mut expected = vec![
+                (0, identity.clone(), 1),
+                (1, identity.clone(), -1),
+                (1, vec![Value::Eid(1), Value::Number(5)], 1),
+            ];
+            expected.sort();
+
+            assert_eq!(outputs, expected);
+            assert!(results.try_recv().is_err());
+        }).join()
+            .unwrap();
+    }).unwrap();
+}
+
+#[test]
+fn empty_groups_with_keys() {
+    let server = Server::new(Default::default());
+
+    // [:find ?e (sum ?amount) :where [?e :amount ?amount]]
+    let (e, amount) = (1, 2);
+    let mut req = Register {
+        rules: vec![Rule {
+            name: "totals".to_string(),
+            plan: Plan::Aggregate(Aggregate {
+                variables: vec![e, amount],
+                plan: Box::new(Plan::MatchA(e, ":amount".to_string(), amount)),
+                aggregation_fns: vec![AggregationFn::SUM],
+                key_symbols: vec![e],
+                aggregation_symbols: vec![amount],
+                with_symbols: vec![],
+                empty_groups: EmptyGroups::Identity,
+            }),
+        }],
+        publish: vec!["totals".to_string()],
+        limits: Default::default(),
+    };
+
+    assert!(server.validate(&mut req).is_err());
+}
+
+#[test]
+fn percentile_out_of_range() {
+    let server = Server::new(Default::default());
+
+    // [:find (percentile 1.5 ?amount) :where [?e :amount ?amount]]
+    let (e, amount) = (1, 2);
+    let mut req = Register {
+        rules: vec![Rule {
+            name: "percentile".to_string(),
+            plan: Plan::Aggregate(Aggregate {
+                variables: vec![amount],
+                plan: Box::new(Plan::MatchA(e, ":amount".to_string(), amount)),
+                aggregation_fns: vec![AggregationFn::PERCENTILE(1.5)],
+                key_symbols: vec![],
+                aggregation_symbols: vec![amount],
+                with_symbols: vec![],
+                empty_groups: EmptyGroups::Retract,
+            }),
+        }],
+        publish: vec!["percentile".to_string()],
+        limits: Default::default(),
+    };
+
+    assert!(server.validate(&mut req).is_err());
+}
@@ -0,0 +1,85 @@
+extern crate declarative_dataflow;
+
+use std::collections::HashMap;
+
+use declarative_dataflow::server::auth::{Guard, Permissions, Policy};
+use declarative_dataflow::server::{CreateInput, Interest, Request, Transact, TxData};
+use declarative_dataflow::Value;
+
+fn policy() -> Policy {
+    let mut tokens = HashMap::new();
+    tokens.insert("s3cr3t".to_string(), "loader".to_string());
+
+    let mut principals = HashMap::new();
+    principals.insert(
+        "loader".to_string(),
+        Permissions {
+            transact: vec![":person/*".to_string()],
+            subscribe: vec![],
+            register: true,
+        },
+    );
+    principals.insert(
+        "viewer".to_string(),
+        Permissions {
+            transact: vec![],
+            subscribe: vec!["adults".to_string()],
+            register: false,
+        },
+    );
+
+    Policy {
+        tokens,
+        principals,
+        anonymous: Some("viewer".to_string()),
+    }
+}
+
+fn transact(attribute: &str) -> Request {
+    Request::Transact(Transact {
+        tx: None,
+        tx_data: vec![TxData(1, 1, attribute.to_string(), Value::Number(1))],
+        wait_for: vec![],
+    })
+}
+
+fn interest(name: &str) -> Request {
+    Request::Interest(Interest {
+        name: name.to_string(),
+        constraint: None,
+    })
+}
+
+#[test]
+fn authenticate() {
+    let policy = policy();
+
+    assert_eq!(policy.authenticate("s3cr3t"), Some("loader".to_string()));
+    assert_eq!(policy.authenticate("guess"), None);
+    assert_eq!(policy.anonymous(), Some("viewer".to_string()));
+}
+
+#[test]
+fn authorize() {
+    let policy = policy();
+
+    assert!(policy.authorize("loader", &transact(":person/age")).is_ok());
+    assert!(policy.authorize("loader", &transact(":account/balance")).is_err());
+    assert!(policy.authorize("loader", &interest("adults")).is_err());
+    assert!(
+        policy
+            .authorize("loader", &Request::CreateInput(CreateInput { name: ":person/age".to_string() }))
+            .is_ok()
+    );
+
+    assert!(policy.authorize("viewer", &transact(":person/age")).is_err());
+    assert!(policy.authorize("viewer", &interest("adults")).is_ok());
+    assert!(policy.authorize("viewer", &interest("children")).is_err());
+    assert!(
+        policy
+            .authorize("viewer", &Request::CreateInput(CreateInput { name: ":person/age".to_string() }))
+            .is_err()
+    );
+
+    assert!(policy.authorize("stranger", &interest("adults")).is_err());
+}
@@ -0,0 +1,403 @@
+extern crate declarative_dataflow;
+extern crate timely;
+
+use std::collections::HashMap;
+use std::sync::mpsc::channel;
+use std::thread;
+
+use timely::Configuration;
+
+use declarative_dataflow::plan::{Collation, Filter, FilterExpr, Operand, Predicate, PredicateExpr};
+use declarative_dataflow::server::{Register, Server, Transact, TxData};
+use declarative_dataflow::{OrderedFloat, Plan, Rational32, Rule, Value};
+
+#[test]
+fn filter_nil() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::new(Default::default());
+        let (send_results, results) = channel();
+
+        // [:find ?e ?a :where [?e :age ?a] [(> ?a 10)]]
+        let (e, a) = (1, 2);
+        let mut constants = HashMap::new();
+        constants.insert(1, Value::Number(10));
+        let plan = Plan::Filter(Filter {
+            variables: vec![a],
+            predicate: Predicate::GT,
+            plan: Box::new(Plan::MatchA(e, ":age".to_string(), a)),
+            constants: constants,
+            collation: Collation::Binary,
+        });
+
+        // [:find ?e ?a :where [?e :age ?a] [(not= ?a 10)]]
+        let mut constants = HashMap::new();
+        constants.insert(1, Value::Number(10));
+        let plan_neq = Plan::Filter(Filter {
+            variables: vec![a],
+            predicate: Predicate::NEQ,
+            plan: Box::new(Plan::MatchA(e, ":age".to_string(), a)),
+            constants: constants,
+            collation: Collation::Binary,
+        });
+
+        worker.dataflow::<u64, _, _>(|mut scope| {
+            server.create_input(":age".to_string(), &mut scope);
+
+            server.register(
+                Register {
+                    rules: vec![
+                        Rule {
+                            name: "filter_gt".to_string(),
+                            plan: plan,
+                        },
+                        Rule {
+                            name: "filter_neq".to_string(),
+                            plan: plan_neq,
+                        },
+                    ],
+                    publish: vec!["filter_gt".to_string(), "filter_neq".to_string()],
+                    limits: Default::default(),
+                },
+                &mut scope,
+            );
+
+            let send_results_copy = send_results.clone();
+            server
+                .interest("filter_gt".to_string(), &mut scope)
+                .inspect(move |x| {
+                    send_results.send(("filter_gt", x.0.clone(), x.2)).unwrap();
+                });
+
+            server
+                .interest("filter_neq".to_string(), &mut scope)
+                .inspect(move |x| {
+                    send_results_copy.send(("filter_neq", x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        server.transact(
+            Transact {
+                tx: Some(0),
+                tx_data: vec![
+                    TxData(1, 1, ":age".to_string(), Value::Number(12)),
+                    TxData(1, 2, ":age".to_string(), Value::Nil),
+                    TxData(1, 3, ":age".to_string(), Value::Number(8)),
+                ],
+                wait_for: vec![],
+            },
+            0,
+            0,
+        );
+
+        worker.step_while(|| server.is_any_outdated());
+
+        thread::spawn(move || {
+            let mut outputs: Vec<_> = (0..3).map(|_| results.recv().unwrap()).collect();
+            outputs.sort();
+
+            assert_eq!(
+                outputs,
+                vec![
+                    ("filter_gt", vec![Value::Eid(1), Value::Number(12)], 1),
+                    ("filter_neq", vec![Value::Eid(1), Value::Number(12)], 1),
+                    ("filter_neq", vec![Value::Eid(3), Value::Number(8)], 1),
+                ]
+            );
+            assert!(results.try_recv().is_err());
+        }).join()
+            .unwrap();
+    }).unwrap();
+}
+
+#[test]
+fn filter_expr() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::new(Default::default());
+        let (send_results, results) = channel();
+
+        // [:find ?e ?a :where [?e :age ?a] (or [(<= 18 ?a 64)] [(contains? #{99 100} ?a)])]
+        let (e, a) = (1, 2);
+        let plan_age = Plan::FilterExpr(FilterExpr {
+            expr: PredicateExpr::Or(vec![
+                PredicateExpr::Between(
+                    Operand::Var(a),
+                    Operand::Const(Value::Number(18)),
+                    Operand::Const(Value::Number(64)),
+                ),
+                PredicateExpr::In(Operand::Var(a), vec![Value::Number(99), Value::Number(100)]),
+            ]),
+            plan: Box::new(Plan::MatchA(e, ":age".to_string(), a)),
+            collation: Collation::Binary,
+        });
+
+        // [:find ?e ?n :where
+        //  [?e :name ?n]
+        //  [(starts-with? ?n "Al")]
+        //  (not [(re-matches #"^Al(ice)?$" ?n)])]
+        let n = 3;
+        let plan_name = Plan::FilterExpr(FilterExpr {
+            expr: PredicateExpr::And(vec![
+                PredicateExpr::StartsWith(
+                    Operand::Var(n),
+                    Operand::Const(Value::String("Al".to_string())),
+                ),
+                PredicateExpr::Not(Box::new(PredicateExpr::Matches(
+                    Operand::Var(n),
+                    "^Al(ice)?$".to_string(),
+                ))),
+            ]),
+            plan: Box::new(Plan::MatchA(e, ":name".to_string(), n)),
+            collation: Collation::Binary,
+        });
+
+        worker.dataflow::<u64, _, _>(|mut scope| {
+            server.create_input(":age".to_string(), &mut scope);
+            server.create_input(":name".to_string(), &mut scope);
+
+            server.register(
+                Register {
+                    rules: vec![
+                        Rule {
+                            name: "filter_age".to_string(),
+                            plan: plan_age,
+                        },
+                        Rule {
+                            name: "filter_name".to_string(),
+                            plan: plan_name,
+                        },
+                    ],
+                    publish: vec!["filter_age".to_string(), "filter_name".to_string()],
+                    limits: Default::default(),
+                },
+                &mut scope,
+            );
+
+            let send_results_copy = send_results.clone();
+            server
+                .interest("filter_age".to_string(), &mut scope)
+                .inspect(move |x| {
+                    send_results.send(("filter_age", x.0.clone(), x.2)).unwrap();
+                });
+
+            server
+                .interest("filter_name".to_string(), &mut scope)
+                .inspect(move |x| {
+                    send_results_copy.send(("filter_name", x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        server.transact(
+            Transact {
+                tx: Some(0),
+                tx_data: vec![
+                    TxData(1, 1, ":age".to_string(), Value::Number(12)),
+                    TxData(1, 2, ":age".to_string(), Value::Number(18)),
+                    TxData(1, 3, ":age".to_string(), Value::Number(64)),
+                    TxData(1, 4, ":age".to_string(), Value::Number(65)),
+                    TxData(1, 5, ":age".to_string(), Value::Number(99)),
+                    TxData(1, 6, ":age".to_string(), Value::Nil),
+                    TxData(1, 1, ":name".to_string(), Value::String("Alice".to_string())),
+                    TxData(1, 2, ":name".to_string(), Value::String("Albert".to_string())),
+                    TxData(1, 3, ":name".to_string(), Value::String("Al".to_string())),
+                    TxData(1, 4, ":name".to_string(), Value::String("Bob".to_string())),
+                ],
+                wait_for: vec![],
+            },
+            0,
+            0,
+        );
+
+        worker.step_while(|| server.is_any_outdated());
+
+        thread::spawn(move || {
+            let mut outputs: Vec<_> = (0..4).map(|_| results.recv().unwrap()).collect();
+            outputs.sort();
+
+            assert_eq!(
+                outputs,
+                vec![
+                    ("filter_age", vec![Value::Eid(2), Value::Number(18)], 1),
+                    ("filter_age", vec![Value::Eid(3), Value::Number(64)], 1),
+                    ("filter_age", vec![Value::Eid(5), Value::Number(99)], 1),
+                    ("filter_name", vec![Value::Eid(2), Value::String("Albert".to_string())], 1),
+                ]
+            );
+            assert!(results.try_recv().is_err());
+        }).join()
+            .unwrap();
+    }).unwrap();
+}
+
+#[test]
+fn filter_mixed_types() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::new(Default::default());
+        let (send_results, results) = channel();
+
+        // [:find ?e ?a :where [?e :amount ?a] [(< ?a 1/2)]]
+        let (e, a) = (1, 2);
+        let mut constants = HashMap::new();
+        constants.insert(1, Value::Rational32(Rational32::new(1, 2)));
+        let plan_lt = Plan::Filter(Filter {
+            variables: vec![a],
+            predicate: Predicate::LT,
+            plan: Box::new(Plan::MatchA(e, ":amount".to_string(), a)),
+            constants: constants,
+            collation: Collation::Binary,
+        });
+
+        // [:find ?e ?n :where [?e :name ?n] [(= ?n "alice")]], ignoring case
+        let n = 3;
+        let mut constants = HashMap::new();
+        constants.insert(1, Value::String("alice".to_string()));
+        let plan_eq = Plan::Filter(Filter {
+            variables: vec![n],
+            predicate: Predicate::EQ,
+            plan: Box::new(Plan::MatchA(e, ":name".to_string(), n)),
+            constants: constants,
+            collation: Collation::CaseInsensitive,
+        });
+
+        worker.dataflow::<u64, _, _>(|mut scope| {
+            server.create_input(":amount".to_string(), &mut scope);
+            server.create_input(":name".to_string(), &mut scope);
+
+            server.register(
+                Register {
+                    rules: vec![
+                        Rule {
+                            name: "filter_lt".to_string(),
+                            plan: plan_lt,
+                        },
+                        Rule {
+                            name: "filter_eq".to_string(),
+                            plan: plan_eq,
+                        },
+                    ],
+                    publish: vec!["filter_lt".to_string(), "filter_eq".to_string()],
+                    limits: Default::default(),
+                },
+                &mut scope,
+            );
+
+            let send_results_copy = send_results.clone();
+            server
+                .interest("filter_lt".to_string(), &mut scope)
+                .inspect(move |x| {
+                    send_results.send(("filter_lt", x.0.clone(), x.2)).unwrap();
+                });
+
+            server
+                .interest("filter_eq".to_string(), &mut scope)
+                .inspect(move |x| {
+                    send_results_copy.send(("filter_eq", x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        server.transact(
+            Transact {
+                tx: Some(0),
+                tx_data: vec![
+                    TxData(1, 1, ":amount".to_string(), Value::Number(0)),
+                    TxData(1, 2, ":amount".to_string(), Value::Number(5)),
+                    TxData(1, 3, ":amount".to_string(), Value::Real(OrderedFloat(0.25))),
+                    TxData(1, 4, ":amount".to_string(), Value::String("0".to_string())),
+                    TxData(1, 1, ":name".to_string(), Value::String("Alice".to_string())),
+                    TxData(1, 2, ":name".to_string(), Value::String("Alicia".to_string())),
+                ],
+                wait_for: vec![],
+            },
+            0,
+            0,
+        );
+
+        worker.step_while(|| server.is_any_outdated());
+
+        thread::spawn(move || {
+            let mut outputs: Vec<_> = (0..3).map(|_| results.recv().unwrap()).collect();
+            outputs.sort();
+
+            assert_eq!(
+                outputs,
+                vec![
+                    ("filter_eq", vec![Value::Eid(1), Value::String("Alice".to_string())], 1),
+                    ("filter_lt", vec![Value::Eid(1), Value::Number(0)], 1),
+                    ("filter_lt", vec![Value::Eid(3), Value::Real(OrderedFloat(0.25))], 1),
+                ]
+            );
+            assert!(results.try_recv().is_err());
+        }).join()
+            .unwrap();
+    }).unwrap();
+}
+
+#[test]
+fn positional_constraint() {
+    // positions 1 and 2 of e.g. [?e ?name ?age]
+    let expr = PredicateExpr::And(vec![
+        PredicateExpr::StartsWith(Operand::Var(1), Operand::Const(Value::String("Ma".to_string()))),
+        PredicateExpr::Between(
+            Operand::Var(2),
+            Operand::Const(Value::Number(10)),
+            Operand::Const(Value::Number(20)),
+        ),
+    ]);
+    let constraint = expr.compile_positional(Collation::Binary).unwrap();
+
+    let tuple = |name: &str, age| vec![Value::Eid(1), Value::String(name.to_string()), Value::Number(age)];
+
+    assert!(constraint.eval(&tuple("Mabel", 12)));
+    assert!(!constraint.eval(&tuple("Mabel", 40)));
+    assert!(!constraint.eval(&tuple("Dipper", 12)));
+    assert!(!constraint.eval(&[Value::Eid(1), Value::String("Mabel".to_string())]));
+}
+
+#[test]
+fn filter_validation() {
+    let (e, n) = (1, 2);
+    let register = |expr: PredicateExpr| Register {
+        rules: vec![Rule {
+            name: "names".to_string(),
+            plan: Plan::FilterExpr(FilterExpr {
+                expr: expr,
+                plan: Box::new(Plan::MatchA(e, ":name".to_string(), n)),
+                collation: Collation::Binary,
+            }),
+        }],
+        publish: vec!["names".to_string()],
+        limits: Default::default(),
+    };
+
+    // registrations are rejected as a whole, rather than panicking
+    // once implemented
+    let server = Server::new(Default::default());
+
+    let mut req = register(PredicateExpr::Matches(Operand::Var(n), "^Al(ice$".to_string()));
+    assert!(server.validate(&mut req).is_err());
+
+    let mut req = register(PredicateExpr::IsNil(Operand::Var(3)));
+    assert!(server.validate(&mut req).is_err());
+
+    let mut req = register(PredicateExpr::Matches(Operand::Var(n), "^Al(ice)?$".to_string()));
+    assert!(server.validate(&mut req).is_ok());
+
+    let mut req = Register {
+        rules: vec![Rule {
+            name: "names".to_string(),
+            plan: Plan::Filter(Filter {
+                variables: vec![3],
+                predicate: Predicate::EQ,
+                plan: Box::new(Plan::MatchA(e, ":name".to_string(), n)),
+                constants: {
+                    let mut constants = HashMap::new();
+                    constants.insert(1, Value::String("Alice".to_string()));
+                    constants
+                },
+                collation: Collation::Binary,
+            }),
+        }],
+        publish: vec!["names".to_string()],
+        limits: Default::default(),
+    };
+    assert!(server.validate(&mut req).is_err());
+}
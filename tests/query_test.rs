@@ -2,14 +2,17 @@ extern crate declarative_dataflow;
 extern crate num_rational;
 extern crate timely;
 
+use std::collections::HashMap;
 use std::sync::mpsc::channel;
 use std::thread;
 
 use timely::Configuration;
 
-use declarative_dataflow::plan::{Aggregate, AggregationFn, Join, Project};
+use declarative_dataflow::plan::{
+    Aggregate, AggregationFn, Function, Join, Project, Transform, Union,
+};
 use declarative_dataflow::server::{Register, Server, Transact, TxData};
-use declarative_dataflow::{Plan, Rule, Value};
+use declarative_dataflow::{Limits, Plan, Rule, Value};
 
 use num_rational::Ratio;
 
@@ -33,6 +36,7 @@ fn match_ea() {
                         plan: plan,
                     }],
                     publish: vec![query_name.to_string()],
+                    limits: Default::default(),
                 },
                 scope,
             );
@@ -67,6 +71,7 @@ fn match_ea() {
                         Value::String("Mabel".to_string()),
                     ),
                 ],
+                wait_for: vec![],
             },
             0,
             0,
@@ -117,6 +122,7 @@ fn join() {
                         plan: plan,
                     }],
                     publish: vec![query_name.to_string()],
+                    limits: Default::default(),
                 },
                 &mut scope,
             );
@@ -140,6 +146,7 @@ fn join() {
                     ),
                     TxData(1, 1, ":age".to_string(), Value::Number(12)),
                 ],
+                wait_for: vec![],
             },
             0,
             0,
@@ -163,3 +170,89 @@ fn join() {
             .unwrap();
     }).unwrap();
 }
+
+#[test]
+fn bounded_recursion() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::new(Default::default());
+        let (send_results, results) = channel();
+        let (send_failures, failures) = channel();
+
+        // [:find ?e ?x :where (count ?e ?x)]
+        // (count ?e ?x) <- [?e :start ?x]
+        // (count ?e ?y) <- (count ?e ?x) [(+ ?x 1) ?y]
+        let (e, x, y) = (1, 2, 3);
+        let mut constants = HashMap::new();
+        constants.insert(1, Value::Number(1));
+        let plan = Plan::Union(Union {
+            variables: vec![e, x],
+            plans: vec![
+                Plan::MatchA(e, ":start".to_string(), x),
+                Plan::Project(Project {
+                    variables: vec![e, y],
+                    plan: Box::new(Plan::Transform(Transform {
+                        variables: vec![x],
+                        result_sym: y,
+                        plan: Box::new(Plan::RuleExpr(vec![e, x], "count".to_string())),
+                        function: Function::ADD,
+                        constants: constants,
+                        udf: None,
+                    })),
+                }),
+            ],
+        });
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server.create_input(":start".to_string(), scope);
+
+            server
+                .register(
+                    Register {
+                        rules: vec![Rule {
+                            name: "count".to_string(),
+                            plan: plan,
+                        }],
+                        publish: vec!["count".to_string()],
+                        limits: Limits {
+                            max_iterations: Some(5),
+                            max_tuples: None,
+                        },
+                    },
+                    scope,
+                )
+                .inspect(move |x| {
+                    send_failures.send((x.0.clone(), x.2)).unwrap();
+                });
+
+            server
+                .interest("count".to_string(), scope)
+                .inspect(move |x| {
+                    send_results.send((x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        server.transact(
+            Transact {
+                tx: Some(0),
+                tx_data: vec![TxData(1, 1, ":start".to_string(), Value::Number(0))],
+                wait_for: vec![],
+            },
+            0,
+            0,
+        );
+
+        // without limits, this would never return
+        worker.step_while(|| server.is_any_outdated());
+
+        // the rule fails, retracting all of its tuples
+        thread::spawn(move || {
+            assert_eq!(
+                failures.recv().unwrap(),
+                ("Rule count exceeded 5 iterations".to_string(), 1)
+            );
+            assert!(failures.try_recv().is_err());
+            assert!(results.try_recv().is_err());
+        }).join()
+            .unwrap();
+    }).unwrap();
+}
@@ -7,7 +7,7 @@ use std::thread;
 use timely::Configuration;
 
 use declarative_dataflow::plan::{Join, Project};
-use declarative_dataflow::server::{Register, Server, Transact, TxData};
+use declarative_dataflow::server::{client_key, Binding, Lookup, Register, Server, SetParams, Transact, TxData};
 use declarative_dataflow::{Plan, Rule, Value};
 
 #[test]
@@ -28,7 +28,7 @@ fn match_ea_after_input() {
             TxData(1, 1, ":name".to_string(), Value::String("Alias".to_string())),
             TxData(1, 2, ":name".to_string(), Value::String("Mabel".to_string())),
         ];
-        let tx0 = Transact { tx: Some(0), tx_data };
+        let tx0 = Transact { tx: Some(0), tx_data, wait_for: vec![] };
         server.transact(tx0, 0, 0);
 
         worker.step_while(|| server.is_any_outdated());
@@ -42,6 +42,7 @@ fn match_ea_after_input() {
                         plan: plan,
                     }],
                     publish: vec![query_name.to_string()],
+                    limits: Default::default(),
                 },
                 scope,
             );
@@ -85,7 +86,7 @@ fn join_after_input() {
             let tx_data = vec![
                 TxData(1, 1, ":user/id".to_string(), Value::String("123-456-789".to_string())),
             ];
-            let tx0 = Transact { tx: None, tx_data };
+            let tx0 = Transact { tx: None, tx_data, wait_for: vec![] };
             server.transact(tx0, 0, 0);
 
             worker.step_while(|| server.is_any_outdated());
@@ -95,7 +96,7 @@ fn join_after_input() {
             let tx_data = vec![
                 TxData(1, 101, ":transfer/from".to_string(), Value::String("123-456-789".to_string())),
             ];
-            let tx1 = Transact { tx: None, tx_data };
+            let tx1 = Transact { tx: None, tx_data, wait_for: vec![] };
             server.transact(tx1, 0, 0);
 
             worker.step_while(|| server.is_any_outdated());
@@ -121,6 +122,7 @@ fn join_after_input() {
                         plan: plan,
                     }],
                     publish: vec![query_name.to_string()],
+                    limits: Default::default(),
                 },
                 &mut scope,
             );
@@ -142,3 +144,368 @@ fn join_after_input() {
         }).join().unwrap();
     }).unwrap();
 }
+
+#[test]
+fn extend_program() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::new(Default::default());
+        let (send_results, results) = channel();
+
+        let (e, v) = (1, 2);
+
+        worker.dataflow::<u64, _, _>(|mut scope| {
+            server.create_input(":label".to_string(), &mut scope);
+            server.create_input(":alias".to_string(), &mut scope);
+
+            // (label ?e ?v) <- [?e :label ?v]
+            server.register(
+                Register {
+                    rules: vec![Rule {
+                        name: "label".to_string(),
+                        plan: Plan::MatchA(e, ":label".to_string(), v),
+                    }],
+                    publish: vec!["label".to_string()],
+                    limits: Default::default(),
+                },
+                &mut scope,
+            );
+        });
+
+        let tx_data = vec![
+            TxData(1, 1, ":label".to_string(), Value::String("a".to_string())),
+            TxData(1, 2, ":alias".to_string(), Value::String("b".to_string())),
+        ];
+        server.transact(Transact { tx: Some(0), tx_data, wait_for: vec![] }, 0, 0);
+
+        worker.step_while(|| server.is_any_outdated());
+
+        worker.dataflow::<u64, _, _>(|mut scope| {
+            // (label ?e ?v) <- [?e :alias ?v]
+            server.register(
+                Register {
+                    rules: vec![Rule {
+                        name: "label".to_string(),
+                        plan: Plan::MatchA(e, ":alias".to_string(), v),
+                    }],
+                    publish: vec![],
+                    limits: Default::default(),
+                },
+                &mut scope,
+            );
+
+            server
+                .interest("label".to_string(), &mut scope)
+                .inspect(move |x| {
+                    send_results.send((x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        worker.step_while(|| server.is_any_outdated());
+
+        thread::spawn(move || {
+            assert_eq!(
+                results.recv().unwrap(),
+                (vec![Value::Eid(1), Value::String("a".to_string())], 1)
+            );
+            assert_eq!(
+                results.recv().unwrap(),
+                (vec![Value::Eid(2), Value::String("b".to_string())], 1)
+            );
+        }).join().unwrap();
+    }).unwrap();
+}
+
+#[test]
+fn extend_dependency() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::new(Default::default());
+        let (send_results, results) = channel();
+
+        let (e, v) = (1, 2);
+
+        worker.dataflow::<u64, _, _>(|mut scope| {
+            server.create_input(":label".to_string(), &mut scope);
+            server.create_input(":alias".to_string(), &mut scope);
+
+            // (label ?e ?v) <- [?e :label ?v]
+            server.register(
+                Register {
+                    rules: vec![Rule {
+                        name: "label".to_string(),
+                        plan: Plan::MatchA(e, ":label".to_string(), v),
+                    }],
+                    publish: vec!["label".to_string()],
+                    limits: Default::default(),
+                },
+                &mut scope,
+            );
+
+            // (entity ?e) <- (label ?e ?v)
+            server.register(
+                Register {
+                    rules: vec![Rule {
+                        name: "entity".to_string(),
+                        plan: Plan::Project(Project {
+                            variables: vec![e],
+                            plan: Box::new(Plan::RuleExpr(vec![e, v], "label".to_string())),
+                        }),
+                    }],
+                    publish: vec!["entity".to_string()],
+                    limits: Default::default(),
+                },
+                &mut scope,
+            );
+        });
+
+        let tx_data = vec![
+            TxData(1, 1, ":label".to_string(), Value::String("a".to_string())),
+            TxData(1, 2, ":alias".to_string(), Value::String("b".to_string())),
+        ];
+        server.transact(Transact { tx: Some(0), tx_data, wait_for: vec![] }, 0, 0);
+
+        worker.step_while(|| server.is_any_outdated());
+
+        worker.dataflow::<u64, _, _>(|mut scope| {
+            // (label ?e ?v) <- [?e :alias ?v]
+            server.register(
+                Register {
+                    rules: vec![Rule {
+                        name: "label".to_string(),
+                        plan: Plan::MatchA(e, ":alias".to_string(), v),
+                    }],
+                    publish: vec![],
+                    limits: Default::default(),
+                },
+                &mut scope,
+            );
+
+            server
+                .interest("entity".to_string(), &mut scope)
+                .inspect(move |x| {
+                    send_results.send((x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        worker.step_while(|| server.is_any_outdated());
+
+        thread::spawn(move || {
+            assert_eq!(results.recv().unwrap(), (vec![Value::Eid(1)], 1));
+            assert_eq!(results.recv().unwrap(), (vec![Value::Eid(2)], 1));
+        }).join().unwrap();
+    }).unwrap();
+}
+
+#[test]
+fn parameterized_query() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::new(Default::default());
+        let (send_results, results) = channel();
+
+        let (e, n) = (1, 2);
+
+        worker.dataflow::<u64, _, _>(|mut scope| {
+            server.create_input(":name".to_string(), &mut scope);
+
+            // (lookup ?n ?e) <- [?e :name ?n], (= ?n $name)
+            server.register(
+                Register {
+                    rules: vec![Rule {
+                        name: "lookup".to_string(),
+                        plan: Plan::Join(Join {
+                            variables: vec![n],
+                            left_plan: Box::new(Plan::MatchA(e, ":name".to_string(), n)),
+                            right_plan: Box::new(Plan::Param(n, "name".to_string())),
+                        }),
+                    }],
+                    publish: vec!["lookup".to_string()],
+                    limits: Default::default(),
+                },
+                &mut scope,
+            );
+
+            server
+                .interest("lookup".to_string(), &mut scope)
+                .inspect(move |x| {
+                    send_results.send((x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        let tx_data = vec![
+            TxData(1, 1, ":name".to_string(), Value::String("Dipper".to_string())),
+            TxData(1, 2, ":name".to_string(), Value::String("Mabel".to_string())),
+        ];
+        server.transact(Transact { tx: None, tx_data, wait_for: vec![] }, 0, 0);
+
+        worker.step_while(|| server.is_any_outdated());
+
+        // bindings take effect without further transactions
+        let bindings = vec![Binding(1, "name".to_string(), Value::String("Mabel".to_string()))];
+        server.set_params(SetParams { query: "lookup".to_string(), bindings }, 0, 0);
+
+        worker.step_while(|| server.is_any_outdated());
+
+        let bindings = vec![
+            Binding(-1, "name".to_string(), Value::String("Mabel".to_string())),
+            Binding(1, "name".to_string(), Value::String("Dipper".to_string())),
+        ];
+        server.set_params(SetParams { query: "lookup".to_string(), bindings }, 0, 0);
+
+        worker.step_while(|| server.is_any_outdated());
+
+        // transactions at timestamps sealed by bindings happen at the
+        // current epoch instead
+        let epoch = server.epoch();
+        assert_eq!(server.transact(Transact { tx: Some(1), tx_data: vec![], wait_for: vec![] }, 0, 0), epoch);
+        assert_eq!(server.epoch(), epoch + 1);
+
+        let unknown = |param: &str| SetParams {
+            query: "lookup".to_string(),
+            bindings: vec![Binding(1, param.to_string(), Value::String("Mabel".to_string()))],
+        };
+        assert!(server.validate_params(&unknown("name")).is_ok());
+        assert!(server.validate_params(&unknown("age")).is_err());
+        assert!(server
+            .validate_params(&SetParams { query: "unknown".to_string(), bindings: vec![] })
+            .is_err());
+        assert!(server
+            .validate_lookup(&Lookup { query: "lookup".to_string(), bindings: vec![] })
+            .is_err());
+
+        thread::spawn(move || {
+            assert_eq!(
+                results.recv().unwrap(),
+                (vec![Value::String("Mabel".to_string()), Value::Eid(2)], 1)
+            );
+            assert_eq!(
+                results.recv().unwrap(),
+                (vec![Value::String("Dipper".to_string()), Value::Eid(1)], 1)
+            );
+            assert_eq!(
+                results.recv().unwrap(),
+                (vec![Value::String("Mabel".to_string()), Value::Eid(2)], -1)
+            );
+        }).join().unwrap();
+    }).unwrap();
+}
+
+#[test]
+fn lookup_query() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::new(Default::default());
+        let (send_results, results) = channel();
+
+        let (c, e, n) = (1, 2, 3);
+
+        worker.dataflow::<u64, _, _>(|mut scope| {
+            server.create_input(":name".to_string(), &mut scope);
+
+            // (lookup ?c ?n ?e) <- [?e :name ?n], (lookup ?c ?n)
+            server.register(
+                Register {
+                    rules: vec![Rule {
+                        name: "lookup".to_string(),
+                        plan: Plan::Project(Project {
+                            variables: vec![c, n, e],
+                            plan: Box::new(Plan::Join(Join {
+                                variables: vec![n],
+                                left_plan: Box::new(Plan::MatchA(e, ":name".to_string(), n)),
+                                right_plan: Box::new(Plan::Lookup(c, n, "name".to_string())),
+                            })),
+                        }),
+                    }],
+                    publish: vec!["lookup".to_string()],
+                    limits: Default::default(),
+                },
+                &mut scope,
+            );
+
+            server
+                .interest("lookup".to_string(), &mut scope)
+                .inspect(move |x| {
+                    send_results.send((x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        let tx_data = vec![
+            TxData(1, 1, ":name".to_string(), Value::String("Dipper".to_string())),
+            TxData(1, 2, ":name".to_string(), Value::String("Mabel".to_string())),
+        ];
+        server.transact(Transact { tx: None, tx_data, wait_for: vec![] }, 0, 0);
+
+        worker.step_while(|| server.is_any_outdated());
+
+        let name = |n: &str| vec![Binding(1, "name".to_string(), Value::String(n.to_string()))];
+        server.lookup(Lookup { query: "lookup".to_string(), bindings: name("Dipper") }, 0, 1, 0);
+        server.lookup(Lookup { query: "lookup".to_string(), bindings: name("Mabel") }, 0, 2, 0);
+
+        assert!(server
+            .validate_lookup(&Lookup { query: "lookup".to_string(), bindings: name("Dipper") })
+            .is_ok());
+        assert!(server
+            .validate_params(&SetParams { query: "lookup".to_string(), bindings: name("Dipper") })
+            .is_err());
+
+        worker.step_while(|| server.is_any_outdated());
+
+        // disconnecting retracts lookups without further transactions
+        assert!(server.disconnect(0, 1, 0));
+        assert!(!server.disconnect(0, 1, 0));
+
+        worker.step_while(|| server.is_any_outdated());
+
+        thread::spawn(move || {
+            assert_eq!(
+                results.recv().unwrap(),
+                (vec![client_key(0, 1), Value::String("Dipper".to_string()), Value::Eid(1)], 1)
+            );
+            assert_eq!(
+                results.recv().unwrap(),
+                (vec![client_key(0, 2), Value::String("Mabel".to_string()), Value::Eid(2)], 1)
+            );
+            assert_eq!(
+                results.recv().unwrap(),
+                (vec![client_key(0, 1), Value::String("Dipper".to_string()), Value::Eid(1)], -1)
+            );
+        }).join().unwrap();
+    }).unwrap();
+}
+
+#[test]
+fn transact_complete() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::new(Default::default());
+
+        let (e, n) = (1, 2);
+
+        worker.dataflow::<u64, _, _>(|mut scope| {
+            server.create_input(":name".to_string(), &mut scope);
+
+            server.register(
+                Register {
+                    rules: vec![Rule {
+                        name: "names".to_string(),
+                        plan: Plan::MatchA(e, ":name".to_string(), n),
+                    }],
+                    publish: vec!["names".to_string()],
+                    limits: Default::default(),
+                },
+                &mut scope,
+            );
+        });
+
+        let tx_data = vec![TxData(1, 1, ":name".to_string(), Value::String("Dipper".to_string()))];
+        let wait_for = vec!["names".to_string()];
+        let tx = server.transact(Transact { tx: None, tx_data, wait_for }, 0, 0);
+
+        assert_eq!(tx, 0);
+        assert!(!server.is_complete("names", tx));
+
+        worker.step_while(|| !server.is_complete("names", tx));
+
+        let tx_data = vec![TxData(1, 2, ":name".to_string(), Value::String("Mabel".to_string()))];
+        let tx = server.transact(Transact { tx: None, tx_data, wait_for: vec![] }, 0, 0);
+
+        assert_eq!(tx, 1);
+        assert!(!server.is_complete("names", tx));
+    }).unwrap();
+}
@@ -0,0 +1,269 @@
+extern crate declarative_dataflow;
+extern crate timely;
+
+use std::fs::File;
+use std::io::Write;
+use std::sync::mpsc::channel;
+use std::thread;
+
+use timely::Configuration;
+
+use declarative_dataflow::server::{RegisterSource, Server};
+use declarative_dataflow::sources::{CsvFile, JsonFile, Source};
+use declarative_dataflow::{Decimal, OrderedFloat, Value};
+
+fn write_file(name: &str, contents: &str) -> String {
+    let path = std::env::temp_dir().join(name);
+    let mut file = File::create(&path).unwrap();
+    file.write_all(contents.as_bytes()).unwrap();
+
+    path.to_str().unwrap().to_string()
+}
+
+#[test]
+fn csv_nil() {
+    let path = write_file("declarative_dataflow_csv_nil.csv", "Dipper,12\nMabel,\n,13\n");
+
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::new(Default::default());
+        let (send_results, results) = channel();
+
+        let source = RegisterSource {
+            names: vec![":age".to_string()],
+            source: Source::CsvFile(CsvFile {
+                path: path.clone(),
+                separator: ',',
+                schema: vec![(1, Value::Number(0))],
+            }),
+        };
+
+        worker.dataflow::<u64, _, _>(|mut scope| {
+            server.register_source(source, &mut scope);
+
+            server
+                .interest(":age".to_string(), &mut scope)
+                .inspect(move |x| {
+                    send_results.send((x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        while !server.probe.done() {
+            worker.step();
+        }
+
+        thread::spawn(move || {
+            assert_eq!(
+                results.recv().unwrap(),
+                (vec![Value::Eid(0), Value::Number(12)], 1)
+            );
+            assert_eq!(
+                results.recv().unwrap(),
+                (vec![Value::Eid(1), Value::Nil], 1)
+            );
+            assert_eq!(
+                results.recv().unwrap(),
+                (vec![Value::Eid(2), Value::Number(13)], 1)
+            );
+        }).join()
+            .unwrap();
+    }).unwrap();
+}
+
+#[test]
+fn json_nil() {
+    let path = write_file(
+        "declarative_dataflow_json_nil.json",
+        "{\"name\": \"Dipper\"}\n{\"name\": null}\n{\"age\": 12}\n",
+    );
+
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::new(Default::default());
+        let (send_results, results) = channel();
+
+        let source = RegisterSource {
+            names: vec!["name".to_string()],
+            source: Source::JsonFile(JsonFile {
+                path: path.clone(),
+                decimals: false,
+            }),
+        };
+
+        worker.dataflow::<u64, _, _>(|mut scope| {
+            server.register_source(source, &mut scope);
+
+            server
+                .interest("name".to_string(), &mut scope)
+                .inspect(move |x| {
+                    send_results.send((x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        while !server.probe.done() {
+            worker.step();
+        }
+
+        thread::spawn(move || {
+            assert_eq!(
+                results.recv().unwrap(),
+                (vec![Value::Eid(0), Value::String("Dipper".to_string())], 1)
+            );
+            assert_eq!(
+                results.recv().unwrap(),
+                (vec![Value::Eid(1), Value::Nil], 1)
+            );
+            assert!(results.try_recv().is_err());
+        }).join()
+            .unwrap();
+    }).unwrap();
+}
+
+#[test]
+fn csv_numeric() {
+    let path = write_file("declarative_dataflow_csv_numeric.csv", "1.5;0.10\n-2;3\n");
+
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::new(Default::default());
+        let (send_results, results) = channel();
+
+        let source = RegisterSource {
+            names: vec![":real".to_string(), ":decimal".to_string()],
+            source: Source::CsvFile(CsvFile {
+                path: path.clone(),
+                separator: ';',
+                schema: vec![
+                    (0, Value::Real(OrderedFloat(0.0))),
+                    (1, Value::Decimal(Decimal::new(0, 0))),
+                ],
+            }),
+        };
+
+        worker.dataflow::<u64, _, _>(|mut scope| {
+            server.register_source(source, &mut scope);
+
+            let send_results_copy = send_results.clone();
+            server
+                .interest(":real".to_string(), &mut scope)
+                .inspect(move |x| {
+                    send_results.send((x.0.clone(), x.2)).unwrap();
+                });
+
+            server
+                .interest(":decimal".to_string(), &mut scope)
+                .inspect(move |x| {
+                    send_results_copy.send((x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        while !server.probe.done() {
+            worker.step();
+        }
+
+        thread::spawn(move || {
+            let mut outputs: Vec<_> = (0..4).map(|_| results.recv().unwrap()).collect();
+            outputs.sort();
+
+            assert_eq!(
+                outputs,
+                vec![
+                    (vec![Value::Eid(0), Value::Real(OrderedFloat(1.5))], 1),
+                    (vec![Value::Eid(0), Value::Decimal(Decimal::new(10, 2))], 1),
+                    (vec![Value::Eid(1), Value::Real(OrderedFloat(-2.0))], 1),
+                    (vec![Value::Eid(1), Value::Decimal(Decimal::new(3, 0))], 1),
+                ]
+            );
+        }).join()
+            .unwrap();
+    }).unwrap();
+}
+
+#[test]
+fn json_numeric() {
+    let path = write_file(
+        "declarative_dataflow_json_numeric.json",
+        "{\"amount\": 1.25}\n{\"amount\": 3}\n",
+    );
+
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::new(Default::default());
+        let (send_results, results) = channel();
+
+        let reals = RegisterSource {
+            names: vec!["amount".to_string()],
+            source: Source::JsonFile(JsonFile {
+                path: path.clone(),
+                decimals: false,
+            }),
+        };
+
+        worker.dataflow::<u64, _, _>(|mut scope| {
+            server.register_source(reals, &mut scope);
+
+            server
+                .interest("amount".to_string(), &mut scope)
+                .inspect(move |x| {
+                    send_results.send((x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        while !server.probe.done() {
+            worker.step();
+        }
+
+        thread::spawn(move || {
+            assert_eq!(
+                results.recv().unwrap(),
+                (vec![Value::Eid(0), Value::Real(OrderedFloat(1.25))], 1)
+            );
+            assert_eq!(
+                results.recv().unwrap(),
+                (vec![Value::Eid(1), Value::Number(3)], 1)
+            );
+        }).join()
+            .unwrap();
+    }).unwrap();
+}
+
+#[test]
+fn json_single_name() {
+    let path = write_file(
+        "declarative_dataflow_json_single_name.json",
+        "{\"name\": \"Dipper\", \"age\": 12}\n",
+    );
+
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::new(Default::default());
+        let (send_results, results) = channel();
+
+        // the source has to be told the name it is registered under
+        let ages = RegisterSource {
+            names: vec!["age".to_string()],
+            source: Source::JsonFile(JsonFile {
+                path: path.clone(),
+                decimals: false,
+            }),
+        };
+
+        worker.dataflow::<u64, _, _>(|mut scope| {
+            server.register_source(ages, &mut scope);
+
+            server
+                .interest("age".to_string(), &mut scope)
+                .inspect(move |x| {
+                    send_results.send((x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        while !server.probe.done() {
+            worker.step();
+        }
+
+        thread::spawn(move || {
+            assert_eq!(
+                results.recv().unwrap(),
+                (vec![Value::Eid(0), Value::Number(12)], 1)
+            );
+            assert!(results.try_recv().is_err());
+        }).join()
+            .unwrap();
+    }).unwrap();
+}
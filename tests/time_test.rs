@@ -0,0 +1,66 @@
+extern crate chrono_tz;
+extern crate declarative_dataflow;
+
+use chrono_tz::Tz;
+
+use declarative_dataflow::time::{self, Field, Unit};
+
+// 2018-10-20T15:15:15.500Z
+const T: u64 = 1540048515500;
+
+#[test]
+fn truncate() {
+    assert_eq!(time::truncate(T, Unit::Hour, &Tz::UTC), Some(1540047600000));
+    assert_eq!(time::truncate(T, Unit::Day, &Tz::UTC), Some(1539993600000));
+    // the Monday before, not the Thursday weeks since the epoch start on
+    assert_eq!(time::truncate(T, Unit::Week, &Tz::UTC), Some(1539561600000));
+    // 2018-10-17T12:00:00Z, a Wednesday, crosses the Thursday before
+    assert_eq!(
+        time::truncate(1539777600000, Unit::Week, &Tz::UTC),
+        Some(1539561600000)
+    );
+    // local Monday midnight in Berlin (UTC+2)
+    assert_eq!(
+        time::truncate(T, Unit::Week, &Tz::Europe__Berlin),
+        Some(1539554400000)
+    );
+    assert_eq!(time::truncate(T, Unit::Quarter, &Tz::UTC), Some(1538352000000));
+    assert_eq!(time::truncate(T, Unit::Year, &Tz::UTC), Some(1514764800000));
+    // local midnight in New York (UTC-4)
+    assert_eq!(
+        time::truncate(T, Unit::Day, &Tz::America__New_York),
+        Some(1540008000000)
+    );
+}
+
+#[test]
+fn add_and_diff() {
+    // 2018-01-31T00:00:00Z plus one month is clamped to February 28th
+    let jan = 1517356800000;
+    let feb = 1519776000000;
+    assert_eq!(time::add(jan, 1, Unit::Month, &Tz::UTC), Some(feb));
+    assert_eq!(time::diff(jan, feb, Unit::Month, &Tz::UTC), Some(1));
+    assert_eq!(time::diff(feb, jan, Unit::Month, &Tz::UTC), Some(-1));
+    assert_eq!(time::diff(jan, feb, Unit::Day, &Tz::UTC), Some(28));
+    assert_eq!(time::diff(jan, feb - 1, Unit::Month, &Tz::UTC), Some(0));
+    assert_eq!(time::add(0, -1, Unit::Day, &Tz::UTC), None);
+}
+
+#[test]
+fn parse_and_format() {
+    assert_eq!(time::parse("2018-10-20T15:15:15.500Z", &Tz::UTC), Some(T));
+    assert_eq!(time::parse("2018-10-20T17:15:15.5+02:00", &Tz::UTC), Some(T));
+    assert_eq!(time::parse("2018-10-20T17:15:15.500", &Tz::Europe__Berlin), Some(T));
+    assert_eq!(time::parse("2018-10-20", &Tz::UTC), Some(1539993600000));
+    assert_eq!(time::parse("yesterday", &Tz::UTC), None);
+
+    assert_eq!(
+        time::format(T, &Tz::UTC),
+        Some("2018-10-20T15:15:15.500+00:00".to_string())
+    );
+    assert_eq!(
+        time::format(T, &Tz::Europe__Berlin),
+        Some("2018-10-20T17:15:15.500+02:00".to_string())
+    );
+    assert_eq!(time::part(T, Field::Quarter, &Tz::UTC), Some(4));
+}
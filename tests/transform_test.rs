@@ -7,9 +7,10 @@ use std::collections::HashMap;
 
 use timely::Configuration;
 
+use declarative_dataflow::functions::{FunctionError, FunctionRegistry, ScalarFunction};
 use declarative_dataflow::plan::{Function, Transform};
 use declarative_dataflow::server::{Register, Server, Transact, TxData};
-use declarative_dataflow::{Plan, Rule, Value};
+use declarative_dataflow::{Decimal, OrderedFloat, Plan, Rational32, Rule, Value, ValueType};
 
 #[test]
 fn truncate() {
@@ -26,7 +27,8 @@ fn truncate() {
             result_sym: h,
             plan: Box::new(Plan::MatchA(e, ":timestamp".to_string(), t)),
             function: Function::TRUNCATE,
-            constants: constants
+            constants: constants,
+            udf: None,
         });
 
         worker.dataflow::<u64, _, _>(|mut scope| {
@@ -40,6 +42,7 @@ fn truncate() {
                         plan: plan,
                     }],
                     publish: vec![query_name.to_string()],
+                    limits: Default::default(),
                 },
                 &mut scope,
             );
@@ -68,6 +71,7 @@ fn truncate() {
                         Value::Instant(1540048515616),
                     ),
                 ],
+                wait_for: vec![],
             },
             0,
             0,
@@ -88,3 +92,459 @@ fn truncate() {
             .unwrap();
     }).unwrap();
 }
+
+#[test]
+fn add_mixed() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::new(Default::default());
+        let (send_results, results) = channel();
+
+        // [:find ?e ?x ?y :where [?e :amount ?x] [(+ ?x 0.5) ?y]]
+        let (e, x, y) = (1, 2, 3);
+        let mut constants = HashMap::new();
+        constants.insert(1, Value::Real(OrderedFloat(0.5)));
+        let plan = Plan::Transform(Transform {
+            variables: vec![x],
+            result_sym: y,
+            plan: Box::new(Plan::MatchA(e, ":amount".to_string(), x)),
+            function: Function::ADD,
+            constants: constants,
+            udf: None,
+        });
+
+        worker.dataflow::<u64, _, _>(|mut scope| {
+            server.create_input(":amount".to_string(), &mut scope);
+
+            let query_name = "add_mixed";
+            server.register(
+                Register {
+                    rules: vec![Rule {
+                        name: query_name.to_string(),
+                        plan: plan,
+                    }],
+                    publish: vec![query_name.to_string()],
+                    limits: Default::default(),
+                },
+                &mut scope,
+            );
+
+            server
+                .interest(query_name.to_string(), &mut scope)
+                .inspect(move |x| {
+                    send_results.send((x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        server.transact(
+            Transact {
+                tx: Some(0),
+                tx_data: vec![
+                    TxData(1, 1, ":amount".to_string(), Value::Number(2)),
+                    TxData(1, 2, ":amount".to_string(), Value::Decimal(Decimal::new(125, 2))),
+                ],
+                wait_for: vec![],
+            },
+            0,
+            0,
+        );
+
+        worker.step_while(|| server.is_any_outdated());
+
+        thread::spawn(move || {
+            assert_eq!(
+                results.recv().unwrap(),
+                (vec![Value::Eid(1), Value::Number(2), Value::Real(OrderedFloat(2.5))], 1)
+            );
+            assert_eq!(
+                results.recv().unwrap(),
+                (
+                    vec![
+                        Value::Eid(2),
+                        Value::Decimal(Decimal::new(125, 2)),
+                        Value::Real(OrderedFloat(1.75)),
+                    ],
+                    1
+                )
+            );
+        }).join()
+            .unwrap();
+    }).unwrap();
+}
+
+fn clamp() -> ScalarFunction {
+    ScalarFunction::new(
+        vec![Some(ValueType::Number), Some(ValueType::Number)],
+        |args| match (&args[0], &args[1]) {
+            (&Value::Number(x), &Value::Number(max)) if max >= 0 => Ok(Value::Number(x.min(max))),
+            _ => Err(FunctionError::Other("Maximum must not be negative".to_string())),
+        },
+    )
+}
+
+#[test]
+fn udf() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::new(Default::default());
+        let (send_results, results) = channel();
+
+        server.register_function("clamp".to_string(), clamp());
+
+        // [:find ?e ?x ?y :where [?e :amount ?x] [(clamp ?x 10) ?y]]
+        let (e, x, y) = (1, 2, 3);
+        let mut constants = HashMap::new();
+        constants.insert(1, Value::Number(10));
+        let plan = Plan::Transform(Transform {
+            variables: vec![x],
+            result_sym: y,
+            plan: Box::new(Plan::MatchA(e, ":amount".to_string(), x)),
+            function: Function::UDF("clamp".to_string()),
+            constants: constants,
+            udf: None,
+        });
+
+        worker.dataflow::<u64, _, _>(|mut scope| {
+            server.create_input(":amount".to_string(), &mut scope);
+
+            let query_name = "udf";
+            server.register(
+                Register {
+                    rules: vec![Rule {
+                        name: query_name.to_string(),
+                        plan: plan,
+                    }],
+                    publish: vec![query_name.to_string()],
+                    limits: Default::default(),
+                },
+                &mut scope,
+            );
+
+            server
+                .interest(query_name.to_string(), &mut scope)
+                .inspect(move |x| {
+                    send_results.send((x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        server.transact(
+            Transact {
+                tx: Some(0),
+                tx_data: vec![
+                    TxData(1, 1, ":amount".to_string(), Value::Number(2)),
+                    TxData(1, 2, ":amount".to_string(), Value::Number(20)),
+                    TxData(1, 3, ":amount".to_string(), Value::String("n/a".to_string())),
+                ],
+                wait_for: vec![],
+            },
+            0,
+            0,
+        );
+
+        worker.step_while(|| server.is_any_outdated());
+
+        thread::spawn(move || {
+            assert_eq!(
+                results.recv().unwrap(),
+                (vec![Value::Eid(1), Value::Number(2), Value::Number(2)], 1)
+            );
+            assert_eq!(
+                results.recv().unwrap(),
+                (vec![Value::Eid(2), Value::Number(20), Value::Number(10)], 1)
+            );
+        }).join()
+            .unwrap();
+    }).unwrap();
+}
+
+#[test]
+fn udf_validation() {
+    let mut registry = FunctionRegistry::new();
+    registry.insert("clamp".to_string(), clamp());
+
+    let transform = |function: &str, constant: Value| {
+        let mut constants = HashMap::new();
+        constants.insert(1, constant);
+
+        Plan::Transform(Transform {
+            variables: vec![2],
+            result_sym: 3,
+            plan: Box::new(Plan::MatchA(1, ":amount".to_string(), 2)),
+            function: Function::UDF(function.to_string()),
+            constants: constants,
+            udf: None,
+        })
+    };
+
+    assert!(transform("clamp", Value::Number(10)).bind_functions(&registry).is_ok());
+    assert!(transform("clamp", Value::String("10".to_string())).bind_functions(&registry).is_err());
+    assert!(transform("unknown", Value::Number(10)).bind_functions(&registry).is_err());
+
+    let mut plan = transform("clamp", Value::Number(10));
+    if let Plan::Transform(ref mut transform) = plan {
+        transform.constants.insert(2, Value::Number(0));
+    }
+    assert!(plan.bind_functions(&registry).is_err());
+
+    // registrations are rejected as a whole, rather than panicking
+    let server = Server::new(Default::default());
+    let mut req = Register {
+        rules: vec![Rule {
+            name: "unknown".to_string(),
+            plan: transform("unknown", Value::Number(10)),
+        }],
+        publish: vec!["unknown".to_string()],
+        limits: Default::default(),
+    };
+    assert!(server.validate(&mut req).is_err());
+}
+
+#[test]
+fn divide() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::new(Default::default());
+        let (send_results, results) = channel();
+
+        // [:find ?e ?x ?y :where [?e :amount ?x] [(/ 10 ?x) ?y]]
+        let (e, x, y) = (1, 2, 3);
+        let mut constants = HashMap::new();
+        constants.insert(0, Value::Number(10));
+        let plan = Plan::Transform(Transform {
+            variables: vec![x],
+            result_sym: y,
+            plan: Box::new(Plan::MatchA(e, ":amount".to_string(), x)),
+            function: Function::DIVIDE,
+            constants: constants,
+            udf: None,
+        });
+
+        worker.dataflow::<u64, _, _>(|mut scope| {
+            server.create_input(":amount".to_string(), &mut scope);
+
+            let query_name = "divide";
+            server.register(
+                Register {
+                    rules: vec![Rule {
+                        name: query_name.to_string(),
+                        plan: plan,
+                    }],
+                    publish: vec![query_name.to_string()],
+                    limits: Default::default(),
+                },
+                &mut scope,
+            );
+
+            server
+                .interest(query_name.to_string(), &mut scope)
+                .inspect(move |x| {
+                    send_results.send((x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        server.transact(
+            Transact {
+                tx: Some(0),
+                tx_data: vec![
+                    TxData(1, 1, ":amount".to_string(), Value::Number(4)),
+                    TxData(1, 2, ":amount".to_string(), Value::Number(0)),
+                    TxData(1, 3, ":amount".to_string(), Value::String("4".to_string())),
+                    TxData(1, 4, ":amount".to_string(), Value::Real(OrderedFloat(0.5))),
+                ],
+                wait_for: vec![],
+            },
+            0,
+            0,
+        );
+
+        worker.step_while(|| server.is_any_outdated());
+
+        thread::spawn(move || {
+            assert_eq!(
+                results.recv().unwrap(),
+                (vec![Value::Eid(1), Value::Number(4), Value::Rational32(Rational32::new(5, 2))], 1)
+            );
+            assert_eq!(
+                results.recv().unwrap(),
+                (
+                    vec![Value::Eid(4), Value::Real(OrderedFloat(0.5)), Value::Real(OrderedFloat(20.0))],
+                    1
+                )
+            );
+        }).join()
+            .unwrap();
+    }).unwrap();
+}
+
+#[test]
+fn regex_extract() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::new(Default::default());
+        let (send_results, results) = channel();
+
+        // [:find ?e ?domain :where
+        //  [?e :email ?email]
+        //  [(re-find "@(.+)$" ?email) ?domain]
+        //  [(upper-case ?domain) ?upper]]
+        let (e, email, domain, upper) = (1, 2, 3, 4);
+        let mut constants = HashMap::new();
+        constants.insert(1, Value::String("@(.+)$".to_string()));
+        let plan = Plan::Transform(Transform {
+            variables: vec![domain],
+            result_sym: upper,
+            plan: Box::new(Plan::Transform(Transform {
+                variables: vec![email],
+                result_sym: domain,
+                plan: Box::new(Plan::MatchA(e, ":email".to_string(), email)),
+                function: Function::REGEX_EXTRACT,
+                constants: constants,
+                udf: None,
+            })),
+            function: Function::UPPER,
+            constants: HashMap::new(),
+            udf: None,
+        });
+
+        worker.dataflow::<u64, _, _>(|mut scope| {
+            server.create_input(":email".to_string(), &mut scope);
+
+            let query_name = "regex_extract";
+            server.register(
+                Register {
+                    rules: vec![Rule {
+                        name: query_name.to_string(),
+                        plan: plan,
+                    }],
+                    publish: vec![query_name.to_string()],
+                    limits: Default::default(),
+                },
+                &mut scope,
+            );
+
+            server
+                .interest(query_name.to_string(), &mut scope)
+                .inspect(move |x| {
+                    send_results.send((x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        server.transact(
+            Transact {
+                tx: Some(0),
+                tx_data: vec![
+                    TxData(1, 1, ":email".to_string(), Value::String("ada@example.com".to_string())),
+                    // no match, thus UPPER is applied to Nil and fails
+                    TxData(1, 2, ":email".to_string(), Value::String("grace".to_string())),
+                ],
+                wait_for: vec![],
+            },
+            0,
+            0,
+        );
+
+        worker.step_while(|| server.is_any_outdated());
+
+        thread::spawn(move || {
+            assert_eq!(
+                results.recv().unwrap(),
+                (
+                    vec![
+                        Value::Eid(1),
+                        Value::String("ada@example.com".to_string()),
+                        Value::String("example.com".to_string()),
+                        Value::String("EXAMPLE.COM".to_string()),
+                    ],
+                    1
+                )
+            );
+        }).join()
+            .unwrap();
+    }).unwrap();
+}
+
+#[test]
+fn truncate_month() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::new(Default::default());
+        let (send_results, results) = channel();
+
+        // [:find ?e ?t ?m ?w :where
+        //  [?e :timestamp ?t]
+        //  [(truncate ?t :month "Europe/Berlin") ?m]
+        //  [(date-part ?t :weekday "Europe/Berlin") ?w]]
+        let (e, t, m, w) = (1, 2, 3, 4);
+        let mut truncate_constants = HashMap::new();
+        truncate_constants.insert(1, Value::String(":month".to_string()));
+        truncate_constants.insert(2, Value::String("Europe/Berlin".to_string()));
+        let mut part_constants = HashMap::new();
+        part_constants.insert(1, Value::String(":weekday".to_string()));
+        part_constants.insert(2, Value::String("Europe/Berlin".to_string()));
+        let plan = Plan::Transform(Transform {
+            variables: vec![t],
+            result_sym: w,
+            plan: Box::new(Plan::Transform(Transform {
+                variables: vec![t],
+                result_sym: m,
+                plan: Box::new(Plan::MatchA(e, ":timestamp".to_string(), t)),
+                function: Function::TRUNCATE,
+                constants: truncate_constants,
+                udf: None,
+            })),
+            function: Function::DATE_PART,
+            constants: part_constants,
+            udf: None,
+        });
+
+        worker.dataflow::<u64, _, _>(|mut scope| {
+            server.create_input(":timestamp".to_string(), &mut scope);
+
+            let query_name = "truncate_month";
+            server.register(
+                Register {
+                    rules: vec![Rule {
+                        name: query_name.to_string(),
+                        plan: plan,
+                    }],
+                    publish: vec![query_name.to_string()],
+                    limits: Default::default(),
+                },
+                &mut scope,
+            );
+
+            server
+                .interest(query_name.to_string(), &mut scope)
+                .inspect(move |x| {
+                    send_results.send((x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        server.transact(
+            Transact {
+                tx: Some(0),
+                tx_data: vec![
+                    // 2018-10-20T15:15:15.500Z, a Saturday
+                    TxData(1, 1, ":timestamp".to_string(), Value::Instant(1540048515500)),
+                ],
+                wait_for: vec![],
+            },
+            0,
+            0,
+        );
+
+        worker.step_while(|| server.is_any_outdated());
+
+        thread::spawn(move || {
+            // 2018-10-01T00:00:00+02:00
+            assert_eq!(
+                results.recv().unwrap(),
+                (
+                    vec![
+                        Value::Eid(1),
+                        Value::Instant(1540048515500),
+                        Value::Instant(1538344800000),
+                        Value::Number(6),
+                    ],
+                    1
+                )
+            );
+        }).join()
+            .unwrap();
+    }).unwrap();
+}